@@ -0,0 +1,151 @@
+//! Documents from the members of tar, tar.gz and zip archives (the archive feature)
+use crate::{collapse_docs, glob_matches, Args, Input};
+use anyhow::Result;
+use std::io::Read;
+use tracing::*;
+
+/// Archive formats that can be read as a stream of their members
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Archive {
+    Tar,
+    TarGz,
+    Zip,
+}
+
+impl Archive {
+    /// Guess the archive format from a file name
+    pub(crate) fn from_path(path: &std::path::Path) -> Option<Self> {
+        let name = path.file_name()?.to_str()?.to_ascii_lowercase();
+        if name.ends_with(".tar") {
+            Some(Archive::Tar)
+        } else if name.ends_with(".tar.gz") || name.ends_with(".tgz") {
+            Some(Archive::TarGz)
+        } else if name.ends_with(".zip") {
+            Some(Archive::Zip)
+        } else {
+            None
+        }
+    }
+
+    /// Paths and contents of the regular files in the archive, in archive order
+    fn members(self, data: &[u8]) -> Result<Vec<(String, Vec<u8>)>> {
+        let mut members = vec![];
+        match self {
+            Archive::Tar | Archive::TarGz => {
+                let reader: Box<dyn Read> = match self {
+                    Archive::TarGz => Box::new(flate2::read::GzDecoder::new(data)),
+                    _ => Box::new(data),
+                };
+                for entry in tar::Archive::new(reader).entries()? {
+                    let mut entry = entry?;
+                    if !entry.header().entry_type().is_file() {
+                        continue;
+                    }
+                    let name = entry.path()?.to_string_lossy().into_owned();
+                    let mut contents = vec![];
+                    entry.read_to_end(&mut contents)?;
+                    members.push((name, contents));
+                }
+            }
+            Archive::Zip => {
+                let mut zip = zip::ZipArchive::new(std::io::Cursor::new(data))?;
+                for i in 0..zip.len() {
+                    let mut file = zip.by_index(i)?;
+                    if !file.is_file() {
+                        continue;
+                    }
+                    let name = file.name().to_string();
+                    let mut contents = vec![];
+                    file.read_to_end(&mut contents)?;
+                    members.push((name, contents));
+                }
+            }
+        }
+        Ok(members)
+    }
+}
+
+impl Args {
+    /// Replace archive input with the documents of its matching members
+    pub(crate) fn read_archive(&mut self) -> Result<()> {
+        let Some(archive) = self.file.as_deref().and_then(Archive::from_path) else {
+            if self.archive_glob.is_some() {
+                anyhow::bail!("--archive-glob requires a .tar, .tar.gz, .tgz or .zip input file");
+            }
+            return Ok(());
+        };
+        if self.in_place {
+            anyhow::bail!("cannot edit archives in place");
+        }
+        let (mut docs, mut sources) = (vec![], vec![]);
+        for (name, contents) in archive.members(&self.input_bytes()?)? {
+            let format = Input::from_path(std::path::Path::new(&name));
+            let matched = match &self.archive_glob {
+                Some(glob) => glob_matches(glob, &name),
+                None => format.is_some(),
+            };
+            if !matched {
+                continue;
+            }
+            debug!("reading archive member {name}");
+            let mut member = self.clone();
+            member.input = format.unwrap_or(self.input);
+            member.source = Some(self.expand_env_vars(self.repaired_utf8(contents))?);
+            let member_docs = match member.input {
+                Input::Yaml => member.read_yaml_docs(),
+                Input::Toml => member.read_toml_docs(),
+                Input::Json => member.read_json_docs(),
+                Input::Xlsx => member.read_workbook_docs(),
+                Input::Csv => member.read_csv_docs(),
+                Input::Xml => member.read_xml_docs(),
+                Input::Edn => member.read_edn_docs(),
+                Input::Kdl => member.read_kdl().map(|doc| vec![doc]),
+            };
+            match member_docs {
+                Ok(member_docs) => {
+                    sources.extend((0..member_docs.len()).map(|i| format!("{name} (doc {i})")));
+                    docs.extend(member_docs);
+                }
+                Err(e) if self.strict => return Err(e.context(format!("failed to parse {name}"))),
+                // e.g. templates in helm charts
+                Err(e) => warn!("skipping {name}: {e}"),
+            }
+        }
+        self.input = Input::Json;
+        self.source = Some(serde_json::to_vec(&collapse_docs(docs))?);
+        self.member_sources = sources;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::Write;
+
+    #[test]
+    fn archive_members() -> Result<()> {
+        let options = zip::write::SimpleFileOptions::default();
+        let mut zip = zip::ZipWriter::new(std::io::Cursor::new(vec![]));
+        zip.add_directory("chart/", options)?;
+        zip.start_file("chart/Chart.yaml", options)?;
+        zip.write_all(b"version: 1.0.0")?;
+        let data = zip.finish()?.into_inner();
+        let members = Archive::Zip.members(&data)?;
+        assert_eq!(
+            members,
+            vec![("chart/Chart.yaml".to_string(), b"version: 1.0.0".to_vec())]
+        );
+        assert_eq!(
+            Archive::from_path("chart-1.0.0.tgz".as_ref()),
+            Some(Archive::TarGz)
+        );
+
+        assert!(glob_matches("*/Chart.yaml", "chart/Chart.yaml"));
+        assert!(glob_matches("*.y*ml", "a/b/c.yaml"));
+        assert!(glob_matches("?hart/*", "chart/x"));
+        assert!(!glob_matches("*.yaml", "chart/values.json"));
+        assert!(!glob_matches("Chart.yaml", "chart/Chart.yaml"));
+        Ok(())
+    }
+}
@@ -0,0 +1,127 @@
+//! External programs converting other formats to and from json (--codec)
+use crate::{collapse_docs, Args, Input};
+use anyhow::Result;
+use std::io::Write;
+use std::path::PathBuf;
+use tracing::*;
+
+/// An external program converting a format to and from json, registered with --codec
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Codec {
+    pub(crate) name: String,
+    pub(crate) program: PathBuf,
+}
+
+impl std::str::FromStr for Codec {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once('=') {
+            Some((name, program)) if !name.is_empty() && !program.is_empty() => Ok(Codec {
+                name: name.into(),
+                program: program.into(),
+            }),
+            _ => Err(format!("expected NAME=PROGRAM, found {s:?}")),
+        }
+    }
+}
+
+impl Codec {
+    /// Run the codec in decode or encode mode over the given input
+    fn run(&self, mode: &str, input: &[u8]) -> Result<Vec<u8>> {
+        use std::process::Stdio;
+        debug!("running {} {mode}", self.program.display());
+        let mut child = std::process::Command::new(&self.program)
+            .arg(mode)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::inherit())
+            .spawn()
+            .map_err(|e| {
+                anyhow::anyhow!(
+                    "failed to run codec {} ({}): {e}",
+                    self.name,
+                    self.program.display()
+                )
+            })?;
+        let mut stdin = child.stdin.take().unwrap();
+        let output = std::thread::scope(|scope| {
+            scope.spawn(move || {
+                let _ = stdin.write_all(input);
+            });
+            child.wait_with_output()
+        })?;
+        if !output.status.success() {
+            anyhow::bail!("codec {} failed to {mode}: {}", self.name, output.status);
+        }
+        Ok(output.stdout)
+    }
+}
+
+impl Args {
+    /// Look up a codec registered with --codec (or in the config file)
+    fn find_codec(&self, name: &str) -> Result<&Codec> {
+        self.codec
+            .iter()
+            .find(|c| c.name == name)
+            .ok_or_else(|| anyhow::anyhow!("unknown codec {name}, register it with --codec {name}=PROGRAM"))
+    }
+
+    /// The codec decoding the input, given with --input-codec or registered for the file extension
+    pub(crate) fn input_codec_name(&self) -> Option<String> {
+        if let Some(name) = &self.input_codec {
+            return Some(name.clone());
+        }
+        let extension = self.file.as_deref()?.extension()?.to_str()?;
+        self.codec
+            .iter()
+            .any(|c| c.name == extension)
+            .then(|| extension.to_string())
+    }
+
+    /// Replace input in the format of a codec with the json documents it decodes to
+    pub(crate) fn decode_codec(&mut self) -> Result<()> {
+        if let Some(name) = &self.output_codec {
+            self.find_codec(name)?; // fail before running anything
+        }
+        let Some(name) = self.input_codec_name() else {
+            return Ok(());
+        };
+        let json = self.find_codec(&name)?.run("decode", &self.input_bytes()?)?;
+        let docs = serde_json::Deserializer::from_slice(&json)
+            .into_iter()
+            .collect::<serde_json::Result<Vec<serde_json::Value>>>()
+            .map_err(|e| anyhow::anyhow!("codec {name} decoded to invalid json: {e}"))?;
+        self.input = Input::Json;
+        self.source = Some(serde_json::to_vec(&collapse_docs(docs))?);
+        Ok(())
+    }
+
+    /// Encode jq results with the --output-codec
+    pub(crate) fn encode_codec(&self, name: &str, stdout: &[u8]) -> Result<String> {
+        let mut input = vec![];
+        for doc in self.parse_stdout_docs(stdout)? {
+            serde_json::to_writer(&mut input, &doc)?;
+            input.push(b'\n');
+        }
+        let encoded = String::from_utf8(self.find_codec(name)?.run("encode", &input)?)?;
+        // the final newline is added on output like for other formats
+        Ok(encoded.strip_suffix('\n').unwrap_or(&encoded).to_string())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn codec_args() {
+        let codec: Codec = "ini=./bin/ini-codec".parse().unwrap();
+        assert_eq!(
+            (codec.name.as_str(), codec.program),
+            ("ini", PathBuf::from("./bin/ini-codec"))
+        );
+        assert!("ini".parse::<Codec>().is_err());
+        assert!("=x".parse::<Codec>().is_err());
+    }
+}
@@ -0,0 +1,134 @@
+//! Conversions between the supported formats and json, as lq does on either side of jq
+//!
+//! Documents are read with the semantics of the cli under its default flags: yaml tags become
+//! singleton maps, merge keys are expanded, numbers keep their json representation, and invalid
+//! input fails with an [`Error::Parse`] pointing at its location. Writing a value gives what
+//! `lq --output <format> .` would print for it.
+//!
+//! ```no_run
+//! let docs = lq::convert::yaml_to_json(&b"base: &b {a: 1}\nderived: {<<: *b, b: 2}"[..])?;
+//! assert_eq!(docs[0]["derived"], serde_json::json!({"a": 1, "b": 2}));
+//! assert_eq!(lq::convert::json_to_toml(&docs[0]["derived"])?, "a = 1\nb = 2\n");
+//! # Ok::<(), lq::Error>(())
+//! ```
+use crate::{Args, Error, Input, Output};
+use serde_json::Value;
+use std::io::Read;
+
+/// Read the documents of input in a format, as lq would pass them to jq
+///
+/// Multidoc yaml gives a document each, as does each item of a top level json array.
+pub fn to_json(input: Input, mut reader: impl Read) -> Result<Vec<Value>, Error> {
+    let mut data = vec![];
+    reader.read_to_end(&mut data)?;
+    let mut args = Args::parse_without_env(["lq"]).expect("no arguments are valid");
+    args.input = input;
+    args.source = Some(data);
+    args.repair_input_utf8()?;
+    Ok(args.read_input_multidoc()?)
+}
+
+/// Write a value in an output format, ending with a newline
+pub fn from_json(output: Output, value: &Value) -> Result<String, Error> {
+    let mut args = Args::parse_without_env(["lq"]).expect("no arguments are valid");
+    args.output = output;
+    let json = serde_json::to_vec(value).map_err(|e| Error::Serialization(e.into()))?;
+    Ok(args.output(json)? + "\n")
+}
+
+/// Read the documents of a single or multidoc yaml stream
+pub fn yaml_to_json(reader: impl Read) -> Result<Vec<Value>, Error> {
+    to_json(Input::Yaml, reader)
+}
+
+/// Write a value as a yaml document
+pub fn json_to_yaml(value: &Value) -> Result<String, Error> {
+    from_json(Output::Yaml, value)
+}
+
+/// Read a toml document
+pub fn toml_to_json(reader: impl Read) -> Result<Value, Error> {
+    to_json(Input::Toml, reader).map(crate::collapse_docs)
+}
+
+/// Write a value as a toml document, which requires a table (an object) at the top level
+pub fn json_to_toml(value: &Value) -> Result<String, Error> {
+    from_json(Output::Toml, value)
+}
+
+/// Read csv with a header row into an array of objects, converting numbers and booleans
+pub fn csv_to_json(reader: impl Read) -> Result<Value, Error> {
+    to_json(Input::Csv, reader).map(crate::collapse_docs)
+}
+
+/// Write an array of objects (or of arrays) as csv rows, with a header row of the object keys
+pub fn json_to_csv(value: &Value) -> Result<String, Error> {
+    from_json(Output::Csv, value)
+}
+
+/// Read an xml document into an object with a key for the root element, like python-yq's xq
+pub fn xml_to_json(reader: impl Read) -> Result<Value, Error> {
+    to_json(Input::Xml, reader).map(crate::collapse_docs)
+}
+
+/// Write an object with a single key for the root element as an xml document
+pub fn json_to_xml(value: &Value) -> Result<String, Error> {
+    from_json(Output::Xml, value)
+}
+
+/// Read the top level forms of edn text, with keywords and symbols as strings and sets as arrays
+pub fn edn_to_json(reader: impl Read) -> Result<Vec<Value>, Error> {
+    to_json(Input::Edn, reader)
+}
+
+/// Read a kdl document into an array of nodes (objects of name, type, args, props and children)
+pub fn kdl_to_json(reader: impl Read) -> Result<Value, Error> {
+    to_json(Input::Kdl, reader).map(crate::collapse_docs)
+}
+
+/// Write an array of nodes (or a single node) as a kdl document
+pub fn json_to_kdl(value: &Value) -> Result<String, Error> {
+    from_json(Output::Kdl, value)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn conversions() -> Result<(), Error> {
+        let docs = yaml_to_json(&b"a: !Ref x\nb: 0x10\n---\n[1, 2]"[..])?;
+        assert_eq!(
+            docs,
+            [
+                serde_json::json!({"a": {"Ref": "x"}, "b": 16}),
+                serde_json::json!([1, 2])
+            ]
+        );
+        assert_eq!(json_to_yaml(&docs[1])?, "- 1\n- 2\n");
+        #[cfg(feature = "csv")]
+        assert_eq!(
+            csv_to_json(&b"a,b\n1,x\n"[..])?,
+            serde_json::json!([{"a": 1, "b": "x"}])
+        );
+        #[cfg(feature = "csv")]
+        assert_eq!(json_to_csv(&serde_json::json!([{"a": 1}]))?, "a\n1\n");
+        #[cfg(feature = "kdl")]
+        assert_eq!(
+            kdl_to_json(&b"n 1"[..])?,
+            serde_json::json!([{"name": "n", "args": [1]}])
+        );
+        assert!(matches!(
+            yaml_to_json(&b"a: [1"[..]),
+            Err(Error::Parse {
+                format: Input::Yaml,
+                ..
+            })
+        ));
+        assert!(matches!(
+            json_to_toml(&serde_json::json!(1)),
+            Err(Error::Serialization(_))
+        ));
+        Ok(())
+    }
+}
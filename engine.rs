@@ -0,0 +1,319 @@
+//! Query engines evaluating jq filters over json documents
+//!
+//! lq runs the jq executable by default ([`JqProcess`]). With the `jaq` feature, [`Jaq`] evaluates
+//! filters in process instead, and a [`Pipeline`](crate::Pipeline) can be given any [`Engine`]
+//! with [`Pipeline::engine`](crate::Pipeline::engine), like a stub in tests:
+//!
+//! ```no_run
+//! use serde_json::Value;
+//!
+//! struct Identity;
+//! impl lq::engine::Engine for Identity {
+//!     fn execute(&self, _filter: &str, docs: &[Value]) -> Result<Vec<Value>, lq::Error> {
+//!         Ok(docs.to_vec())
+//!     }
+//! }
+//!
+//! let mut out = vec![];
+//! lq::Pipeline::new().engine(Identity).run(&b"a: 1"[..], &mut out)?;
+//! # Ok::<(), lq::Error>(())
+//! ```
+use crate::Error;
+use anyhow::Result;
+use serde_json::Value;
+use std::path::PathBuf;
+
+/// A jq implementation, running filters over json documents
+pub trait Engine: Send + Sync {
+    /// Run the filter over each of the documents in turn, returning all of their results in order
+    ///
+    /// Failing to compile or evaluate the filter is an error, as a jq exit code would be.
+    fn execute(&self, filter: &str, docs: &[Value]) -> Result<Vec<Value>, Error>;
+}
+
+/// The jq executable, run as a child process for each execution
+#[derive(Debug, Clone)]
+pub struct JqProcess {
+    program: PathBuf,
+}
+
+impl JqProcess {
+    /// The jq executable at a path, or found on the PATH for a bare name
+    pub fn new(program: impl Into<PathBuf>) -> Self {
+        Self {
+            program: program.into(),
+        }
+    }
+}
+
+impl Default for JqProcess {
+    fn default() -> Self {
+        Self::new("jq")
+    }
+}
+
+impl Engine for JqProcess {
+    fn execute(&self, filter: &str, docs: &[Value]) -> Result<Vec<Value>, Error> {
+        let mut input = vec![];
+        for doc in docs {
+            serde_json::to_writer(&mut input, doc).map_err(|e| Error::Serialization(e.into()))?;
+            input.push(b'\n');
+        }
+        let output = crate::run_jq(&self.program, &["-c".into(), filter.into()], &input)?;
+        match output.status.code() {
+            Some(0) => {}
+            Some(code) => return Err(Error::JqFailed(code)),
+            None => return Err(Error::Other(anyhow::anyhow!("jq was killed: {}", output.status))),
+        }
+        serde_json::Deserializer::from_slice(&output.stdout)
+            .into_iter()
+            .collect::<Result<_, _>>()
+            .map_err(|e| Error::Other(e.into()))
+    }
+}
+
+/// The jaq implementation of jq, evaluating filters in process (requires the `jaq` feature)
+///
+/// Its standard library and number handling differ from jq in places; see the jaq documentation.
+#[cfg(feature = "jaq")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Jaq;
+
+#[cfg(feature = "jaq")]
+impl Engine for Jaq {
+    fn execute(&self, filter: &str, docs: &[Value]) -> Result<Vec<Value>, Error> {
+        use jaq_core::load::{Arena, File, Loader};
+        use jaq_core::{Compiler, Ctx, RcIter};
+        use jaq_json::Val;
+        let invalid = |errors: String| Error::Other(anyhow::anyhow!("invalid filter {filter:?}: {errors}"));
+        let loader = Loader::new(jaq_std::defs().chain(jaq_json::defs()));
+        let arena = Arena::default();
+        let modules = loader
+            .load(
+                &arena,
+                File {
+                    code: filter,
+                    path: (),
+                },
+            )
+            .map_err(|errors| {
+                invalid(format!(
+                    "{:?}",
+                    errors.into_iter().map(|e| e.1).collect::<Vec<_>>()
+                ))
+            })?;
+        let compiled = Compiler::default()
+            .with_funs(jaq_std::funs().chain(jaq_json::funs()))
+            .compile(modules)
+            .map_err(|errors| {
+                invalid(format!(
+                    "{:?}",
+                    errors.into_iter().map(|e| e.1).collect::<Vec<_>>()
+                ))
+            })?;
+        // the documents are also what input and inputs read from, as in jq
+        let docs: Box<dyn Iterator<Item = Result<Val, String>>> =
+            Box::new(docs.iter().map(|doc| Ok(doc.clone().into())));
+        let inputs = RcIter::new(docs);
+        let mut results = vec![];
+        for doc in &inputs {
+            let doc = doc.map_err(|e| Error::Other(anyhow::anyhow!(e)))?;
+            for result in compiled.run((Ctx::new([], &inputs), doc)) {
+                let value = result.map_err(|e| Error::Other(anyhow::anyhow!("{e}")))?;
+                results.push(value.into());
+            }
+        }
+        Ok(results)
+    }
+}
+
+/// An engine given to a pipeline, shared between clones of its options
+#[derive(Clone)]
+pub(crate) struct Shared(pub(crate) std::sync::Arc<dyn Engine>);
+
+impl std::fmt::Debug for Shared {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("Engine")
+    }
+}
+
+impl Engine for Shared {
+    fn execute(&self, filter: &str, docs: &[Value]) -> Result<Vec<Value>, Error> {
+        self.0.execute(filter, docs)
+    }
+}
+
+/// Evaluate jq arguments with an engine, returning what jq would write to stdout and its exit code
+///
+/// Supports the filter, --arg and --argjson variables (bound at the start of the filter),
+/// and the -c, -r, -j, -n, -e, --seq and --indent options.
+pub(crate) fn run_engine(engine: &dyn Engine, input: &[u8], args: &[String]) -> Result<(Vec<u8>, i32)> {
+    use serde::Serialize;
+    let (mut query, mut bindings) = (None, String::new());
+    let (mut compact, mut raw, mut join, mut null_input, mut exit_status, mut seq) =
+        (false, false, false, false, false, false);
+    let mut indent = 2;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--arg" | "--argjson" => {
+                let (Some(name), Some(value)) = (args.next(), args.next()) else {
+                    anyhow::bail!("missing name or value for {arg}");
+                };
+                let value = if arg == "--arg" {
+                    serde_json::to_string(value)?
+                } else {
+                    value.clone()
+                };
+                bindings.push_str(&format!("({value}) as ${name} | "));
+            }
+            "--indent" => indent = args.next().and_then(|n| n.parse().ok()).unwrap_or(2),
+            "-c" => compact = true,
+            "-r" => raw = true,
+            "-j" => (raw, join) = (true, true),
+            "-n" => null_input = true,
+            "-e" => exit_status = true,
+            "--seq" => seq = true,
+            // colors and buffering only concern the jq executable
+            "-C" | "--unbuffered" => {}
+            flag if flag.starts_with('-') => {
+                anyhow::bail!("{flag} is not supported by custom query engines")
+            }
+            // the first positional argument is the filter, as in jq
+            filter if query.is_none() => query = Some(filter),
+            other => anyhow::bail!("unexpected jq argument {other}"),
+        }
+    }
+    // module directives must stay at the start of the program
+    let (directives, query) = crate::split_directives(query.unwrap_or("."));
+    let filter = format!("{directives}{bindings}{query}");
+    // record separators of --seq input cannot appear within json texts, so read them as whitespace
+    let input: Vec<u8> = input
+        .iter()
+        .map(|&b| if seq && b == 0x1e { b' ' } else { b })
+        .collect();
+    let docs = if null_input {
+        vec![Value::Null]
+    } else {
+        serde_json::Deserializer::from_slice(&input)
+            .into_iter()
+            .collect::<Result<_, _>>()?
+    };
+    let results = engine.execute(&filter, &docs)?;
+    // --exit-status reports on the last result, like jq
+    let code = match results.last() {
+        _ if !exit_status => 0,
+        None => 4,
+        Some(Value::Null | Value::Bool(false)) => 1,
+        Some(_) => 0,
+    };
+    let mut out = vec![];
+    for result in results {
+        if seq {
+            out.push(0x1e);
+        }
+        match result {
+            Value::String(s) if raw => out.extend_from_slice(s.as_bytes()),
+            value if compact || indent == 0 => serde_json::to_writer(&mut out, &value)?,
+            value => {
+                let indent = " ".repeat(indent);
+                let formatter = serde_json::ser::PrettyFormatter::with_indent(indent.as_bytes());
+                value.serialize(&mut serde_json::Serializer::with_formatter(&mut out, formatter))?;
+            }
+        }
+        if !join {
+            out.push(b'\n');
+        }
+    }
+    Ok((out, code))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{Input, Output, Pipeline};
+
+    /// Picks a key of each document, recording the filters it was given
+    #[derive(Default)]
+    struct Pick(std::sync::Mutex<Vec<String>>);
+
+    impl Engine for Pick {
+        fn execute(&self, filter: &str, docs: &[Value]) -> Result<Vec<Value>, Error> {
+            self.0.lock().unwrap().push(filter.to_string());
+            let key = filter.rsplit('.').next().unwrap_or_default();
+            Ok(docs.iter().map(|doc| doc[key].clone()).collect())
+        }
+    }
+
+    #[test]
+    fn custom_engines() -> Result<()> {
+        let engine = std::sync::Arc::new(Pick::default());
+        let mut out = vec![];
+        Pipeline::new()
+            .engine(Shared(engine.clone()))
+            .input_format(Input::Json)
+            .query(".a")
+            .output_format(Output::Yaml)
+            .run(&b"{\"a\": {\"b\": [1]}}"[..], &mut out)?;
+        assert_eq!(String::from_utf8(out)?, "b:\n- 1\n");
+        let (stdout, code) = run_engine(
+            &Shared(engine.clone()),
+            b"{\"s\": \"x\"}",
+            &[
+                ".s".into(),
+                "--arg".into(),
+                "v".into(),
+                "y".into(),
+                "-r".into(),
+                "-e".into(),
+            ],
+        )?;
+        assert_eq!((stdout, code), (b"x\n".to_vec(), 0));
+        assert_eq!(engine.0.lock().unwrap().last().unwrap(), "(\"y\") as $v | .s");
+        let (stdout, _) = run_engine(
+            &Shared(engine.clone()),
+            b"{\"s\": 1}",
+            &["-c".into(), "--seq".into(), ".s".into()],
+        )?;
+        assert_eq!(stdout, b"\x1e1\n");
+        let (stdout, _) = run_engine(
+            &Shared(engine.clone()),
+            b"\x1e{\"s\": 2}\n",
+            &["--seq".into(), ".s".into()],
+        )?;
+        assert_eq!(stdout, b"\x1e2\n");
+        assert!(run_engine(&Shared(engine), b"1", &["--slurp".into()]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn jq_process() -> Result<()> {
+        let docs = [serde_json::json!({"a": 1}), serde_json::json!({"a": 2})];
+        assert_eq!(JqProcess::default().execute(".a", &docs)?, [1, 2]);
+        assert!(matches!(
+            JqProcess::default().execute(".a |||", &docs),
+            Err(Error::JqFailed(3))
+        ));
+        Ok(())
+    }
+
+    #[test]
+    #[cfg(feature = "jaq")]
+    fn jaq() -> Result<()> {
+        let docs = [serde_json::json!({"a": [1, 2]}), serde_json::json!({"a": [3]})];
+        assert_eq!(Jaq.execute(".a | add", &docs)?, [3, 3]);
+        assert_eq!(
+            Jaq.execute("[., input.a[0]]", &docs)?,
+            [serde_json::json!([docs[0], 3])]
+        );
+        assert!(Jaq.execute(".a |||", &docs).is_err());
+        let mut out = vec![];
+        Pipeline::new()
+            .engine(Jaq)
+            .query("[.a[], $__doc_count]")
+            .output_format(Output::Json)
+            .run(&b"a: [1, 2]"[..], &mut out)?;
+        assert_eq!(String::from_utf8(out)?, "[\n  1,\n  2,\n  1\n]\n");
+        Ok(())
+    }
+}
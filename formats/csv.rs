@@ -0,0 +1,142 @@
+//! Csv and tsv input and output (the csv feature)
+use super::SyntaxError;
+use crate::{table_cell, table_columns, table_rows};
+use anyhow::Result;
+
+/// Split csv text into records of fields, with fields containing the delimiter, quotes or newlines quoted
+///
+/// Quotes inside quoted fields are doubled. Empty lines are skipped.
+pub(crate) fn parse_csv(text: &str, delimiter: char, quote: char) -> Result<Vec<Vec<String>>> {
+    let (mut records, mut record, mut field) = (vec![], vec![], String::new());
+    // byte offset of the opening quote of the quoted field being read
+    let mut quoted = None;
+    let bom = if text.starts_with('\u{feff}') {
+        '\u{feff}'.len_utf8()
+    } else {
+        0
+    };
+    let mut chars = text[bom..].char_indices().map(|(i, c)| (i + bom, c)).peekable();
+    while let Some((i, c)) = chars.next() {
+        if quoted.is_some() {
+            match c {
+                c if c == quote && chars.peek().map(|p| p.1) == Some(quote) => {
+                    field.push(quote);
+                    chars.next();
+                }
+                c if c == quote => quoted = None,
+                c => field.push(c),
+            }
+            continue;
+        }
+        match c {
+            c if c == quote && field.is_empty() => quoted = Some(i),
+            c if c == delimiter => record.push(std::mem::take(&mut field)),
+            '\r' if chars.peek().map(|p| p.1) == Some('\n') => {}
+            '\n' => {
+                if !record.is_empty() || !field.is_empty() {
+                    record.push(std::mem::take(&mut field));
+                    records.push(std::mem::take(&mut record));
+                }
+            }
+            c => field.push(c),
+        }
+    }
+    if let Some(offset) = quoted {
+        return Err(SyntaxError {
+            offset,
+            message: "unterminated quoted field".into(),
+        }
+        .into());
+    }
+    if !record.is_empty() || !field.is_empty() {
+        record.push(field);
+        records.push(record);
+    }
+    Ok(records)
+}
+
+/// Write results as csv rows, from objects (with a header row of their keys) or arrays of cells
+///
+/// A single array result is written as its items. Strings are written as is, nulls as empty
+/// cells, and other values as json.
+pub(crate) fn csv_table(
+    mut results: Vec<serde_json::Value>,
+    delimiter: char,
+    quote: char,
+    headers: bool,
+) -> Result<String> {
+    if let [serde_json::Value::Array(items)] = results.as_mut_slice() {
+        if items.iter().all(|item| item.is_object() || item.is_array()) {
+            results = std::mem::take(items);
+        }
+    }
+    let field = |text: &str| {
+        if text.contains([delimiter, quote, '\n', '\r']) {
+            let doubled = text.replace(quote, &format!("{quote}{quote}"));
+            format!("{quote}{doubled}{quote}")
+        } else {
+            text.to_string()
+        }
+    };
+    let line = |cells: Vec<String>| cells.join(&delimiter.to_string()) + "\n";
+    let mut csv = String::new();
+    if results.iter().all(|row| row.is_array()) {
+        for row in &results {
+            let cells = row.as_array().into_iter().flatten();
+            let cells = cells.map(|cell| match cell {
+                serde_json::Value::Null => String::new(),
+                serde_json::Value::String(s) => field(s),
+                value => field(&value.to_string()),
+            });
+            csv.push_str(&line(cells.collect()));
+        }
+        return Ok(csv);
+    }
+    let rows =
+        table_rows(results).map_err(|_| anyhow::anyhow!("csv rows must all be objects or all be arrays"))?;
+    let columns = table_columns(&rows, &[]);
+    if headers {
+        csv.push_str(&line(columns.iter().map(|c| field(c)).collect()));
+    }
+    for row in &rows {
+        let cells = columns
+            .iter()
+            .map(|c| field(&table_cell(row, c).unwrap_or_default()));
+        csv.push_str(&line(cells.collect()));
+    }
+    Ok(csv)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{parse_csv_char, query, Error, Input};
+
+    #[test]
+    fn csv_tables() {
+        let text = "zip;name\r\n01234;\"a;\"\"b\"\"\"\n\n5;'x'\n";
+        let records = parse_csv(text, ';', '"').unwrap();
+        assert_eq!(records, [["zip", "name"], ["01234", "a;\"b\""], ["5", "'x'"]]);
+        let err = parse_csv("a,\"b\n", ',', '"').unwrap_err();
+        assert_eq!(err.downcast_ref::<SyntaxError>().unwrap().offset, 2);
+        assert!(matches!(
+            query(Input::Csv, b"a\n\"1\n", "."),
+            Err(Error::Parse {
+                line: 2,
+                column: 1,
+                ..
+            })
+        ));
+        assert!(query(Input::Csv, b"a,a\n1,2\n", ".").is_err());
+        let rows = vec![serde_json::json!([{"a": 1, "b": "x,y"}, {"b": null, "c": true}])];
+        assert_eq!(
+            csv_table(rows.clone(), ',', '"', true).unwrap(),
+            "a,b,c\n1,\"x,y\",\n,,true\n"
+        );
+        assert_eq!(csv_table(rows, ';', '\'', false).unwrap(), "1;x,y;\n;;true\n");
+        let arrays = vec![serde_json::json!(["a", null]), serde_json::json!([2])];
+        assert_eq!(csv_table(arrays, '\t', '"', true).unwrap(), "a\t\n2\n");
+        assert_eq!(parse_csv_char("\\t"), Ok('\t'));
+        assert!(parse_csv_char(";;").is_err());
+    }
+}
@@ -0,0 +1,296 @@
+//! Clojure edn input (the edn feature)
+use super::{SyntaxError, MAX_DEPTH};
+use anyhow::Result;
+
+/// Parse the top level forms of edn text into json documents
+///
+/// nil, booleans, numbers and strings map to their json counterparts (dropping the N and M
+/// suffixes of big numbers). Keywords and symbols become strings (keywords without their colon),
+/// characters become one character strings, and lists, vectors and sets become arrays. Map keys
+/// that are not strings or keywords are written as json. #inst and #uuid values are kept as their
+/// strings, and other tagged values become singleton maps ({"my/tag": value}), like yaml tags.
+pub(crate) fn edn_to_json(text: &str) -> Result<Vec<serde_json::Value>> {
+    let mut parser = EdnParser {
+        text,
+        pos: 0,
+        depth: 0,
+    };
+    let mut docs = vec![];
+    while let Some(doc) = parser.next_form()? {
+        docs.push(doc);
+    }
+    if let Some(c) = parser.peek() {
+        return Err(parser.error(format!("unexpected {c:?}")).into());
+    }
+    Ok(docs)
+}
+
+/// A recursive descent edn parser over text, at a byte position
+struct EdnParser<'a> {
+    text: &'a str,
+    pos: usize,
+    /// Number of forms being read, up to MAX_DEPTH
+    depth: usize,
+}
+
+impl<'a> EdnParser<'a> {
+    fn error(&self, message: impl Into<String>) -> SyntaxError {
+        SyntaxError {
+            offset: self.pos,
+            message: message.into(),
+        }
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.text[self.pos..].chars().next()
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.pos += c.len_utf8();
+        Some(c)
+    }
+
+    /// Skip whitespace, commas, comments and #_ discarded forms
+    fn skip_blank(&mut self) -> Result<(), SyntaxError> {
+        loop {
+            match self.peek() {
+                Some(c) if c.is_whitespace() || c == ',' => {
+                    self.bump();
+                }
+                Some(';') => {
+                    let rest = &self.text[self.pos..];
+                    self.pos += rest.find('\n').unwrap_or(rest.len());
+                }
+                Some('#') if self.text[self.pos..].starts_with("#_") => {
+                    self.pos += 2;
+                    if self.next_form()?.is_none() {
+                        return Err(self.error("expected a form to discard after #_"));
+                    }
+                }
+                _ => return Ok(()),
+            }
+        }
+    }
+
+    /// The next form, or None at the end of the input or a closing delimiter
+    fn next_form(&mut self) -> Result<Option<serde_json::Value>, SyntaxError> {
+        if self.depth > MAX_DEPTH {
+            return Err(self.error(format!("form is nested deeper than {MAX_DEPTH} levels")));
+        }
+        self.depth += 1;
+        let form = self.form();
+        self.depth -= 1;
+        form
+    }
+
+    /// The form at the position, after next_form's depth check
+    fn form(&mut self) -> Result<Option<serde_json::Value>, SyntaxError> {
+        use serde_json::Value;
+        self.skip_blank()?;
+        let Some(c) = self.peek() else {
+            return Ok(None);
+        };
+        let value = match c {
+            ')' | ']' | '}' => return Ok(None),
+            '(' | '[' => {
+                self.bump();
+                Value::Array(self.forms_until(if c == '(' { ')' } else { ']' })?)
+            }
+            '{' => {
+                self.bump();
+                let start = self.pos;
+                let forms = self.forms_until('}')?;
+                if forms.len() % 2 != 0 {
+                    self.pos = start;
+                    return Err(self.error("map literal must contain an even number of forms"));
+                }
+                let mut map = serde_json::Map::new();
+                let mut forms = forms.into_iter();
+                while let (Some(key), Some(value)) = (forms.next(), forms.next()) {
+                    let key = match key {
+                        Value::String(s) => s,
+                        key => key.to_string(),
+                    };
+                    map.insert(key, value);
+                }
+                Value::Object(map)
+            }
+            '#' => {
+                self.bump();
+                match self.peek() {
+                    Some('{') => {
+                        self.bump();
+                        Value::Array(self.forms_until('}')?)
+                    }
+                    _ => {
+                        let tag = self.token();
+                        if tag.is_empty() {
+                            return Err(self.error("expected a tag after #"));
+                        }
+                        let Some(value) = self.next_form()? else {
+                            return Err(self.error(format!("expected a value for tag #{tag}")));
+                        };
+                        match tag {
+                            "inst" | "uuid" => value,
+                            tag => Value::Object(serde_json::Map::from_iter([(tag.to_string(), value)])),
+                        }
+                    }
+                }
+            }
+            '"' => Value::String(self.string()?),
+            '\\' => {
+                self.bump();
+                let name = self.token();
+                let character = match name {
+                    "newline" => '\n',
+                    "return" => '\r',
+                    "space" => ' ',
+                    "tab" => '\t',
+                    _ if name.len() == 5 && name.starts_with('u') => u32::from_str_radix(&name[1..], 16)
+                        .ok()
+                        .and_then(char::from_u32)
+                        .ok_or_else(|| self.error(format!("invalid character \\{name}")))?,
+                    _ => {
+                        // single characters are not followed by a delimiter, like \( or \a
+                        let mut chars = name.chars();
+                        match (chars.next(), chars.next()) {
+                            (Some(c), None) => c,
+                            (None, _) => self
+                                .bump()
+                                .ok_or_else(|| self.error("expected a character after \\"))?,
+                            _ => return Err(self.error(format!("invalid character \\{name}"))),
+                        }
+                    }
+                };
+                Value::String(character.to_string())
+            }
+            ':' => {
+                self.bump();
+                Value::String(self.token().to_string())
+            }
+            _ => {
+                let start = self.pos;
+                let token = self.token();
+                match token {
+                    "" => return Err(self.error(format!("unexpected character {c:?}"))),
+                    "nil" => Value::Null,
+                    "true" => Value::Bool(true),
+                    "false" => Value::Bool(false),
+                    _ if token.starts_with(|c: char| c.is_ascii_digit())
+                        || (token.len() > 1
+                            && token.starts_with(['+', '-'])
+                            && token[1..].starts_with(|c: char| c.is_ascii_digit())) =>
+                    {
+                        let number = token.trim_start_matches('+').trim_end_matches(['N', 'M']);
+                        serde_json::from_str::<serde_json::Number>(number)
+                            .map(Value::Number)
+                            .map_err(|_| SyntaxError {
+                                offset: start,
+                                message: format!("invalid number {token}"),
+                            })?
+                    }
+                    symbol => Value::String(symbol.to_string()),
+                }
+            }
+        };
+        Ok(Some(value))
+    }
+
+    /// Forms up to and including the closing delimiter
+    fn forms_until(&mut self, close: char) -> Result<Vec<serde_json::Value>, SyntaxError> {
+        let mut forms = vec![];
+        loop {
+            if let Some(form) = self.next_form()? {
+                forms.push(form);
+                continue;
+            }
+            return match self.bump() {
+                Some(c) if c == close => Ok(forms),
+                Some(c) => {
+                    self.pos -= c.len_utf8();
+                    Err(self.error(format!("expected {close:?}, found {c:?}")))
+                }
+                None => Err(self.error(format!("expected {close:?} before the end of the input"))),
+            };
+        }
+    }
+
+    /// A symbol, keyword or number token, up to the next delimiter
+    fn token(&mut self) -> &'a str {
+        let start = self.pos;
+        let rest = &self.text[start..];
+        let end = rest
+            .find(|c: char| c.is_whitespace() || "()[]{}\";,".contains(c))
+            .unwrap_or(rest.len());
+        self.pos += end;
+        &self.text[start..start + end]
+    }
+
+    /// A string literal, with its escapes decoded
+    fn string(&mut self) -> Result<String, SyntaxError> {
+        let start = self.pos;
+        self.bump();
+        let mut s = String::new();
+        loop {
+            match self.bump() {
+                Some('"') => return Ok(s),
+                Some('\\') => match self.bump() {
+                    Some('n') => s.push('\n'),
+                    Some('t') => s.push('\t'),
+                    Some('r') => s.push('\r'),
+                    Some('b') => s.push('\u{8}'),
+                    Some('f') => s.push('\u{c}'),
+                    Some(c @ ('"' | '\\')) => s.push(c),
+                    Some('u') => {
+                        let hex = self.text.get(self.pos..self.pos + 4).unwrap_or_default();
+                        let c = u32::from_str_radix(hex, 16).ok().and_then(char::from_u32);
+                        s.push(c.ok_or_else(|| self.error(format!("invalid unicode escape \\u{hex}")))?);
+                        self.pos += 4;
+                    }
+                    c => return Err(self.error(format!("invalid escape {c:?} in string"))),
+                },
+                Some(c) => s.push(c),
+                None => {
+                    self.pos = start;
+                    return Err(self.error("unterminated string"));
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn edn_parsing() {
+        let text = r#"; config
+{:name "a\"b" :n 42N, :tags #{:x} #{1} #inst "2024-01-01" :t #my/tag (sym \space A) #_ 1} 2"#;
+        let docs = edn_to_json(text).unwrap();
+        let expected = serde_json::json!({
+            "name": "a\"b",
+            "n": 42,
+            "tags": ["x"],
+            "[1]": "2024-01-01",
+            "t": {"my/tag": ["sym", " ", "A"]},
+        });
+        assert_eq!(docs, [expected, serde_json::json!(2)]);
+        assert!(edn_to_json("").unwrap().is_empty());
+        let err = edn_to_json("[1\n {:a}]").unwrap_err();
+        let err = err.downcast_ref::<SyntaxError>().unwrap();
+        assert_eq!(err.location(b"[1\n {:a}]").0, 2);
+        assert!(edn_to_json("(1]").is_err());
+        assert!(edn_to_json("\"x").is_err());
+        let err = edn_to_json("{:a 1} ] {:b 2}").unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<SyntaxError>().unwrap().message,
+            "unexpected ']'"
+        );
+        assert!(edn_to_json(&"[".repeat(200_000)).is_err());
+        let nested = format!("{}{}", "[".repeat(128), "]".repeat(128));
+        assert!(edn_to_json(&nested).is_ok());
+        assert!(edn_to_json(&format!("[{nested}]")).is_err());
+    }
+}
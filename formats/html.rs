@@ -0,0 +1,58 @@
+//! Html tables of query results (--output=html)
+use crate::{table_cell, table_columns};
+
+/// Stylesheet of standalone --output=html documents
+const HTML_STYLE: &str = "body { font-family: sans-serif; }
+table { border-collapse: collapse; }
+th, td { border: 1px solid #ccc; padding: 4px 8px; text-align: left; vertical-align: top; }
+th { background: #f2f2f2; }
+tr:nth-child(even) td { background: #fafafa; }";
+
+/// Render rows of objects as an html table, in a standalone document with a stylesheet unless bare
+pub(crate) fn html_table(rows: &[serde_json::Map<String, serde_json::Value>], bare: bool) -> String {
+    let escape = |text: &str| {
+        text.replace('&', "&amp;")
+            .replace('<', "&lt;")
+            .replace('>', "&gt;")
+            .replace('"', "&quot;")
+    };
+    let columns = table_columns(rows, &[]);
+    let mut table = String::from("<table>\n<thead>\n<tr>");
+    for column in &columns {
+        table.push_str(&format!("<th>{}</th>", escape(column)));
+    }
+    table.push_str("</tr>\n</thead>\n<tbody>\n");
+    for row in rows {
+        table.push_str("<tr>");
+        for column in &columns {
+            let cell = table_cell(row, column).unwrap_or_default();
+            table.push_str(&format!("<td>{}</td>", escape(&cell)));
+        }
+        table.push_str("</tr>\n");
+    }
+    table.push_str("</tbody>\n</table>");
+    if bare {
+        return table;
+    }
+    format!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<style>\n{HTML_STYLE}\n</style>\n</head>\n<body>\n{table}\n</body>\n</html>"
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::table_rows;
+
+    #[test]
+    fn html_tables() {
+        let rows = table_rows(vec![serde_json::json!([{"name": "<b>", "n": 1}, {"n": null}])]).unwrap();
+        assert_eq!(
+            html_table(&rows, true),
+            "<table>\n<thead>\n<tr><th>name</th><th>n</th></tr>\n</thead>\n<tbody>\n\
+             <tr><td>&lt;b&gt;</td><td>1</td></tr>\n<tr><td></td><td></td></tr>\n</tbody>\n</table>"
+        );
+        assert!(html_table(&rows, false).starts_with("<!DOCTYPE html>"));
+        assert!(table_rows(vec![serde_json::json!(1)]).is_err());
+    }
+}
@@ -0,0 +1,465 @@
+//! Kdl input and output (the kdl feature) with a node/args/props/children mapping
+use super::{SyntaxError, MAX_DEPTH};
+use anyhow::Result;
+
+/// Parse a kdl document into json, as an array of its nodes
+///
+/// Nodes become objects with their "name", and when present, their "type" annotation, "args"
+/// (an array of their arguments), "props" (an object of their properties) and "children" (an
+/// array of their child nodes). Type annotations of values are dropped, and /- comments out the
+/// next node, argument, property or children block. Both kdl v1 and v2 keywords are accepted,
+/// with #inf, #-inf and #nan read as strings.
+pub(crate) fn kdl_to_json(text: &str) -> Result<serde_json::Value> {
+    let mut parser = KdlParser {
+        text,
+        pos: 0,
+        depth: 0,
+    };
+    Ok(serde_json::Value::Array(parser.nodes(false)?))
+}
+
+/// A recursive descent kdl parser over text, at a byte position
+struct KdlParser<'a> {
+    text: &'a str,
+    pos: usize,
+    /// Number of children blocks being read, up to MAX_DEPTH
+    depth: usize,
+}
+
+impl<'a> KdlParser<'a> {
+    fn error(&self, message: impl Into<String>) -> SyntaxError {
+        SyntaxError {
+            offset: self.pos,
+            message: message.into(),
+        }
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.text[self.pos..].chars().next()
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.pos += c.len_utf8();
+        Some(c)
+    }
+
+    fn eat(&mut self, prefix: &str) -> bool {
+        let found = self.text[self.pos..].starts_with(prefix);
+        if found {
+            self.pos += prefix.len();
+        }
+        found
+    }
+
+    /// Skip spaces, comments and escaped newlines, and also newlines and semicolons if `lines`
+    fn skip_blank(&mut self, lines: bool) -> Result<(), SyntaxError> {
+        loop {
+            match self.peek() {
+                Some('\n' | ';') if lines => {
+                    self.bump();
+                }
+                Some(c) if c.is_whitespace() && c != '\n' => {
+                    self.bump();
+                }
+                Some('\u{feff}') => {
+                    self.bump();
+                }
+                Some('/') if self.eat("//") => {
+                    let rest = &self.text[self.pos..];
+                    self.pos += rest.find('\n').unwrap_or(rest.len());
+                }
+                Some('/') if self.eat("/*") => {
+                    let start = self.pos - 2;
+                    let mut depth = 1;
+                    while depth > 0 {
+                        if self.eat("/*") {
+                            depth += 1;
+                        } else if self.eat("*/") {
+                            depth -= 1;
+                        } else if self.bump().is_none() {
+                            self.pos = start;
+                            return Err(self.error("unterminated comment"));
+                        }
+                    }
+                }
+                Some('\\') => {
+                    self.bump();
+                    self.skip_blank(false)?;
+                    if !self.eat("\n") && self.peek().is_some() {
+                        return Err(self.error("expected a newline after \\"));
+                    }
+                }
+                _ => return Ok(()),
+            }
+        }
+    }
+
+    /// The nodes up to the end of the input, or up to and including a closing brace for children
+    fn nodes(&mut self, children: bool) -> Result<Vec<serde_json::Value>, SyntaxError> {
+        let mut nodes = vec![];
+        loop {
+            self.skip_blank(true)?;
+            match self.peek() {
+                None if children => return Err(self.error("expected '}' before the end of the input")),
+                None => return Ok(nodes),
+                Some('}') if children => {
+                    self.bump();
+                    return Ok(nodes);
+                }
+                Some('}') => return Err(self.error("unexpected '}'")),
+                _ if self.eat("/-") => {
+                    self.skip_blank(false)?;
+                    self.node()?;
+                }
+                _ => nodes.push(self.node()?),
+            }
+        }
+    }
+
+    /// A node, up to its terminating newline or semicolon (or a closing brace, left in place)
+    fn node(&mut self) -> Result<serde_json::Value, SyntaxError> {
+        use serde_json::{Map, Value};
+        let mut node = Map::new();
+        let annotation = self.annotation()?;
+        let name = match self.value()? {
+            Value::String(name) => name,
+            _ => return Err(self.error("expected a node name")),
+        };
+        node.insert("name".into(), name.into());
+        if let Some(annotation) = annotation {
+            node.insert("type".into(), annotation.into());
+        }
+        let (mut args, mut props, mut children) = (vec![], Map::new(), None);
+        loop {
+            let start = self.pos;
+            self.skip_blank(false)?;
+            let discard = self.eat("/-");
+            if discard {
+                self.skip_blank(false)?;
+            }
+            match self.peek() {
+                None | Some('}') if !discard => break,
+                Some('\n' | ';') if !discard => {
+                    self.bump();
+                    break;
+                }
+                Some('{') => {
+                    if self.depth == MAX_DEPTH {
+                        return Err(self.error(format!("node is nested deeper than {MAX_DEPTH} levels")));
+                    }
+                    self.bump();
+                    self.depth += 1;
+                    let nodes = self.nodes(true)?;
+                    self.depth -= 1;
+                    if !discard {
+                        children = Some(nodes);
+                    }
+                }
+                _ if children.is_some() => {
+                    return Err(self.error("unexpected entry after the children of a node"))
+                }
+                _ if start == self.pos && !discard => {
+                    return Err(self.error("expected a space before an entry"))
+                }
+                _ => {
+                    self.annotation()?;
+                    match self.value()? {
+                        Value::String(key) if self.eat("=") => {
+                            self.annotation()?;
+                            let value = self.value()?;
+                            if !discard {
+                                props.insert(key, value);
+                            }
+                        }
+                        value if !discard => args.push(value),
+                        _ => {}
+                    }
+                }
+            }
+        }
+        if !args.is_empty() {
+            node.insert("args".into(), Value::Array(args));
+        }
+        if !props.is_empty() {
+            node.insert("props".into(), Value::Object(props));
+        }
+        if let Some(children) = children.filter(|c| !c.is_empty()) {
+            node.insert("children".into(), Value::Array(children));
+        }
+        Ok(Value::Object(node))
+    }
+
+    /// A (type) annotation, if there is one
+    fn annotation(&mut self) -> Result<Option<String>, SyntaxError> {
+        if !self.eat("(") {
+            return Ok(None);
+        }
+        let Ok(serde_json::Value::String(annotation)) = self.value() else {
+            return Err(self.error("expected a type name"));
+        };
+        if !self.eat(")") {
+            return Err(self.error("expected ')' after the type name"));
+        }
+        Ok(Some(annotation))
+    }
+
+    /// A string, number, keyword or bare identifier
+    fn value(&mut self) -> Result<serde_json::Value, SyntaxError> {
+        use serde_json::Value;
+        let start = self.pos;
+        let rest = &self.text[start..];
+        if rest.starts_with('"') {
+            return self.string().map(Value::String);
+        }
+        // raw strings are r"..." or r#"..."# in kdl v1, and #"..."# in kdl v2
+        let raw = rest.strip_prefix('r').unwrap_or(rest);
+        let hashes = raw.len() - raw.trim_start_matches('#').len();
+        if (raw.len() < rest.len() || hashes > 0) && raw[hashes..].starts_with('"') {
+            self.pos += rest.len() - raw.len() + hashes + 1;
+            let close = format!("\"{}", "#".repeat(hashes));
+            let Some(end) = self.text[self.pos..].find(&close) else {
+                self.pos = start;
+                return Err(self.error("unterminated raw string"));
+            };
+            let value = &self.text[self.pos..self.pos + end];
+            self.pos += end + close.len();
+            return Ok(Value::String(value.to_string()));
+        }
+        let end = rest
+            .find(|c: char| c.is_whitespace() || "\\/(){}<>;[]=,\"".contains(c))
+            .unwrap_or(rest.len());
+        if end == 0 {
+            return Err(match self.peek() {
+                Some(c) => self.error(format!("unexpected {c:?}")),
+                None => self.error("unexpected end of input"),
+            });
+        }
+        let token = &rest[..end];
+        self.pos += end;
+        let unsigned = token.trim_start_matches(['+', '-']);
+        let value = match token {
+            "true" | "#true" => Value::Bool(true),
+            "false" | "#false" => Value::Bool(false),
+            "null" | "#null" => Value::Null,
+            "#inf" | "#-inf" | "#nan" => Value::String(token[1..].to_string()),
+            _ if token.starts_with('#') => {
+                self.pos = start;
+                return Err(self.error(format!("unknown keyword {token}")));
+            }
+            _ if unsigned.starts_with(|c: char| c.is_ascii_digit()) && unsigned.len() + 1 >= token.len() => {
+                let number = token.replace('_', "");
+                let negative = number.starts_with('-');
+                let digits = number.trim_start_matches(['+', '-']);
+                let radix = match digits.get(..2) {
+                    Some("0x") => Some(16),
+                    Some("0o") => Some(8),
+                    Some("0b") => Some(2),
+                    _ => None,
+                };
+                let parsed = match radix {
+                    Some(radix) => i64::from_str_radix(&digits[2..], radix)
+                        .ok()
+                        .map(|n| Value::from(if negative { -n } else { n })),
+                    None => serde_json::from_str(number.trim_start_matches('+')).ok(),
+                };
+                match parsed {
+                    Some(value @ Value::Number(_)) => value,
+                    _ => {
+                        self.pos = start;
+                        return Err(self.error(format!("invalid number {token}")));
+                    }
+                }
+            }
+            identifier => Value::String(identifier.to_string()),
+        };
+        Ok(value)
+    }
+
+    /// A quoted string, with its escapes decoded
+    fn string(&mut self) -> Result<String, SyntaxError> {
+        let start = self.pos;
+        self.bump();
+        let mut s = String::new();
+        loop {
+            match self.bump() {
+                Some('"') => return Ok(s),
+                Some('\\') => match self.bump() {
+                    Some('n') => s.push('\n'),
+                    Some('r') => s.push('\r'),
+                    Some('t') => s.push('\t'),
+                    Some('b') => s.push('\u{8}'),
+                    Some('f') => s.push('\u{c}'),
+                    Some('s') => s.push(' '),
+                    Some(c @ ('"' | '\\' | '/')) => s.push(c),
+                    Some('u') if self.eat("{") => {
+                        let rest = &self.text[self.pos..];
+                        let hex = &rest[..rest.find('}').unwrap_or(0)];
+                        let c = u32::from_str_radix(hex, 16).ok().and_then(char::from_u32);
+                        s.push(c.ok_or_else(|| self.error(format!("invalid unicode escape \\u{{{hex}}}")))?);
+                        self.pos += hex.len() + 1;
+                    }
+                    Some(c) if c.is_whitespace() => {
+                        let rest = &self.text[self.pos..];
+                        self.pos += rest.find(|c: char| !c.is_whitespace()).unwrap_or(rest.len());
+                    }
+                    c => return Err(self.error(format!("invalid escape {c:?} in string"))),
+                },
+                Some(c) => s.push(c),
+                None => {
+                    self.pos = start;
+                    return Err(self.error("unterminated string"));
+                }
+            }
+        }
+    }
+}
+
+/// Write json as a kdl document, mapping it back like kdl_to_json
+///
+/// The value must be an array of nodes (or a single node), with only scalar arguments and properties.
+pub(crate) fn json_to_kdl(value: &serde_json::Value) -> Result<String> {
+    use serde_json::Value;
+    fn string(s: &str) -> String {
+        let mut out = String::from("\"");
+        for c in s.chars() {
+            match c {
+                '"' => out.push_str("\\\""),
+                '\\' => out.push_str("\\\\"),
+                '\n' => out.push_str("\\n"),
+                '\r' => out.push_str("\\r"),
+                '\t' => out.push_str("\\t"),
+                c if c.is_control() => out.push_str(&format!("\\u{{{:x}}}", c as u32)),
+                c => out.push(c),
+            }
+        }
+        out + "\""
+    }
+    fn identifier(s: &str) -> String {
+        let unsigned = s.trim_start_matches(['+', '-']);
+        let bare = !s.is_empty()
+            && !s.contains(|c: char| c.is_whitespace() || c.is_control() || "\\/(){}<>;[]=,\"#".contains(c))
+            && !unsigned.starts_with(|c: char| c.is_ascii_digit())
+            && !matches!(s, "true" | "false" | "null");
+        if bare {
+            s.to_string()
+        } else {
+            string(s)
+        }
+    }
+    fn scalar(value: &Value) -> Result<String> {
+        match value {
+            Value::String(s) => Ok(string(s)),
+            Value::Array(_) | Value::Object(_) => anyhow::bail!("kdl values must be scalars, found {value}"),
+            value => Ok(value.to_string()),
+        }
+    }
+    fn node(value: &Value, depth: usize, out: &mut String) -> Result<()> {
+        let Some(Value::String(name)) = value.get("name") else {
+            anyhow::bail!("kdl nodes must be objects with a string name, found {value}");
+        };
+        if let Some(key) = value.as_object().and_then(|map| {
+            map.keys()
+                .find(|k| !["name", "type", "args", "props", "children"].contains(&k.as_str()))
+        }) {
+            anyhow::bail!("unexpected key {key:?} in kdl node {name:?}");
+        }
+        let indent = "    ".repeat(depth);
+        out.push_str(&indent);
+        if let Some(annotation) = value.get("type").and_then(Value::as_str) {
+            out.push_str(&format!("({})", identifier(annotation)));
+        }
+        out.push_str(&identifier(name));
+        match value.get("args") {
+            Some(Value::Array(args)) => {
+                for arg in args {
+                    out.push_str(&format!(" {}", scalar(arg)?));
+                }
+            }
+            None | Some(Value::Null) => {}
+            Some(args) => out.push_str(&format!(" {}", scalar(args)?)),
+        }
+        match value.get("props") {
+            Some(Value::Object(props)) => {
+                for (key, value) in props {
+                    out.push_str(&format!(" {}={}", identifier(key), scalar(value)?));
+                }
+            }
+            None | Some(Value::Null) => {}
+            Some(props) => anyhow::bail!("kdl props must be an object, found {props}"),
+        }
+        match value.get("children") {
+            Some(Value::Array(children)) if !children.is_empty() => {
+                out.push_str(" {\n");
+                for child in children {
+                    node(child, depth + 1, out)?;
+                }
+                out.push_str(&format!("{indent}}}\n"));
+            }
+            Some(Value::Object(_)) => {
+                out.push_str(" {\n");
+                node(&value["children"], depth + 1, out)?;
+                out.push_str(&format!("{indent}}}\n"));
+            }
+            None | Some(Value::Null | Value::Array(_)) => out.push('\n'),
+            Some(children) => anyhow::bail!("kdl children must be an array of nodes, found {children}"),
+        }
+        Ok(())
+    }
+    let mut out = String::new();
+    match value {
+        Value::Array(nodes) => {
+            for value in nodes {
+                node(value, 0, &mut out)?;
+            }
+        }
+        value => node(value, 0, &mut out)?,
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn kdl_mapping() -> Result<()> {
+        let text =
+            "// c\nnode 1 r#\"a\"b\"# \\\n  key=0x10 /* x */ { (t)child null; }\n/-gone\nlast \"e\\u{e9}\"";
+        let doc = kdl_to_json(text)?;
+        let expected = serde_json::json!([
+            {"name": "node", "args": [1, "a\"b"], "props": {"key": 16}, "children": [{"name": "child", "type": "t", "args": [null]}]},
+            {"name": "last", "args": ["e\u{e9}"]},
+        ]);
+        assert_eq!(doc, expected);
+        let written = json_to_kdl(&doc)?;
+        assert_eq!(
+            written,
+            "node 1 \"a\\\"b\" key=16 {\n    (t)child null\n}\nlast \"e\u{e9}\"\n"
+        );
+        assert_eq!(kdl_to_json(&written)?, doc);
+        assert_eq!(
+            json_to_kdl(&serde_json::json!({"name": "1 x", "props": {"true": 1}}))?,
+            "\"1 x\" \"true\"=1\n"
+        );
+        assert!(json_to_kdl(&serde_json::json!({"name": "a", "args": [[1]]})).is_err());
+        assert!(json_to_kdl(&serde_json::json!({"name": "a", "other": 1})).is_err());
+        assert!(kdl_to_json("a { b").is_err());
+        assert!(kdl_to_json(&format!("a {}", "{ b ".repeat(200_000))).is_err());
+        let nested = |n| format!("a {}{}", "{ b ".repeat(n), "}".repeat(n));
+        assert!(kdl_to_json(&nested(128)).is_ok());
+        let err = kdl_to_json(&nested(129)).unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<SyntaxError>().unwrap().message,
+            "node is nested deeper than 128 levels"
+        );
+        for eof in ["a b=", "a (t)", "/-", "a /-"] {
+            let err = kdl_to_json(eof).unwrap_err();
+            assert_eq!(
+                err.downcast_ref::<SyntaxError>().unwrap().message,
+                "unexpected end of input"
+            );
+        }
+        Ok(())
+    }
+}
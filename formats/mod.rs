@@ -0,0 +1,126 @@
+//! Readers and writers of the formats lq converts to and from json, around jq
+//!
+//! Formats behind a feature have a stub here failing with the feature to enable when it is disabled.
+#[cfg(feature = "csv")]
+mod csv;
+#[cfg(feature = "edn")]
+mod edn;
+mod html;
+mod json;
+#[cfg(feature = "kdl")]
+mod kdl;
+#[cfg(feature = "toml")]
+mod toml;
+#[cfg(feature = "xlsx")]
+mod workbook;
+mod xml;
+mod yaml;
+
+#[cfg(feature = "csv")]
+pub(crate) use csv::{csv_table, parse_csv};
+#[cfg(feature = "edn")]
+pub(crate) use edn::edn_to_json;
+pub(crate) use html::html_table;
+pub(crate) use json::StrictJson;
+#[cfg(feature = "kdl")]
+pub(crate) use kdl::{json_to_kdl, kdl_to_json};
+#[cfg(feature = "toml")]
+pub(crate) use toml::{json_to_styled_toml, json_to_toml, toml_error_location, toml_to_json};
+#[cfg(feature = "xlsx")]
+pub(crate) use workbook::workbook_to_json;
+pub(crate) use xml::{json_to_xml, xml_error_location, xml_to_json, XmlStyle};
+pub(crate) use yaml::{
+    check_strict_yaml, flow_yaml, read_yaml_chunks, yaml_error_location, yaml_is_blank, yaml_to_json,
+    Limited, StreamEvents, YamlLimits, STREAM_CLOSED,
+};
+
+/// Nesting limit of the recursive descent parsers, as serde_json's for json
+#[cfg(any(feature = "edn", feature = "kdl"))]
+const MAX_DEPTH: usize = 128;
+
+/// The (1-based) line and column of a byte offset in the text
+fn offset_location(data: &[u8], offset: usize) -> (usize, usize) {
+    let before = &data[..offset.min(data.len())];
+    let line = before.iter().filter(|b| **b == b'\n').count() + 1;
+    let line_start = before.iter().rposition(|b| *b == b'\n').map_or(0, |i| i + 1);
+    let column = String::from_utf8_lossy(&before[line_start..]).chars().count() + 1;
+    (line, column)
+}
+
+/// A syntax error in csv, edn or kdl input, at a byte offset
+#[derive(Debug)]
+#[cfg_attr(not(any(feature = "csv", feature = "edn", feature = "kdl")), allow(dead_code))]
+pub(crate) struct SyntaxError {
+    offset: usize,
+    message: String,
+}
+
+impl std::fmt::Display for SyntaxError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} at byte {}", self.message, self.offset)
+    }
+}
+
+impl std::error::Error for SyntaxError {}
+
+impl SyntaxError {
+    /// Line, column and message of the error in the input
+    pub(crate) fn location(&self, data: &[u8]) -> (usize, usize, String) {
+        let (line, column) = offset_location(data, self.offset);
+        (line, column, self.message.clone())
+    }
+}
+
+#[cfg(not(feature = "csv"))]
+pub(crate) fn parse_csv(_: &str, _: char, _: char) -> anyhow::Result<Vec<Vec<String>>> {
+    anyhow::bail!("csv input is not supported by this build (compiled without the csv feature)")
+}
+
+#[cfg(not(feature = "csv"))]
+pub(crate) fn csv_table(_: Vec<serde_json::Value>, _: char, _: char, _: bool) -> anyhow::Result<String> {
+    anyhow::bail!("csv output is not supported by this build (compiled without the csv feature)")
+}
+
+#[cfg(not(feature = "edn"))]
+pub(crate) fn edn_to_json(_: &str) -> anyhow::Result<Vec<serde_json::Value>> {
+    anyhow::bail!("edn input is not supported by this build (compiled without the edn feature)")
+}
+
+#[cfg(not(feature = "kdl"))]
+pub(crate) fn kdl_to_json(_: &str) -> anyhow::Result<serde_json::Value> {
+    anyhow::bail!("kdl input is not supported by this build (compiled without the kdl feature)")
+}
+
+#[cfg(not(feature = "kdl"))]
+pub(crate) fn json_to_kdl(_: &serde_json::Value) -> anyhow::Result<String> {
+    anyhow::bail!("kdl output is not supported by this build (compiled without the kdl feature)")
+}
+
+#[cfg(not(feature = "toml"))]
+pub(crate) fn json_to_styled_toml(
+    _: &serde_json::Value,
+    _: bool,
+    _: crate::TomlArrays,
+) -> anyhow::Result<String> {
+    anyhow::bail!("toml output is not supported by this build (compiled without the toml feature)")
+}
+
+#[cfg(not(feature = "toml"))]
+pub(crate) fn toml_to_json(_: &str) -> anyhow::Result<serde_json::Value> {
+    anyhow::bail!("toml input is not supported by this build (compiled without the toml feature)")
+}
+
+#[cfg(not(feature = "toml"))]
+pub(crate) fn toml_error_location(_: &anyhow::Error, _: &[u8]) -> Option<(usize, usize, String)> {
+    None
+}
+
+#[cfg(not(feature = "toml"))]
+pub(crate) fn json_to_toml(_: &serde_json::Value) -> anyhow::Result<String> {
+    anyhow::bail!("toml output is not supported by this build (compiled without the toml feature)")
+}
+
+#[cfg(not(feature = "xlsx"))]
+pub(crate) fn workbook_to_json(_: Vec<u8>, _: Option<&str>) -> anyhow::Result<serde_json::Value> {
+    anyhow::bail!("spreadsheet input is not supported by this build (compiled without the xlsx feature)")
+}
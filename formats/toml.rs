@@ -0,0 +1,64 @@
+//! Toml input and output (the toml feature)
+use super::offset_location;
+use crate::TomlArrays;
+use anyhow::Result;
+
+/// Parse a toml document into json
+pub(crate) fn toml_to_json(toml_str: &str) -> Result<serde_json::Value> {
+    let doc: toml::Table = toml_str.parse()?;
+    Ok(doc.try_into()?)
+}
+
+/// Line, column and message of a toml parse error, from the byte span it reports
+pub(crate) fn toml_error_location(err: &anyhow::Error, data: &[u8]) -> Option<(usize, usize, String)> {
+    let e = err.downcast_ref::<toml::de::Error>()?;
+    let (line, column) = offset_location(data, e.span()?.start);
+    Some((line, column, e.message().to_string()))
+}
+
+/// Serialize json as a toml document
+pub(crate) fn json_to_toml(value: &serde_json::Value) -> Result<String> {
+    Ok(toml::to_string(value)?)
+}
+
+/// Serialize json as a toml document, with nested tables and arrays of objects written inline if asked
+pub(crate) fn json_to_styled_toml(
+    value: &serde_json::Value,
+    inline_tables: bool,
+    arrays: TomlArrays,
+) -> Result<String> {
+    use toml_edit::{ArrayOfTables, Item, Table, Value};
+    // the serializer writes everything below the top level inline, so expand what should not be
+    fn expand(table: &mut Table, inline_tables: bool, arrays: TomlArrays) {
+        // leave out headers of tables with only subtables, but keep empty tables
+        table.set_implicit(!table.is_empty());
+        for (_, item) in table.iter_mut() {
+            *item = match std::mem::take(item) {
+                Item::Value(Value::InlineTable(inline)) if !inline_tables => {
+                    let mut table = inline.into_table();
+                    expand(&mut table, inline_tables, arrays);
+                    Item::Table(table)
+                }
+                Item::Value(Value::Array(array))
+                    if arrays == TomlArrays::Tables
+                        && !array.is_empty()
+                        && array.iter().all(|v| v.is_inline_table()) =>
+                {
+                    let mut tables = ArrayOfTables::new();
+                    for value in array {
+                        if let Value::InlineTable(inline) = value {
+                            let mut table = inline.into_table();
+                            expand(&mut table, inline_tables, arrays);
+                            tables.push(table);
+                        }
+                    }
+                    Item::ArrayOfTables(tables)
+                }
+                other => other,
+            };
+        }
+    }
+    let mut doc = toml_edit::ser::to_document(value)?;
+    expand(doc.as_table_mut(), inline_tables, arrays);
+    Ok(doc.to_string())
+}
@@ -0,0 +1,80 @@
+//! Excel and opendocument spreadsheets (the xlsx feature), as objects of sheets of rows
+use anyhow::Result;
+
+/// Read the sheets of an xlsx, xls or ods workbook into json, or only the named sheet
+///
+/// Sheets become arrays of objects keyed by the cells of their first row (or the column letter
+/// when that is empty). Whole numbers become integers, dates become ISO 8601 strings, and
+/// empty cells become null.
+pub(crate) fn workbook_to_json(data: Vec<u8>, sheet: Option<&str>) -> Result<serde_json::Value> {
+    use calamine::{Data, Reader};
+    use serde_json::Value;
+    let cell = |data: &Data| match data {
+        Data::Empty => Value::Null,
+        Data::Bool(b) => Value::Bool(*b),
+        Data::Int(n) => Value::from(*n),
+        Data::Float(f) if f.fract() == 0.0 && f.abs() < 9007199254740992.0 => Value::from(*f as i64),
+        Data::Float(f) => Value::from(*f),
+        Data::String(s) | Data::DateTimeIso(s) | Data::DurationIso(s) => Value::String(s.clone()),
+        Data::DateTime(d) => match d.as_datetime() {
+            Some(d) => Value::String(d.to_string().replacen(' ', "T", 1)),
+            None => Value::from(d.as_f64()),
+        },
+        Data::Error(e) => Value::String(e.to_string()),
+    };
+    let mut workbook = calamine::open_workbook_auto_from_rs(std::io::Cursor::new(data))
+        .map_err(|e| anyhow::anyhow!("invalid spreadsheet: {e}"))?;
+    let names = workbook.sheet_names();
+    if let Some(name) = sheet {
+        if !names.iter().any(|n| n == name) {
+            anyhow::bail!(
+                "no sheet named {name} in the workbook (found {})",
+                names.join(", ")
+            );
+        }
+    }
+    let mut sheets = serde_json::Map::new();
+    for name in names.into_iter().filter(|n| sheet.map_or(true, |s| s == n)) {
+        let range = workbook
+            .worksheet_range(&name)
+            .map_err(|e| anyhow::anyhow!("failed to read sheet {name}: {e}"))?;
+        let mut rows = range.rows();
+        let headers: Vec<String> = match rows.next() {
+            Some(header) => header
+                .iter()
+                .enumerate()
+                .map(|(i, h)| match cell(h) {
+                    Value::Null => column_letter(i),
+                    Value::String(s) => s,
+                    h => h.to_string(),
+                })
+                .collect(),
+            None => vec![],
+        };
+        let objects = rows
+            .map(|row| {
+                let fields = headers.iter().cloned().zip(row.iter().map(cell));
+                Value::Object(fields.collect())
+            })
+            .collect();
+        sheets.insert(name, Value::Array(objects));
+    }
+    Ok(match sheet {
+        Some(name) => sheets.remove(name).unwrap_or_default(),
+        None => Value::Object(sheets),
+    })
+}
+
+/// Spreadsheet name of a zero based column index (A, B, ..., Z, AA, ...)
+fn column_letter(mut i: usize) -> String {
+    let mut name = vec![];
+    loop {
+        name.push(b'A' + (i % 26) as u8);
+        if i < 26 {
+            break;
+        }
+        i = i / 26 - 1;
+    }
+    name.reverse();
+    String::from_utf8(name).unwrap()
+}
@@ -0,0 +1,229 @@
+//! Xml input and output (the xml feature), mapping elements to objects
+use anyhow::Result;
+
+/// How xml maps to json, from the --xml-* flags
+#[derive(Debug, Clone)]
+#[cfg_attr(not(feature = "xml"), allow(dead_code))]
+pub(crate) struct XmlStyle {
+    pub(crate) attribute_prefix: String,
+    pub(crate) text_key: String,
+    pub(crate) strip_namespaces: bool,
+    pub(crate) force_list: Vec<String>,
+}
+
+/// Parse an xml document into json, as an object with a key for the root element
+///
+/// Elements with only text become strings (and empty elements null). Others become objects of
+/// their attributes (as prefixed keys), child elements (as arrays when repeated) and any text
+/// under the text key. Namespace prefixes are kept in names as written, unless stripped.
+#[cfg(feature = "xml")]
+pub(crate) fn xml_to_json(text: &str, style: &XmlStyle) -> Result<serde_json::Value> {
+    use serde_json::{Map, Value};
+    fn name(prefix: Option<&str>, local: &str, style: &XmlStyle) -> String {
+        match prefix {
+            Some(prefix) if !style.strip_namespaces => format!("{prefix}:{local}"),
+            _ => local.to_string(),
+        }
+    }
+    fn element(node: roxmltree::Node, style: &XmlStyle) -> Value {
+        let mut map = Map::new();
+        if !style.strip_namespaces {
+            // declarations are the namespaces in scope here but not in the parent
+            let parent: Vec<_> = node.parent_element().map_or(vec![], |p| p.namespaces().collect());
+            for ns in node.namespaces().filter(|ns| !parent.contains(ns)) {
+                let key = match ns.name() {
+                    Some(prefix) => format!("{}xmlns:{prefix}", style.attribute_prefix),
+                    None => format!("{}xmlns", style.attribute_prefix),
+                };
+                map.insert(key, ns.uri().into());
+            }
+        }
+        for attr in node.attributes() {
+            let prefix = attr.namespace().and_then(|uri| node.lookup_prefix(uri));
+            let key = format!("{}{}", style.attribute_prefix, name(prefix, attr.name(), style));
+            map.insert(key, attr.value().into());
+        }
+        let mut text = String::new();
+        for child in node.children() {
+            if child.is_text() {
+                text.push_str(child.text().unwrap_or_default());
+                continue;
+            }
+            if !child.is_element() {
+                continue;
+            }
+            let tag = child.tag_name();
+            let prefix = tag.namespace().and_then(|uri| child.lookup_prefix(uri));
+            let key = name(prefix, tag.name(), style);
+            let value = element(child, style);
+            match map.get_mut(&key) {
+                Some(Value::Array(items)) => items.push(value),
+                Some(existing) => *existing = Value::Array(vec![existing.take(), value]),
+                None if style.force_list.contains(&key) => {
+                    map.insert(key, Value::Array(vec![value]));
+                }
+                None => {
+                    map.insert(key, value);
+                }
+            }
+        }
+        let text = text.trim();
+        match (map.is_empty(), text.is_empty()) {
+            (true, true) => Value::Null,
+            (true, false) => text.into(),
+            (false, false) => {
+                map.insert(style.text_key.clone(), text.into());
+                Value::Object(map)
+            }
+            (false, true) => Value::Object(map),
+        }
+    }
+    let options = roxmltree::ParsingOptions {
+        allow_dtd: true,
+        ..Default::default()
+    };
+    let doc = roxmltree::Document::parse_with_options(text, options)?;
+    let root = doc.root_element();
+    let prefix = root
+        .tag_name()
+        .namespace()
+        .and_then(|uri| root.lookup_prefix(uri));
+    let value = element(root, style);
+    let key = name(prefix, root.tag_name().name(), style);
+    Ok(Value::Object(Map::from_iter([(key, value)])))
+}
+
+/// Line, column and message of an xml parse error
+#[cfg(feature = "xml")]
+pub(crate) fn xml_error_location(err: &anyhow::Error) -> Option<(usize, usize, String)> {
+    let e = err.downcast_ref::<roxmltree::Error>()?;
+    let message = e.to_string();
+    let message = message
+        .rsplit_once(" at ")
+        .map_or(message.as_str(), |(head, _)| head);
+    Some((e.pos().row as usize, e.pos().col as usize, message.to_string()))
+}
+
+#[cfg(not(feature = "xml"))]
+pub(crate) fn xml_error_location(_: &anyhow::Error) -> Option<(usize, usize, String)> {
+    None
+}
+
+#[cfg(not(feature = "xml"))]
+pub(crate) fn xml_to_json(_: &str, _: &XmlStyle) -> Result<serde_json::Value> {
+    anyhow::bail!("xml input is not supported by this build (compiled without the xml feature)")
+}
+
+/// Write json as an indented xml document, mapping it back like xml_to_json
+///
+/// The value must be an object with a single key, naming the root element.
+pub(crate) fn json_to_xml(value: &serde_json::Value, style: &XmlStyle) -> Result<String> {
+    use serde_json::Value;
+    fn escape(text: &str) -> String {
+        text.replace('&', "&amp;")
+            .replace('<', "&lt;")
+            .replace('>', "&gt;")
+            .replace('"', "&quot;")
+    }
+    fn text(value: &Value) -> String {
+        match value {
+            Value::String(s) => escape(s),
+            Value::Null => String::new(),
+            value => escape(&value.to_string()),
+        }
+    }
+    fn element(name: &str, value: &Value, depth: usize, style: &XmlStyle, out: &mut String) -> Result<()> {
+        if name.is_empty() || name.contains(|c: char| c.is_whitespace() || "<>&\"'/=".contains(c)) {
+            anyhow::bail!("{name:?} is not a valid xml name");
+        }
+        let indent = "  ".repeat(depth);
+        let Value::Object(map) = value else {
+            match value {
+                Value::Array(items) => {
+                    for item in items {
+                        element(name, item, depth, style, out)?;
+                    }
+                }
+                Value::Null => out.push_str(&format!("{indent}<{name}/>\n")),
+                value => out.push_str(&format!("{indent}<{name}>{}</{name}>\n", text(value))),
+            }
+            return Ok(());
+        };
+        out.push_str(&format!("{indent}<{name}"));
+        let prefix = &style.attribute_prefix;
+        let is_attribute = |key: &str| !prefix.is_empty() && key.starts_with(prefix.as_str());
+        for (key, value) in map.iter().filter(|(key, _)| is_attribute(key)) {
+            out.push_str(&format!(" {}=\"{}\"", &key[prefix.len()..], text(value)));
+        }
+        let inner = map.get(&style.text_key);
+        let children: Vec<_> = map
+            .iter()
+            .filter(|(key, _)| !is_attribute(key) && **key != style.text_key)
+            .collect();
+        match (inner, children.is_empty()) {
+            (None, true) => out.push_str("/>\n"),
+            (Some(inner), true) => out.push_str(&format!(">{}</{name}>\n", text(inner))),
+            (inner, false) => {
+                out.push_str(">\n");
+                if let Some(inner) = inner {
+                    out.push_str(&format!("{indent}  {}\n", text(inner)));
+                }
+                for (key, value) in children {
+                    element(key, value, depth + 1, style, out)?;
+                }
+                out.push_str(&format!("{indent}</{name}>\n"));
+            }
+        }
+        Ok(())
+    }
+    let root = match value {
+        Value::Object(map) if map.len() == 1 => map.iter().next().filter(|(_, v)| !v.is_array()),
+        _ => None,
+    };
+    let Some((name, value)) = root else {
+        anyhow::bail!("xml output must be an object with a single key for the root element, found {value}");
+    };
+    let mut out = String::new();
+    element(name, value, 0, style, &mut out)?;
+    Ok(out)
+}
+
+#[cfg(all(test, feature = "xml"))]
+mod test {
+    use super::*;
+
+    #[test]
+    fn xml_mapping() -> Result<()> {
+        let xml = r#"<r xmlns:n="urn:n" id="1"><n:a>x</n:a><b>1</b><b>2</b><c k="v">t</c><d/></r>"#;
+        let style = XmlStyle {
+            attribute_prefix: "@".into(),
+            text_key: "#text".into(),
+            strip_namespaces: false,
+            force_list: vec!["n:a".into()],
+        };
+        let doc = xml_to_json(xml, &style)?;
+        let expected = serde_json::json!({"r": {
+            "@xmlns:n": "urn:n",
+            "@id": "1",
+            "n:a": ["x"],
+            "b": ["1", "2"],
+            "c": {"@k": "v", "#text": "t"},
+            "d": null,
+        }});
+        assert_eq!(doc, expected);
+        assert_eq!(xml_to_json(&json_to_xml(&doc, &style)?, &style)?, doc);
+
+        let style = XmlStyle {
+            attribute_prefix: "_".into(),
+            strip_namespaces: true,
+            force_list: vec![],
+            ..style
+        };
+        let doc = xml_to_json(xml, &style)?;
+        assert_eq!(doc["r"]["a"], "x");
+        assert_eq!(doc["r"]["c"]["_k"], "v");
+        assert!(doc["r"].get("_xmlns:n").is_none());
+        assert!(json_to_xml(&serde_json::json!({"a": 1, "b": 2}), &style).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,700 @@
+//! Yaml documents: conversion to json and flow style output, limits on aliases, and streaming
+use super::offset_location;
+use crate::{push_index, push_key, strip_location};
+use anyhow::Result;
+use std::io::{BufRead, Write};
+
+/// Where serde_yaml actually found an error, as it reports some at the start of their mapping or input
+///
+/// Byte offsets in the message are resolved (and dropped from it), and duplicate keys are looked
+/// up in the block mapping they were reported at. None when the error cannot be placed.
+pub(crate) fn yaml_error_location(e: &serde_yaml::Error, data: &[u8]) -> Option<(usize, usize, String)> {
+    let message = strip_location(&e.to_string());
+    if let Some((head, offset)) = message.rsplit_once(" at position ") {
+        if let Ok(offset) = offset.parse() {
+            let (line, column) = offset_location(data, offset);
+            return Some((line, column, head.to_string()));
+        }
+    }
+    let location = e.location()?;
+    // after the path of the mapping in nested ones, like .a[0]: duplicate entry with key "b"
+    if let Some((_, key)) = message.split_once("duplicate entry with key ") {
+        let key = key.trim_matches('"');
+        let (line, column) = duplicate_key_location(data, location.line(), location.column(), key)?;
+        return Some((line, column, message));
+    }
+    Some((location.line(), location.column(), message))
+}
+
+/// The line and column of the second `key` of the block mapping starting at a line and column
+fn duplicate_key_location(data: &[u8], line: usize, column: usize, key: &str) -> Option<(usize, usize)> {
+    let text = String::from_utf8_lossy(data);
+    let indent = column - 1;
+    let mut seen = false;
+    for (i, source) in text.lines().enumerate().skip(line - 1) {
+        let rest = if i == line - 1 {
+            // the first key can follow a sequence indicator, like - a: 1
+            source.get(indent..)?
+        } else {
+            let trimmed = source.trim_start_matches(' ');
+            if trimmed.is_empty() || trimmed.starts_with('#') {
+                continue;
+            }
+            match source.len() - trimmed.len() {
+                n if n < indent => return None,
+                n if n > indent => continue,
+                _ => trimmed,
+            }
+        };
+        let is_key = [key.to_string(), format!("\"{key}\""), format!("'{key}'")]
+            .iter()
+            .any(|k| rest.strip_prefix(k.as_str()).is_some_and(|r| r.starts_with(':')));
+        if is_key && std::mem::replace(&mut seen, true) {
+            return Some((i + 1, column));
+        }
+    }
+    None
+}
+
+/// Split a yaml stream into raw documents at `---` markers, handing each to `f` once the next one starts
+///
+/// Comments and directives before a marker stay with the document that follows.
+/// `f` also gets the number of input lines before the document, for locating errors in it.
+/// Stops early when `f` returns false, and fails on a document larger than `max` bytes.
+pub(crate) fn read_yaml_chunks(
+    mut reader: impl BufRead,
+    max: u64,
+    mut f: impl FnMut(&[u8], usize) -> Result<bool>,
+) -> Result<()> {
+    let (mut chunk, mut has_content, mut line) = (vec![], false, vec![]);
+    let (mut lines, mut first_line) = (0, 0);
+    loop {
+        line.clear();
+        if reader.read_until(b'\n', &mut line)? == 0 {
+            break;
+        }
+        let marker = line.starts_with(b"---") && line.get(3).map_or(true, |c| c.is_ascii_whitespace());
+        if marker && has_content {
+            if !f(&chunk, first_line)? {
+                return Ok(());
+            }
+            (chunk, has_content, first_line) = (vec![], false, lines);
+        }
+        lines += 1;
+        let first = line.iter().find(|c| !c.is_ascii_whitespace());
+        has_content |= marker || !matches!(first, None | Some(b'#' | b'%'));
+        chunk.extend_from_slice(&line);
+        if chunk.len() as u64 > max {
+            anyhow::bail!("a document of the input is larger than --max-input-size ({max} bytes)");
+        }
+    }
+    if has_content {
+        f(&chunk, first_line)?;
+    }
+    Ok(())
+}
+
+/// Reject YAML constructs without a faithful JSON equivalent for --strict
+pub(crate) fn check_strict_yaml(value: &serde_yaml::Value, path: &str) -> Result<()> {
+    use serde_yaml::Value;
+    let at = if path.is_empty() { "." } else { path };
+    match value {
+        Value::Mapping(map) => {
+            for (key, val) in map {
+                let Value::String(key) = key else {
+                    anyhow::bail!("{at}: non-string key {}", serde_yaml::to_string(key)?.trim_end());
+                };
+                check_strict_yaml(val, &push_key(path, key))?;
+            }
+        }
+        Value::Sequence(xs) => {
+            for (i, x) in xs.iter().enumerate() {
+                check_strict_yaml(x, &push_index(path, i))?;
+            }
+        }
+        Value::Tagged(tagged) => anyhow::bail!("{at}: unknown tag {}", tagged.tag),
+        _ => {}
+    }
+    Ok(())
+}
+
+/// Serialize json as yaml with objects and arrays at and below depth in flow style
+pub(crate) fn flow_yaml(value: &serde_json::Value, depth: usize) -> Result<String> {
+    use serde_json::Value;
+    // scalars as serde_yaml writes them, but double quoted where that would not fit on one line
+    // or (in flow style) contains flow indicators
+    fn scalar(value: &Value, flow: bool) -> Result<String> {
+        let Value::String(s) = value else {
+            return Ok(serde_yaml::to_string(value)?.trim_end().to_string());
+        };
+        let yaml = serde_yaml::to_string(s)?;
+        let yaml = yaml.trim_end();
+        let quoted = yaml.starts_with(['\'', '"']);
+        if yaml.contains('\n') || (flow && !quoted && yaml.contains([',', '[', ']', '{', '}'])) {
+            return Ok(serde_json::to_string(s)?);
+        }
+        Ok(yaml.to_string())
+    }
+    fn flow(value: &Value) -> Result<String> {
+        Ok(match value {
+            Value::Array(items) => format!(
+                "[{}]",
+                items.iter().map(flow).collect::<Result<Vec<_>>>()?.join(", ")
+            ),
+            Value::Object(map) => {
+                let entries = map
+                    .iter()
+                    .map(|(k, v)| Ok(format!("{}: {}", scalar(&k.as_str().into(), true)?, flow(v)?)))
+                    .collect::<Result<Vec<_>>>()?;
+                format!("{{{}}}", entries.join(", "))
+            }
+            scalar_value => scalar(scalar_value, true)?,
+        })
+    }
+    // values written on the line of their key or dash
+    fn inline(value: &Value, depth: usize) -> Result<Option<String>> {
+        match value {
+            Value::Array(items) if depth > 0 && !items.is_empty() => Ok(None),
+            Value::Object(map) if depth > 0 && !map.is_empty() => Ok(None),
+            Value::Array(_) | Value::Object(_) => Ok(Some(flow(value)?)),
+            _ => Ok(Some(scalar(value, false)?)),
+        }
+    }
+    // lines of an object or array in block style, unindented
+    fn block(value: &Value, depth: usize) -> Result<Vec<String>> {
+        let mut lines = vec![];
+        match value {
+            Value::Object(map) => {
+                for (k, v) in map {
+                    let key = scalar(&k.as_str().into(), false)?;
+                    match inline(v, depth - 1)? {
+                        Some(text) => lines.push(format!("{key}: {text}")),
+                        None => {
+                            lines.push(format!("{key}:"));
+                            // nested arrays are not indented, as in serde_yaml
+                            let indent = if v.is_object() { "  " } else { "" };
+                            lines.extend(block(v, depth - 1)?.into_iter().map(|l| format!("{indent}{l}")));
+                        }
+                    }
+                }
+            }
+            Value::Array(items) => {
+                for item in items {
+                    match inline(item, depth - 1)? {
+                        Some(text) => lines.push(format!("- {text}")),
+                        None => {
+                            for (i, line) in block(item, depth - 1)?.into_iter().enumerate() {
+                                lines.push(format!("{}{line}", if i == 0 { "- " } else { "  " }));
+                            }
+                        }
+                    }
+                }
+            }
+            _ => lines.push(scalar(value, false)?),
+        }
+        Ok(lines)
+    }
+    let lines = match inline(value, depth)? {
+        Some(text) => vec![text],
+        None => block(value, depth)?,
+    };
+    Ok(lines.join("\n") + "\n")
+}
+
+/// Convert a yaml value to json directly rather than through a serialized yaml string
+///
+/// Merge keys must already be applied. Tagged values are expanded into singleton maps (`!Foo x` -> `{"Foo": "x"}`),
+/// scalar keys are stringified, and non-finite floats become null (as in serde_json).
+pub(crate) fn yaml_to_json(value: serde_yaml::Value) -> Result<serde_json::Value> {
+    use serde_json::Value as Json;
+    use serde_yaml::Value;
+    Ok(match value {
+        Value::Null => Json::Null,
+        Value::Bool(b) => Json::Bool(b),
+        Value::Number(n) => {
+            if let Some(u) = n.as_u64() {
+                u.into()
+            } else if let Some(i) = n.as_i64() {
+                i.into()
+            } else {
+                n.as_f64()
+                    .and_then(serde_json::Number::from_f64)
+                    .map_or(Json::Null, Json::Number)
+            }
+        }
+        Value::String(s) => Json::String(s),
+        Value::Sequence(xs) => Json::Array(xs.into_iter().map(yaml_to_json).collect::<Result<_>>()?),
+        Value::Mapping(map) => {
+            let mut obj = serde_json::Map::new();
+            for (key, val) in map {
+                obj.insert(yaml_key_to_string(key)?, yaml_to_json(val)?);
+            }
+            Json::Object(obj)
+        }
+        Value::Tagged(tagged) => {
+            let tag = tagged.tag.to_string();
+            let tag = tag.strip_prefix('!').unwrap_or(&tag).to_string();
+            Json::Object([(tag, yaml_to_json(tagged.value)?)].into_iter().collect())
+        }
+    })
+}
+
+/// Stringify a yaml mapping key the way serde_json does for non-string scalar keys
+fn yaml_key_to_string(key: serde_yaml::Value) -> Result<String> {
+    use serde_yaml::Value;
+    Ok(match key {
+        Value::String(s) => s,
+        Value::Null => "null".into(),
+        Value::Bool(b) => b.to_string(),
+        Value::Number(n) => n.to_string(),
+        Value::Tagged(tagged) => yaml_key_to_string(tagged.value)?,
+        Value::Sequence(_) | Value::Mapping(_) => {
+            anyhow::bail!(
+                "unsupported non-scalar key {}",
+                serde_yaml::to_string(&key)?.trim_end()
+            )
+        }
+    })
+}
+
+/// Error message used to stop deserialization once jq stops reading events
+pub(crate) const STREAM_CLOSED: &str = "jq closed its input";
+
+/// Writes jq --stream style events for a document while it is being deserialized
+///
+/// Leaves become [path, value], and the end of a non-empty container at path p
+/// with a last key (or index) k becomes [p + [k]]. Empty containers are leaves.
+pub(crate) struct StreamEvents<'a> {
+    pub(crate) path: &'a mut Vec<serde_json::Value>,
+    pub(crate) out: &'a mut dyn Write,
+}
+
+impl StreamEvents<'_> {
+    fn emit<E: serde::de::Error>(&mut self, event: serde_json::Value) -> Result<(), E> {
+        let mut line = serde_json::to_vec(&event).map_err(E::custom)?;
+        line.push(b'\n');
+        self.out.write_all(&line).map_err(|_| E::custom(STREAM_CLOSED))
+    }
+
+    fn leaf<E: serde::de::Error>(&mut self, value: serde_json::Value) -> Result<(), E> {
+        let event = serde_json::json!([self.path, value]);
+        self.emit(event)
+    }
+
+    fn close<E: serde::de::Error>(
+        &mut self,
+        last: Option<serde_json::Value>,
+        empty: serde_json::Value,
+    ) -> Result<(), E> {
+        let Some(last) = last else {
+            return self.leaf(empty);
+        };
+        self.path.push(last);
+        let event = serde_json::json!([self.path]);
+        self.path.pop();
+        self.emit(event)
+    }
+
+    fn child(&mut self) -> StreamEvents<'_> {
+        StreamEvents {
+            path: self.path,
+            out: self.out,
+        }
+    }
+}
+
+impl<'de> serde::de::DeserializeSeed<'de> for StreamEvents<'_> {
+    type Value = ();
+
+    fn deserialize<D: serde::Deserializer<'de>>(self, deserializer: D) -> Result<(), D::Error> {
+        deserializer.deserialize_any(self)
+    }
+}
+
+impl<'de> serde::de::Visitor<'de> for StreamEvents<'_> {
+    type Value = ();
+
+    fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str("any value")
+    }
+
+    fn visit_bool<E: serde::de::Error>(mut self, v: bool) -> Result<(), E> {
+        self.leaf(v.into())
+    }
+
+    fn visit_i64<E: serde::de::Error>(mut self, v: i64) -> Result<(), E> {
+        self.leaf(v.into())
+    }
+
+    fn visit_u64<E: serde::de::Error>(mut self, v: u64) -> Result<(), E> {
+        self.leaf(v.into())
+    }
+
+    fn visit_f64<E: serde::de::Error>(mut self, v: f64) -> Result<(), E> {
+        self.leaf(v.into())
+    }
+
+    fn visit_str<E: serde::de::Error>(mut self, v: &str) -> Result<(), E> {
+        self.leaf(v.into())
+    }
+
+    fn visit_unit<E: serde::de::Error>(mut self) -> Result<(), E> {
+        self.leaf(serde_json::Value::Null)
+    }
+
+    fn visit_none<E: serde::de::Error>(self) -> Result<(), E> {
+        self.visit_unit()
+    }
+
+    fn visit_some<D: serde::Deserializer<'de>>(self, deserializer: D) -> Result<(), D::Error> {
+        deserializer.deserialize_any(self)
+    }
+
+    fn visit_seq<A: serde::de::SeqAccess<'de>>(mut self, mut seq: A) -> Result<(), A::Error> {
+        let mut len = 0usize;
+        loop {
+            self.path.push(len.into());
+            let more = seq.next_element_seed(self.child())?.is_some();
+            self.path.pop();
+            if !more {
+                break;
+            }
+            len += 1;
+        }
+        let last = len.checked_sub(1).map(serde_json::Value::from);
+        self.close(last, serde_json::json!([]))
+    }
+
+    fn visit_map<A: serde::de::MapAccess<'de>>(mut self, mut map: A) -> Result<(), A::Error> {
+        use serde::de::Error;
+        let mut last = None;
+        while let Some(key) = map.next_key::<serde_yaml::Value>()? {
+            let key = yaml_key_to_string(key).map_err(A::Error::custom)?;
+            self.path.push(key.clone().into());
+            map.next_value_seed(self.child())?;
+            self.path.pop();
+            last = Some(key.into());
+        }
+        self.close(last, serde_json::json!({}))
+    }
+
+    fn visit_enum<A: serde::de::EnumAccess<'de>>(mut self, data: A) -> Result<(), A::Error> {
+        use serde::de::VariantAccess;
+        // tags are expanded into singleton maps as in yaml_to_json
+        let (tag, variant) = data.variant::<String>()?;
+        let tag = tag.strip_prefix('!').unwrap_or(&tag).to_string();
+        self.path.push(tag.clone().into());
+        variant.newtype_variant_seed(self.child())?;
+        self.path.pop();
+        self.close(Some(tag.into()), serde_json::json!({}))
+    }
+}
+
+/// Limits on the nodes a yaml document expands to, guarding against alias expansion bombs
+///
+/// Aliases are expanded by serde_yaml while deserializing, so a tiny document of nested aliases
+/// (billion laughs) can expand to more nodes than fit in memory unless they are counted as they come.
+#[derive(Clone, Copy)]
+pub(crate) struct YamlLimits<'a> {
+    pub(crate) nodes: &'a std::cell::Cell<usize>,
+    pub(crate) max_nodes: usize,
+    pub(crate) depth: usize,
+    pub(crate) max_depth: usize,
+}
+
+impl YamlLimits<'_> {
+    /// Count a node nested one level below the current one
+    fn enter<E: serde::de::Error>(self) -> Result<Self, E> {
+        self.nodes.set(self.nodes.get() + 1);
+        if self.nodes.get() > self.max_nodes {
+            return Err(E::custom(format!(
+                "document expands to more than {} nodes (raise the limit with --yaml-max-nodes)",
+                self.max_nodes
+            )));
+        }
+        if self.depth >= self.max_depth {
+            return Err(E::custom(format!(
+                "document is nested deeper than {} levels (raise the limit with --yaml-max-depth)",
+                self.max_depth
+            )));
+        }
+        Ok(YamlLimits {
+            depth: self.depth + 1,
+            ..self
+        })
+    }
+}
+
+/// Wraps a deserializer (and the visitors, seeds and accessors it passes values through) to apply YamlLimits
+pub(crate) struct Limited<'a, T> {
+    pub(crate) inner: T,
+    pub(crate) limits: YamlLimits<'a>,
+}
+
+impl<'a, T> Limited<'a, T> {
+    fn wrap<U>(&self, inner: U) -> Limited<'a, U> {
+        Limited {
+            inner,
+            limits: self.limits,
+        }
+    }
+}
+
+impl<'de, D: serde::Deserializer<'de>> serde::Deserializer<'de> for Limited<'_, D> {
+    type Error = D::Error;
+
+    fn deserialize_any<V: serde::de::Visitor<'de>>(self, visitor: V) -> Result<V::Value, D::Error> {
+        let limits = self.limits.enter::<D::Error>()?;
+        self.inner.deserialize_any(Limited {
+            inner: visitor,
+            limits,
+        })
+    }
+
+    serde::forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string bytes byte_buf option
+        unit unit_struct newtype_struct seq tuple tuple_struct map struct enum identifier ignored_any
+    }
+}
+
+impl<'de, S: serde::de::DeserializeSeed<'de>> serde::de::DeserializeSeed<'de> for Limited<'_, S> {
+    type Value = S::Value;
+
+    fn deserialize<D: serde::Deserializer<'de>>(self, deserializer: D) -> Result<S::Value, D::Error> {
+        let deserializer = self.wrap(deserializer);
+        self.inner.deserialize(deserializer)
+    }
+}
+
+impl<'de, V: serde::de::Visitor<'de>> serde::de::Visitor<'de> for Limited<'_, V> {
+    type Value = V::Value;
+
+    fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        self.inner.expecting(f)
+    }
+
+    fn visit_bool<E: serde::de::Error>(self, v: bool) -> Result<V::Value, E> {
+        self.inner.visit_bool(v)
+    }
+
+    fn visit_i64<E: serde::de::Error>(self, v: i64) -> Result<V::Value, E> {
+        self.inner.visit_i64(v)
+    }
+
+    fn visit_i128<E: serde::de::Error>(self, v: i128) -> Result<V::Value, E> {
+        self.inner.visit_i128(v)
+    }
+
+    fn visit_u64<E: serde::de::Error>(self, v: u64) -> Result<V::Value, E> {
+        self.inner.visit_u64(v)
+    }
+
+    fn visit_u128<E: serde::de::Error>(self, v: u128) -> Result<V::Value, E> {
+        self.inner.visit_u128(v)
+    }
+
+    fn visit_f64<E: serde::de::Error>(self, v: f64) -> Result<V::Value, E> {
+        self.inner.visit_f64(v)
+    }
+
+    fn visit_str<E: serde::de::Error>(self, v: &str) -> Result<V::Value, E> {
+        self.inner.visit_str(v)
+    }
+
+    fn visit_borrowed_str<E: serde::de::Error>(self, v: &'de str) -> Result<V::Value, E> {
+        self.inner.visit_borrowed_str(v)
+    }
+
+    fn visit_string<E: serde::de::Error>(self, v: String) -> Result<V::Value, E> {
+        self.inner.visit_string(v)
+    }
+
+    fn visit_bytes<E: serde::de::Error>(self, v: &[u8]) -> Result<V::Value, E> {
+        self.inner.visit_bytes(v)
+    }
+
+    fn visit_unit<E: serde::de::Error>(self) -> Result<V::Value, E> {
+        self.inner.visit_unit()
+    }
+
+    fn visit_none<E: serde::de::Error>(self) -> Result<V::Value, E> {
+        self.inner.visit_none()
+    }
+
+    fn visit_some<D: serde::Deserializer<'de>>(self, deserializer: D) -> Result<V::Value, D::Error> {
+        let deserializer = self.wrap(deserializer);
+        self.inner.visit_some(deserializer)
+    }
+
+    fn visit_newtype_struct<D: serde::Deserializer<'de>>(
+        self,
+        deserializer: D,
+    ) -> Result<V::Value, D::Error> {
+        let deserializer = self.wrap(deserializer);
+        self.inner.visit_newtype_struct(deserializer)
+    }
+
+    fn visit_seq<A: serde::de::SeqAccess<'de>>(self, seq: A) -> Result<V::Value, A::Error> {
+        let seq = self.wrap(seq);
+        self.inner.visit_seq(seq)
+    }
+
+    fn visit_map<A: serde::de::MapAccess<'de>>(self, map: A) -> Result<V::Value, A::Error> {
+        let map = self.wrap(map);
+        self.inner.visit_map(map)
+    }
+
+    fn visit_enum<A: serde::de::EnumAccess<'de>>(self, data: A) -> Result<V::Value, A::Error> {
+        let data = self.wrap(data);
+        self.inner.visit_enum(data)
+    }
+}
+
+impl<'de, A: serde::de::SeqAccess<'de>> serde::de::SeqAccess<'de> for Limited<'_, A> {
+    type Error = A::Error;
+
+    fn next_element_seed<T: serde::de::DeserializeSeed<'de>>(
+        &mut self,
+        seed: T,
+    ) -> Result<Option<T::Value>, A::Error> {
+        let seed = self.wrap(seed);
+        self.inner.next_element_seed(seed)
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        self.inner.size_hint()
+    }
+}
+
+impl<'de, A: serde::de::MapAccess<'de>> serde::de::MapAccess<'de> for Limited<'_, A> {
+    type Error = A::Error;
+
+    fn next_key_seed<K: serde::de::DeserializeSeed<'de>>(
+        &mut self,
+        seed: K,
+    ) -> Result<Option<K::Value>, A::Error> {
+        let seed = self.wrap(seed);
+        self.inner.next_key_seed(seed)
+    }
+
+    fn next_value_seed<T: serde::de::DeserializeSeed<'de>>(&mut self, seed: T) -> Result<T::Value, A::Error> {
+        let seed = self.wrap(seed);
+        self.inner.next_value_seed(seed)
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        self.inner.size_hint()
+    }
+}
+
+impl<'a, 'de, A: serde::de::EnumAccess<'de>> serde::de::EnumAccess<'de> for Limited<'a, A> {
+    type Error = A::Error;
+    type Variant = Limited<'a, A::Variant>;
+
+    fn variant_seed<T: serde::de::DeserializeSeed<'de>>(
+        self,
+        seed: T,
+    ) -> Result<(T::Value, Self::Variant), A::Error> {
+        let seed = self.wrap(seed);
+        let (value, variant) = self.inner.variant_seed(seed)?;
+        Ok((
+            value,
+            Limited {
+                inner: variant,
+                limits: self.limits,
+            },
+        ))
+    }
+}
+
+impl<'de, A: serde::de::VariantAccess<'de>> serde::de::VariantAccess<'de> for Limited<'_, A> {
+    type Error = A::Error;
+
+    fn unit_variant(self) -> Result<(), A::Error> {
+        self.inner.unit_variant()
+    }
+
+    fn newtype_variant_seed<T: serde::de::DeserializeSeed<'de>>(self, seed: T) -> Result<T::Value, A::Error> {
+        let seed = self.wrap(seed);
+        self.inner.newtype_variant_seed(seed)
+    }
+
+    fn tuple_variant<V: serde::de::Visitor<'de>>(self, len: usize, visitor: V) -> Result<V::Value, A::Error> {
+        let visitor = self.wrap(visitor);
+        self.inner.tuple_variant(len, visitor)
+    }
+
+    fn struct_variant<V: serde::de::Visitor<'de>>(
+        self,
+        fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, A::Error> {
+        let visitor = self.wrap(visitor);
+        self.inner.struct_variant(fields, visitor)
+    }
+}
+
+/// Whether yaml input has no documents, only blank lines, comments and document markers
+pub(crate) fn yaml_is_blank(data: &[u8]) -> bool {
+    String::from_utf8_lossy(data).lines().all(|line| {
+        let line = line.trim();
+        line.is_empty() || line.starts_with('#') || line == "---" || line == "..."
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn yaml_json_conversion() {
+        let yaml: serde_yaml::Value =
+            serde_yaml::from_str("a: !Foo {b: 1}\n1: .nan\n~: [-2, 2.5, true]").unwrap();
+        let expected = serde_json::json!({"a": {"Foo": {"b": 1}}, "1": null, "null": [-2, 2.5, true]});
+        assert_eq!(yaml_to_json(yaml).unwrap(), expected);
+        let yaml: serde_yaml::Value = serde_yaml::from_str("[1, 2]: x").unwrap();
+        assert!(yaml_to_json(yaml).is_err());
+    }
+
+    #[test]
+    fn flow_style_yaml() {
+        let doc = serde_json::json!({"a": {"b": [1, "x, y"], "c": {}}, "d": [{"e": "1", "f": null}, []]});
+        assert_eq!(
+            flow_yaml(&doc, 0).unwrap(),
+            "{a: {b: [1, \"x, y\"], c: {}}, d: [{e: '1', f: null}, []]}\n"
+        );
+        assert_eq!(
+            flow_yaml(&doc, 1).unwrap(),
+            "a: {b: [1, \"x, y\"], c: {}}\nd: [{e: '1', f: null}, []]\n"
+        );
+        assert_eq!(
+            flow_yaml(&doc, 2).unwrap(),
+            "a:\n  b: [1, \"x, y\"]\n  c: {}\nd:\n- {e: '1', f: null}\n- []\n"
+        );
+        assert_eq!(
+            flow_yaml(&doc, 9).unwrap(),
+            "a:\n  b:\n  - 1\n  - x, y\n  c: {}\nd:\n- e: '1'\n  f: null\n- []\n"
+        );
+        assert_eq!(flow_yaml(&serde_json::json!("a\nb"), 0).unwrap(), "\"a\\nb\"\n");
+    }
+
+    #[test]
+    fn yaml_chunks() -> Result<()> {
+        let input = "# head\n---\na: 1\n--- |\n  ---\n---\n---x: 2\n";
+        let mut chunks = vec![];
+        read_yaml_chunks(input.as_bytes(), u64::MAX, |chunk, first_line| {
+            chunks.push((String::from_utf8(chunk.to_vec())?, first_line));
+            Ok(true)
+        })?;
+        assert_eq!(
+            chunks,
+            [
+                ("# head\n---\na: 1\n".to_string(), 0),
+                ("--- |\n  ---\n".to_string(), 3),
+                ("---\n---x: 2\n".to_string(), 5)
+            ]
+        );
+        assert!(read_yaml_chunks(input.as_bytes(), 12, |_, _| Ok(true)).is_err());
+        Ok(())
+    }
+}
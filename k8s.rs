@@ -0,0 +1,814 @@
+//! Kubernetes manifests: schema validation, metadata edits, ordering and container images
+use crate::formats::{csv_table, json_to_kdl, json_to_toml, json_to_xml};
+use crate::{
+    http_get, push_index, push_key, still_open, unwrap_list, write_atomic, Args, Input, KeyValue, Output,
+};
+use anyhow::Result;
+use std::io::{BufReader, Write};
+use std::path::PathBuf;
+use tracing::*;
+
+fn default_kubernetes_schema_dir() -> Result<PathBuf> {
+    let cache = match std::env::var_os("XDG_CACHE_HOME") {
+        Some(dir) => PathBuf::from(dir),
+        None => match std::env::var_os("HOME") {
+            Some(home) => PathBuf::from(home).join(".cache"),
+            None => anyhow::bail!("no cache directory for kubernetes schemas, set --kubernetes-schemas"),
+        },
+    };
+    Ok(cache.join("lq").join("kubernetes-json-schema"))
+}
+
+/// Schema file name for a kind in the kubeconform layout, e.g. deployment-apps-v1.json
+fn kubernetes_schema_file(api_version: &str, kind: &str) -> String {
+    let kind = kind.to_lowercase();
+    match api_version.split_once('/') {
+        Some((group, version)) => {
+            let group = group.split('.').next().unwrap_or(group);
+            format!("{kind}-{group}-{version}.json")
+        }
+        None => format!("{kind}-{api_version}.json"),
+    }
+}
+
+/// Load a standalone schema from the schema dir, downloading it first if missing
+///
+/// None when there is no upstream schema for the file (e.g. for custom resources).
+fn load_kubernetes_schema(
+    dir: &std::path::Path,
+    version: &str,
+    strict: bool,
+    file: &str,
+) -> Result<Option<serde_json::Value>> {
+    // the file name comes from the documents, so keep it within the schema dir
+    if file.contains(['/', '\\']) || file.starts_with('.') {
+        anyhow::bail!("invalid kubernetes schema name {file:?}");
+    }
+    let subdir = format!("{version}-standalone{}", if strict { "-strict" } else { "" });
+    let path = dir.join(&subdir).join(file);
+    if !path.exists() {
+        let url =
+            format!("https://raw.githubusercontent.com/yannh/kubernetes-json-schema/master/{subdir}/{file}");
+        debug!("downloading {url} to {}", path.display());
+        let Some(body) = http_get(&url, &[])? else {
+            return Ok(None);
+        };
+        let schema = serde_json::from_slice(&body)
+            .map_err(|e| anyhow::anyhow!("invalid kubernetes schema at {url}: {e}"))?;
+        // written whole, so an interrupted download cannot leave a broken schema in the cache
+        std::fs::create_dir_all(dir.join(&subdir))?;
+        write_atomic(&path, &body)?;
+        return Ok(Some(schema));
+    }
+    let schema = serde_json::from_reader(BufReader::new(std::fs::File::open(&path)?))?;
+    Ok(Some(schema))
+}
+
+/// Kinds of Kubernetes resources that are not namespaced
+const CLUSTER_SCOPED_KINDS: &[&str] = &[
+    "Namespace",
+    "Node",
+    "PersistentVolume",
+    "StorageClass",
+    "CSIDriver",
+    "CSINode",
+    "VolumeAttachment",
+    "CustomResourceDefinition",
+    "APIService",
+    "ClusterRole",
+    "ClusterRoleBinding",
+    "PriorityClass",
+    "RuntimeClass",
+    "IngressClass",
+    "PodSecurityPolicy",
+    "CertificateSigningRequest",
+    "MutatingWebhookConfiguration",
+    "ValidatingWebhookConfiguration",
+    "ValidatingAdmissionPolicy",
+    "ValidatingAdmissionPolicyBinding",
+    "FlowSchema",
+    "PriorityLevelConfiguration",
+];
+
+/// Set metadata.namespace on namespaced documents, recursing into the items of lists
+pub(crate) fn set_namespace(doc: &mut serde_json::Value, namespace: &str) {
+    use serde_json::Value;
+    let Some(kind) = doc["kind"].as_str() else {
+        return;
+    };
+    if kind.ends_with("List") {
+        if let Some(Value::Array(items)) = doc.get_mut("items") {
+            items.iter_mut().for_each(|item| set_namespace(item, namespace));
+        }
+    } else if !CLUSTER_SCOPED_KINDS.contains(&kind) {
+        if !doc["metadata"].is_object() {
+            doc["metadata"] = serde_json::json!({});
+        }
+        doc["metadata"]["namespace"] = namespace.into();
+    }
+}
+
+/// Add labels and annotations to Kubernetes documents, and optionally to their pod templates
+pub(crate) fn add_metadata(
+    doc: &mut serde_json::Value,
+    labels: &[KeyValue],
+    annotations: &[KeyValue],
+    pod_templates: bool,
+) {
+    use serde_json::Value;
+    fn insert(owner: &mut Value, field: &str, pairs: &[KeyValue]) {
+        if pairs.is_empty() {
+            return;
+        }
+        if !owner["metadata"].is_object() {
+            owner["metadata"] = serde_json::json!({});
+        }
+        let metadata = &mut owner["metadata"];
+        if !metadata[field].is_object() {
+            metadata[field] = serde_json::json!({});
+        }
+        for KeyValue { key, value } in pairs {
+            metadata[field][key] = value.as_str().into();
+        }
+    }
+    let Some(kind) = doc["kind"].as_str() else {
+        return;
+    };
+    let cronjob = kind == "CronJob";
+    if kind.ends_with("List") {
+        if let Some(Value::Array(items)) = doc.get_mut("items") {
+            for item in items {
+                add_metadata(item, labels, annotations, pod_templates);
+            }
+        }
+        return;
+    }
+    insert(doc, "labels", labels);
+    insert(doc, "annotations", annotations);
+    if pod_templates {
+        // workloads keep their pod template in spec.template, and cronjobs one level deeper
+        let mut spec = doc.get_mut("spec");
+        if cronjob {
+            spec = spec
+                .and_then(|s| s.get_mut("jobTemplate"))
+                .and_then(|t| t.get_mut("spec"));
+        }
+        if let Some(template) = spec.and_then(|s| s.get_mut("template")).filter(|t| t.is_object()) {
+            insert(template, "labels", labels);
+            insert(template, "annotations", annotations);
+        }
+    }
+}
+
+/// Metadata fields set by the Kubernetes api server
+const SERVER_METADATA: &[&str] = &[
+    "managedFields",
+    "creationTimestamp",
+    "deletionTimestamp",
+    "deletionGracePeriodSeconds",
+    "resourceVersion",
+    "uid",
+    "generation",
+    "selfLink",
+];
+
+/// Annotations set by kubectl and controllers
+const SERVER_ANNOTATIONS: &[&str] = &[
+    "kubectl.kubernetes.io/last-applied-configuration",
+    "deployment.kubernetes.io/revision",
+];
+
+/// Remove fields populated by the Kubernetes api server, recursing into the items of lists
+pub(crate) fn k8s_clean(doc: &mut serde_json::Value) {
+    use serde_json::Value;
+    let Value::Object(obj) = doc else {
+        return;
+    };
+    obj.remove("status");
+    if let Some(Value::Object(metadata)) = obj.get_mut("metadata") {
+        for field in SERVER_METADATA {
+            metadata.remove(*field);
+        }
+        if let Some(Value::Object(annotations)) = metadata.get_mut("annotations") {
+            for annotation in SERVER_ANNOTATIONS {
+                annotations.remove(*annotation);
+            }
+            if annotations.is_empty() {
+                metadata.remove("annotations");
+            }
+        }
+    }
+    let is_list = obj
+        .get("kind")
+        .and_then(Value::as_str)
+        .is_some_and(|k| k.ends_with("List"));
+    if let (true, Some(Value::Array(items))) = (is_list, obj.get_mut("items")) {
+        items.iter_mut().for_each(k8s_clean);
+    }
+}
+
+/// Kinds in the order they should be applied to a cluster (as helm installs them)
+const KIND_ORDER: &[&str] = &[
+    "Namespace",
+    "NetworkPolicy",
+    "ResourceQuota",
+    "LimitRange",
+    "PodSecurityPolicy",
+    "PodDisruptionBudget",
+    "ServiceAccount",
+    "Secret",
+    "SecretList",
+    "ConfigMap",
+    "StorageClass",
+    "PersistentVolume",
+    "PersistentVolumeClaim",
+    "CustomResourceDefinition",
+    "ClusterRole",
+    "ClusterRoleList",
+    "ClusterRoleBinding",
+    "ClusterRoleBindingList",
+    "Role",
+    "RoleList",
+    "RoleBinding",
+    "RoleBindingList",
+    "Service",
+    "DaemonSet",
+    "Pod",
+    "ReplicationController",
+    "ReplicaSet",
+    "Deployment",
+    "HorizontalPodAutoscaler",
+    "StatefulSet",
+    "Job",
+    "CronJob",
+    "IngressClass",
+    "Ingress",
+    "APIService",
+];
+
+/// The --split expression for --split-k8s, writing to DIR/namespace/kind_name.ext
+pub(crate) fn k8s_split_expr(dir: &str, output: Output) -> String {
+    let extension = match output {
+        Output::Jq | Output::Json => "json",
+        Output::Yaml => "yaml",
+        Output::Toml => "toml",
+        Output::Html => "html",
+        Output::Csv => "csv",
+        Output::Xml => "xml",
+        Output::Kdl => "kdl",
+    };
+    let dir = serde_json::Value::from(format!("{}/", dir.trim_end_matches('/')));
+    format!(
+        "{dir} + (.metadata.namespace | if . then . + \"/\" else \"\" end) + \
+         (.kind | ascii_downcase) + \"_\" + .metadata.name + \".{extension}\""
+    )
+}
+
+/// Call visit with the image of every container, init container and ephemeral container in a document
+fn visit_images(value: &mut serde_json::Value, visit: &mut impl FnMut(&mut String)) {
+    use serde_json::Value;
+    match value {
+        Value::Object(obj) => {
+            for (key, val) in obj.iter_mut() {
+                match (key.as_str(), val) {
+                    ("containers" | "initContainers" | "ephemeralContainers", Value::Array(containers)) => {
+                        for container in containers {
+                            if let Some(Value::String(image)) = container.get_mut("image") {
+                                visit(image);
+                            }
+                        }
+                    }
+                    (_, val) => visit_images(val, visit),
+                }
+            }
+        }
+        Value::Array(items) => {
+            for item in items {
+                visit_images(item, visit);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// The repository of an image reference, without its tag or digest
+fn image_repository(image: &str) -> &str {
+    let image = image.split_once('@').map_or(image, |(repository, _)| repository);
+    match image.rsplit_once(':') {
+        // a colon before the last slash is a registry port rather than a tag
+        Some((repository, tag)) if !tag.contains('/') => repository,
+        _ => image,
+    }
+}
+
+/// Position of a document's kind in the apply order, with unknown kinds (like custom resources) last
+pub(crate) fn kind_order(doc: &serde_json::Value) -> usize {
+    let kind = doc["kind"].as_str().unwrap_or_default();
+    KIND_ORDER
+        .iter()
+        .position(|k| *k == kind)
+        .unwrap_or(KIND_ORDER.len())
+}
+
+pub(crate) fn json_type_name(value: &serde_json::Value) -> &'static str {
+    use serde_json::Value;
+    match value {
+        Value::Null => "null",
+        Value::Bool(_) => "boolean",
+        Value::Number(n) if n.is_f64() => "number",
+        Value::Number(_) => "integer",
+        Value::String(_) => "string",
+        Value::Array(_) => "array",
+        Value::Object(_) => "object",
+    }
+}
+
+fn json_type_matches(ty: &str, value: &serde_json::Value) -> bool {
+    match (ty, json_type_name(value)) {
+        ("number", "integer") => true,
+        ("integer", "number") => value.as_f64().is_some_and(|f| f.fract() == 0.0),
+        (ty, actual) => ty == actual,
+    }
+}
+
+/// Collect the openAPIV3Schema of every version of a CustomResourceDefinition by (apiVersion, kind)
+///
+/// Supports apiextensions.k8s.io/v1 per version schemas, and the top level validation of v1beta1.
+fn crd_schemas(
+    crd: &serde_json::Value,
+    schemas: &mut std::collections::HashMap<(String, String), serde_json::Value>,
+) {
+    let spec = &crd["spec"];
+    let (Some("CustomResourceDefinition"), Some(group), Some(kind)) = (
+        crd["kind"].as_str(),
+        spec["group"].as_str(),
+        spec["names"]["kind"].as_str(),
+    ) else {
+        return;
+    };
+    let shared = &spec["validation"]["openAPIV3Schema"];
+    let versions = spec["versions"].as_array().cloned().unwrap_or_default();
+    let legacy = spec["version"].as_str().map(|v| serde_json::json!({ "name": v }));
+    for version in versions.iter().chain(legacy.as_ref()) {
+        let schema = match &version["schema"]["openAPIV3Schema"] {
+            serde_json::Value::Null => shared,
+            schema => schema,
+        };
+        if let (Some(name), false) = (version["name"].as_str(), schema.is_null()) {
+            schemas.insert((format!("{group}/{name}"), kind.to_string()), schema.clone());
+        }
+    }
+}
+
+/// Validate a value against the subset of JSON schema used by Kubernetes OpenAPI schemas
+///
+/// Violations are collected into `errors` prefixed by the jq style path they were found at.
+fn validate_schema(
+    schema: &serde_json::Value,
+    value: &serde_json::Value,
+    path: &str,
+    errors: &mut Vec<String>,
+) {
+    use serde_json::Value;
+    // boolean schemas (and anything else we do not understand) accept everything
+    let Some(schema) = schema.as_object() else {
+        return;
+    };
+    let at = if path.is_empty() { "." } else { path };
+    if value.is_null() && schema.get("nullable") == Some(&Value::Bool(true)) {
+        return;
+    }
+    if schema.get("x-kubernetes-int-or-string") == Some(&Value::Bool(true)) {
+        if !value.is_string() && !json_type_matches("integer", value) {
+            errors.push(format!(
+                "{at}: expected integer or string, found {}",
+                json_type_name(value)
+            ));
+        }
+        return;
+    }
+    let types: Vec<&str> = match schema.get("type") {
+        Some(Value::String(ty)) => vec![ty],
+        Some(Value::Array(tys)) => tys.iter().filter_map(Value::as_str).collect(),
+        _ => vec![],
+    };
+    if !types.is_empty() && !types.iter().any(|ty| json_type_matches(ty, value)) {
+        let expected = types.join(" or ");
+        errors.push(format!(
+            "{at}: expected {expected}, found {}",
+            json_type_name(value)
+        ));
+        return;
+    }
+    if let Some(Value::Array(allowed)) = schema.get("enum") {
+        if !allowed.contains(value) {
+            errors.push(format!(
+                "{at}: {value} is not one of {}",
+                Value::from(allowed.clone())
+            ));
+        }
+    }
+    if let Some(Value::Array(all)) = schema.get("allOf") {
+        for sub in all {
+            validate_schema(sub, value, path, errors);
+        }
+    }
+    for (keyword, accept) in [("anyOf", 1..usize::MAX), ("oneOf", 1..2)] {
+        if let Some(Value::Array(options)) = schema.get(keyword) {
+            let passing = options
+                .iter()
+                .filter(|sub| {
+                    let mut errs = vec![];
+                    validate_schema(sub, value, path, &mut errs);
+                    errs.is_empty()
+                })
+                .count();
+            if !accept.contains(&passing) {
+                errors.push(format!("{at}: matched {passing} schemas in {keyword}"));
+            }
+        }
+    }
+    match value {
+        Value::Object(map) => {
+            if let Some(Value::Array(required)) = schema.get("required") {
+                for key in required.iter().filter_map(Value::as_str) {
+                    if !map.contains_key(key) {
+                        errors.push(format!("{at}: missing required field {key}"));
+                    }
+                }
+            }
+            let properties = schema.get("properties").and_then(Value::as_object);
+            for (key, val) in map {
+                let child = push_key(path, key);
+                match (
+                    properties.and_then(|p| p.get(key)),
+                    schema.get("additionalProperties"),
+                ) {
+                    (Some(sub), _) => validate_schema(sub, val, &child, errors),
+                    (None, Some(Value::Bool(false))) => errors.push(format!("{child}: unknown field")),
+                    (None, Some(sub)) => validate_schema(sub, val, &child, errors),
+                    (None, None) => {}
+                }
+            }
+        }
+        Value::Array(xs) => {
+            if let Some(items) = schema.get("items") {
+                for (i, x) in xs.iter().enumerate() {
+                    validate_schema(items, x, &push_index(path, i), errors);
+                }
+            }
+        }
+        Value::Number(n) => {
+            let n = n.as_f64().unwrap_or_default();
+            if let Some(min) = schema.get("minimum").and_then(Value::as_f64) {
+                if n < min {
+                    errors.push(format!("{at}: {n} is less than minimum {min}"));
+                }
+            }
+            if let Some(max) = schema.get("maximum").and_then(Value::as_f64) {
+                if n > max {
+                    errors.push(format!("{at}: {n} is greater than maximum {max}"));
+                }
+            }
+        }
+        _ => {}
+    }
+}
+
+impl Args {
+    /// List the container images in files, or rewrite those of the repositories in `set`
+    pub(crate) fn k8s_images(&self, files: &[PathBuf], set: &[String], in_place: bool) -> Result<()> {
+        let mut replacements = vec![];
+        for image in set {
+            let repository = image_repository(image);
+            if repository == image {
+                anyhow::bail!("expected an image with a tag or digest for --set, found {image}");
+            }
+            replacements.push((repository, image.as_str()));
+        }
+        let mut listed: Vec<String> = vec![];
+        for file in files {
+            let mut args = self.file_args(file)?;
+            let mut docs = match args.input {
+                Input::Yaml => args.read_yaml_docs()?,
+                Input::Json => vec![args.read_json()?],
+                Input::Toml => vec![args.read_toml()?],
+                Input::Xlsx => vec![args.read_workbook()?],
+                Input::Csv => vec![args.read_csv()?],
+                Input::Xml => vec![args.read_xml()?],
+                Input::Edn => args.read_edn_docs()?,
+                Input::Kdl => vec![args.read_kdl()?],
+            };
+            for doc in &mut docs {
+                visit_images(doc, &mut |image: &mut String| {
+                    let repository = image_repository(image);
+                    match replacements.iter().find(|(repo, _)| *repo == repository) {
+                        Some((_, replacement)) => *image = replacement.to_string(),
+                        None if set.is_empty() && !listed.contains(image) => listed.push(image.clone()),
+                        None => {}
+                    }
+                });
+            }
+            if set.is_empty() {
+                continue;
+            }
+            let rewritten = match args.input {
+                Input::Yaml => docs
+                    .iter()
+                    .map(serde_yaml::to_string)
+                    .collect::<Result<Vec<_>, _>>()?
+                    .join("---\n"),
+                Input::Json => serde_json::to_string_pretty(&docs[0])? + "\n",
+                Input::Toml => json_to_toml(&docs[0])?,
+                Input::Xlsx => anyhow::bail!("cannot write spreadsheets ({})", file.display()),
+                Input::Csv => {
+                    let rows = docs[0].as_array().cloned().unwrap_or_default();
+                    csv_table(rows, args.delimiter, args.quote_char, !args.no_headers)?
+                }
+                Input::Xml => json_to_xml(&docs[0], &args.xml_style())?,
+                Input::Edn => anyhow::bail!("cannot write edn ({})", file.display()),
+                Input::Kdl => json_to_kdl(&docs[0])?,
+            };
+            if in_place {
+                debug!("Writing {}", file.display());
+                write_atomic(file, rewritten.as_bytes())?;
+            } else {
+                still_open(write!(std::io::stdout(), "{rewritten}"))?;
+            }
+        }
+        for image in listed {
+            still_open(writeln!(std::io::stdout(), "{image}"))?;
+        }
+        Ok(())
+    }
+
+    /// Validate documents against Kubernetes schemas when --kubernetes-validate or --crd is set
+    ///
+    /// Custom resources covered by a --crd are only checked against it, kinds without an upstream
+    /// schema are skipped with a warning, and documents without apiVersion and kind are left alone.
+    pub(crate) fn validate_kubernetes(&self, docs: &[serde_json::Value]) -> Result<()> {
+        let crds = self.crd_schemas()?;
+        self.validate_custom_resources(docs, &crds)?;
+        let Some(version) = &self.kubernetes_validate else {
+            return Ok(());
+        };
+        let dir = match &self.kubernetes_schemas {
+            Some(dir) => dir.clone(),
+            None => default_kubernetes_schema_dir()?,
+        };
+        let mut schemas = std::collections::HashMap::new();
+        let mut invalid = 0;
+        for (i, doc) in docs.iter().enumerate() {
+            let (Some(api_version), Some(kind)) = (doc["apiVersion"].as_str(), doc["kind"].as_str()) else {
+                debug!("doc {i}: no apiVersion or kind to validate");
+                continue;
+            };
+            if crds.contains_key(&(api_version.to_string(), kind.to_string())) {
+                continue;
+            }
+            let name = doc["metadata"]["name"].as_str().unwrap_or_default();
+            let file = kubernetes_schema_file(api_version, kind);
+            if !schemas.contains_key(&file) {
+                let schema = load_kubernetes_schema(&dir, version, self.strict, &file)?;
+                if schema.is_none() {
+                    warn!("no kubernetes {version} schema for {api_version} {kind}, skipping its validation");
+                }
+                schemas.insert(file.clone(), schema);
+            }
+            let Some(schema) = &schemas[&file] else {
+                continue;
+            };
+            let mut errors = vec![];
+            validate_schema(schema, doc, "", &mut errors);
+            for err in &errors {
+                eprintln!("doc {i} ({kind}/{name}): {err}");
+            }
+            if !errors.is_empty() {
+                invalid += 1;
+            }
+        }
+        if invalid > 0 {
+            anyhow::bail!(
+                "{invalid} of {} documents failed kubernetes validation",
+                docs.len()
+            );
+        }
+        debug!("validated {} documents against kubernetes {version}", docs.len());
+        Ok(())
+    }
+
+    /// The schemas of the --crd files by (apiVersion, kind)
+    fn crd_schemas(&self) -> Result<std::collections::HashMap<(String, String), serde_json::Value>> {
+        let mut schemas = std::collections::HashMap::new();
+        for path in &self.crd {
+            // read with default options, as document selection and validation is for the main input
+            let crds = Args::default().file_args(path)?.read_input_multidoc()?;
+            let found = schemas.len();
+            for crd in crds.into_iter().flat_map(unwrap_list) {
+                crd_schemas(&crd, &mut schemas);
+            }
+            if schemas.len() == found {
+                anyhow::bail!("no CustomResourceDefinition schemas found in {}", path.display());
+            }
+        }
+        Ok(schemas)
+    }
+
+    /// Validate custom resources against the schemas of the --crd files
+    fn validate_custom_resources(
+        &self,
+        docs: &[serde_json::Value],
+        schemas: &std::collections::HashMap<(String, String), serde_json::Value>,
+    ) -> Result<()> {
+        if schemas.is_empty() {
+            return Ok(());
+        }
+        let mut invalid = 0;
+        for (i, doc) in docs.iter().enumerate() {
+            let (Some(api_version), Some(kind)) = (doc["apiVersion"].as_str(), doc["kind"].as_str()) else {
+                continue;
+            };
+            let Some(schema) = schemas.get(&(api_version.to_string(), kind.to_string())) else {
+                continue;
+            };
+            let name = doc["metadata"]["name"].as_str().unwrap_or_default();
+            let mut errors = vec![];
+            validate_schema(schema, doc, "", &mut errors);
+            for err in &errors {
+                eprintln!("doc {i} ({kind}/{name}): {err}");
+            }
+            if !errors.is_empty() {
+                invalid += 1;
+            }
+        }
+        if invalid > 0 {
+            anyhow::bail!("{invalid} of {} documents failed crd validation", docs.len());
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::parse_kubernetes_version;
+
+    #[test]
+    fn kubernetes_schema_validation() {
+        assert_eq!(
+            kubernetes_schema_file("apps/v1", "Deployment"),
+            "deployment-apps-v1.json"
+        );
+        assert_eq!(
+            kubernetes_schema_file("rbac.authorization.k8s.io/v1", "ClusterRole"),
+            "clusterrole-rbac-v1.json"
+        );
+        assert_eq!(kubernetes_schema_file("v1", "Service"), "service-v1.json");
+        assert!(parse_kubernetes_version("v1.29.0").is_ok() && parse_kubernetes_version("1.29").is_ok());
+        assert!(parse_kubernetes_version("../../x").is_err());
+        assert!(load_kubernetes_schema("/nonexistent".as_ref(), "master", false, "../x-v1.json").is_err());
+
+        let schema = serde_json::json!({
+            "type": "object",
+            "required": ["kind"],
+            "additionalProperties": false,
+            "properties": {
+                "kind": {"type": "string"},
+                "ports": {"type": "array", "items": {"x-kubernetes-int-or-string": true}},
+            }
+        });
+        let mut errors = vec![];
+        let doc = serde_json::json!({"kind": "Service", "ports": [80, "http"]});
+        validate_schema(&schema, &doc, "", &mut errors);
+        assert!(errors.is_empty());
+        let doc = serde_json::json!({"ports": [true], "app.kubernetes.io/name": "x"});
+        validate_schema(&schema, &doc, "", &mut errors);
+        assert_eq!(
+            errors,
+            vec![
+                ".: missing required field kind",
+                ".ports[0]: expected integer or string, found boolean",
+                ".[\"app.kubernetes.io/name\"]: unknown field",
+            ]
+        );
+    }
+
+    #[test]
+    fn crd_schema_lookup() {
+        let crd = serde_json::json!({
+            "kind": "CustomResourceDefinition",
+            "spec": {
+                "group": "example.com",
+                "names": {"kind": "Widget"},
+                "versions": [
+                    {"name": "v1", "schema": {"openAPIV3Schema": {"type": "object"}}},
+                    {"name": "v1alpha1"},
+                ],
+                "validation": {"openAPIV3Schema": {"type": "array"}},
+            }
+        });
+        let mut schemas = std::collections::HashMap::new();
+        crd_schemas(&crd, &mut schemas);
+        let key = |version: &str| (format!("example.com/{version}"), "Widget".to_string());
+        assert_eq!(schemas[&key("v1")]["type"], "object");
+        assert_eq!(schemas[&key("v1alpha1")]["type"], "array");
+        assert_eq!(schemas.len(), 2);
+    }
+
+    #[test]
+    fn kubernetes_cleaning() {
+        let mut doc = serde_json::json!({
+            "kind": "List",
+            "items": [{
+                "kind": "Deployment",
+                "metadata": {
+                    "name": "app",
+                    "uid": "1",
+                    "managedFields": [],
+                    "annotations": {"kubectl.kubernetes.io/last-applied-configuration": "{}"},
+                },
+                "spec": {"replicas": 1},
+                "status": {"replicas": 1},
+            }],
+        });
+        k8s_clean(&mut doc);
+        let expected = serde_json::json!({
+            "kind": "List",
+            "items": [{"kind": "Deployment", "metadata": {"name": "app"}, "spec": {"replicas": 1}}],
+        });
+        assert_eq!(doc, expected);
+    }
+
+    #[test]
+    fn container_images() {
+        assert_eq!(image_repository("nginx:1.25"), "nginx");
+        assert_eq!(
+            image_repository("registry:5000/team/app"),
+            "registry:5000/team/app"
+        );
+        assert_eq!(
+            image_repository("registry:5000/app:v1@sha256:abc"),
+            "registry:5000/app"
+        );
+        let mut doc = serde_json::json!({
+            "kind": "CronJob",
+            "spec": {"jobTemplate": {"spec": {"template": {"spec": {
+                "initContainers": [{"name": "init", "image": "busybox:1"}],
+                "containers": [{"name": "app", "image": "app:1"}, {"name": "nameless"}],
+            }}}}},
+        });
+        let mut images = vec![];
+        visit_images(&mut doc, &mut |image: &mut String| {
+            images.push(image.clone());
+            image.push_str(".1");
+        });
+        assert_eq!(images, ["busybox:1", "app:1"]);
+        assert_eq!(
+            doc["spec"]["jobTemplate"]["spec"]["template"]["spec"]["containers"][0]["image"],
+            "app:1.1"
+        );
+    }
+
+    #[test]
+    fn namespace_setting() {
+        let mut doc = serde_json::json!({
+            "kind": "List",
+            "items": [
+                {"kind": "Deployment", "metadata": {"name": "app", "namespace": "default"}},
+                {"kind": "ConfigMap"},
+                {"kind": "ClusterRole", "metadata": {"name": "app"}},
+            ],
+        });
+        set_namespace(&mut doc, "staging");
+        assert_eq!(doc["items"][0]["metadata"]["namespace"], "staging");
+        assert_eq!(
+            doc["items"][1]["metadata"],
+            serde_json::json!({"namespace": "staging"})
+        );
+        assert!(doc["items"][2]["metadata"].get("namespace").is_none());
+        assert!(doc.get("metadata").is_none());
+    }
+
+    #[test]
+    fn metadata_adding() {
+        let mut doc = serde_json::json!({
+            "kind": "CronJob",
+            "metadata": {"name": "backup", "labels": {"app": "backup"}},
+            "spec": {"jobTemplate": {"spec": {"template": {"spec": {}}}}},
+        });
+        let labels = vec!["team=infra".parse().unwrap()];
+        let annotations = vec!["note=".parse().unwrap()];
+        add_metadata(&mut doc, &labels, &annotations, true);
+        assert_eq!(
+            doc["metadata"]["labels"],
+            serde_json::json!({"app": "backup", "team": "infra"})
+        );
+        assert_eq!(doc["metadata"]["annotations"], serde_json::json!({"note": ""}));
+        let template = &doc["spec"]["jobTemplate"]["spec"]["template"];
+        assert_eq!(
+            template["metadata"]["labels"],
+            serde_json::json!({"team": "infra"})
+        );
+        assert!("=infra".parse::<KeyValue>().is_err());
+    }
+}
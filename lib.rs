@@ -0,0 +1,5720 @@
+//! A lightweight jq wrapper for yaml, toml, and json documents
+//!
+//! The `lq` binary is a thin wrapper around [`Args::run`]. To embed the pipeline, use
+//! [`query`] or parse an [`Args`] and call [`Args::eval`] on in-memory input, or build a
+//! [`Pipeline`] to convert between readers and writers like the cli does:
+//!
+//! ```no_run
+//! let names = lq::query(lq::Input::Yaml, b"metadata: {name: app}", ".metadata.name")?;
+//! assert_eq!(names, vec![serde_json::json!("app")]);
+//! # Ok::<(), anyhow::Error>(())
+//! ```
+//!
+//! NB: evaluation shells out to `jq`, which must be on the `PATH`, unless given an
+//! [`engine`]. wasm targets cannot spawn jq, and evaluate queries with [`engine::Jaq`] instead.
+use anyhow::Result;
+use clap::{Parser, Subcommand, ValueEnum, ValueHint};
+use serde_yaml::{self, Deserializer};
+use std::io::{stdin, BufRead, BufReader, IsTerminal, Read, Write};
+use std::path::PathBuf;
+use tracing::*;
+
+#[cfg(feature = "archive")]
+mod archive;
+mod codec;
+pub mod convert;
+pub mod engine;
+mod formats;
+mod k8s;
+#[cfg(feature = "repl")]
+mod repl;
+#[cfg(feature = "serve")]
+mod serve;
+#[cfg(feature = "sops")]
+mod sops;
+
+#[cfg(feature = "archive")]
+use archive::Archive;
+use codec::Codec;
+use formats::{
+    check_strict_yaml, csv_table, edn_to_json, flow_yaml, html_table, json_to_kdl, json_to_styled_toml,
+    json_to_toml, json_to_xml, kdl_to_json, parse_csv, read_yaml_chunks, toml_error_location, toml_to_json,
+    workbook_to_json, xml_error_location, xml_to_json, yaml_error_location, yaml_is_blank, yaml_to_json,
+    Limited, StreamEvents, StrictJson, SyntaxError, XmlStyle, YamlLimits, STREAM_CLOSED,
+};
+use k8s::{add_metadata, json_type_name, k8s_clean, k8s_split_expr, kind_order, set_namespace};
+#[cfg(feature = "sops")]
+use sops::sops_rewrite;
+
+#[cfg(all(
+    target_family = "wasm",
+    any(
+        feature = "http",
+        feature = "sops",
+        feature = "watch",
+        feature = "repl",
+        feature = "serve"
+    )
+))]
+compile_error!(
+    "the http, sops, watch, repl and serve features need processes or threads, which wasm targets lack"
+);
+
+/// The ways lq can fail, returned by the library entry points
+///
+/// The `lq` binary maps these to its exit codes: 127 when jq is not found, jq's own exit code
+/// when jq fails, the status of a [`Error::Status`], 2 without input, and 100 for everything else.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum Error {
+    /// The input is not valid in its format, at a line and column (both from 1) of the named input
+    ///
+    /// Line and column are 0 (and the snippet empty) when the parser does not say where.
+    Parse {
+        format: Input,
+        /// The input file, or stdin
+        name: String,
+        line: usize,
+        column: usize,
+        message: String,
+        /// The offending line of the input, pointing at the column
+        snippet: String,
+    },
+    /// The jq executable could not be found
+    JqNotFound(PathBuf),
+    /// The jq executable could not be started for another reason
+    JqSpawn {
+        program: PathBuf,
+        source: std::io::Error,
+    },
+    /// jq exited unsuccessfully with the exit code (2 for usage errors, 3 for compile errors, 5 for runtime errors)
+    JqFailed(i32),
+    /// The results could not be converted to the output format
+    Serialization(anyhow::Error),
+    /// Reading the input or writing the output failed
+    Io(std::io::Error),
+    /// There was no input to read: no input file exists, and stdin is a terminal
+    ///
+    /// The `lq` binary prints its help and exits with status 2.
+    NoInput,
+    /// The run finished with an unsuccessful status and nothing more to report
+    ///
+    /// Like differences found by `lq equal`, or nothing found by `lq find`. The `lq` binary exits
+    /// with the status without a message.
+    Status(i32),
+    /// Any other failure, like invalid arguments or configuration
+    Other(anyhow::Error),
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Parse {
+                format,
+                name,
+                message,
+                snippet,
+                ..
+            } => {
+                let format = format!("{format:?}").to_lowercase();
+                write!(f, "invalid {format} in {name}: {message}")?;
+                match snippet.as_str() {
+                    "" => Ok(()),
+                    snippet => write!(f, "\n{snippet}"),
+                }
+            }
+            Error::JqNotFound(program) if program.components().count() > 1 => write!(
+                f,
+                "{} not found; check the --jq option or the jq key in the config file",
+                program.display()
+            ),
+            Error::JqNotFound(program) => write!(
+                f,
+                "{} not found on PATH; install it from https://jqlang.github.io/jq/download/ \
+                 (e.g. apt install jq, brew install jq) or point --jq at an executable",
+                program.display()
+            ),
+            Error::JqSpawn { program, .. } => write!(f, "failed to run {}", program.display()),
+            Error::JqFailed(code) => write!(f, "arguments rejected by jq: exit status: {code}"),
+            Error::Serialization(e) | Error::Other(e) => e.fmt(f),
+            Error::Io(e) => e.fmt(f),
+            Error::NoInput => f.write_str("no input file given, and stdin is a terminal"),
+            Error::Status(code) => write!(f, "finished with status {code}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::JqSpawn { source, .. } => Some(source),
+            Error::Serialization(e) | Error::Other(e) => e.chain().nth(1),
+            Error::Io(e) => e.source(),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(err: std::io::Error) -> Self {
+        Error::Io(err)
+    }
+}
+
+impl From<anyhow::Error> for Error {
+    /// Recover the kind of failure from an error raised inside lq
+    fn from(err: anyhow::Error) -> Self {
+        // only bare io errors, as the messages of io errors with context would be lost
+        if err.chain().next().is_some_and(|e| e.is::<std::io::Error>()) {
+            return err.downcast().map_or_else(Error::Other, Error::Io);
+        }
+        match err.downcast::<Error>() {
+            Ok(e) => e,
+            Err(err) => Error::Other(err),
+        }
+    }
+}
+
+#[derive(Copy, Debug, Default, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+pub enum Input {
+    #[default]
+    Yaml,
+    Json,
+    Toml,
+    Xlsx,
+    Csv,
+    Xml,
+    Edn,
+    Kdl,
+}
+
+impl Input {
+    /// The input format implied by invoking lq through a link with another name, like busybox
+    fn from_binary_name(argv0: &std::path::Path) -> Option<Self> {
+        match argv0.file_stem()?.to_str()? {
+            "tq" => Some(Input::Toml),
+            "jsonq" => Some(Input::Json),
+            "xq" => Some(Input::Xml),
+            _ => None,
+        }
+    }
+
+    /// Guess the input format from a file extension
+    fn from_path(path: &std::path::Path) -> Option<Self> {
+        match path.extension()?.to_str()? {
+            "yaml" | "yml" => Some(Input::Yaml),
+            "json" => Some(Input::Json),
+            "toml" => Some(Input::Toml),
+            "xlsx" | "xlsm" | "xls" | "ods" => Some(Input::Xlsx),
+            "csv" => Some(Input::Csv),
+            "xml" => Some(Input::Xml),
+            "edn" => Some(Input::Edn),
+            "kdl" => Some(Input::Kdl),
+            _ => None,
+        }
+    }
+}
+
+/// Document indices and inclusive ranges selected by --doc
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct DocSelection(Vec<(usize, Option<usize>)>);
+
+impl DocSelection {
+    fn contains(&self, i: usize) -> bool {
+        self.0
+            .iter()
+            .any(|(start, end)| i >= *start && end.map_or(true, |end| i <= end))
+    }
+}
+
+/// A query whose results are collected under a name in the output, given with --query
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct NamedQuery {
+    name: String,
+    query: String,
+}
+
+impl std::str::FromStr for NamedQuery {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once('=') {
+            Some((name, query)) if !name.is_empty() && !query.trim().is_empty() => Ok(NamedQuery {
+                name: name.into(),
+                query: query.into(),
+            }),
+            _ => Err(format!("expected NAME=QUERY, found {s:?}")),
+        }
+    }
+}
+
+/// A Kubernetes label or annotation given with --add-label or --add-annotation
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct KeyValue {
+    key: String,
+    value: String,
+}
+
+impl std::str::FromStr for KeyValue {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once('=') {
+            Some((key, value)) if !key.is_empty() => Ok(KeyValue {
+                key: key.into(),
+                value: value.into(),
+            }),
+            _ => Err(format!("expected KEY=VALUE, found {s:?}")),
+        }
+    }
+}
+
+/// A path given with --pick or --omit, like .spec.containers[].image or .metadata.labels.*
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct FieldPath(Vec<FieldStep>);
+
+/// A step in a --pick or --omit path
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum FieldStep {
+    /// An object key, or a glob of keys with * and ? wildcards
+    Key(String),
+    Index(usize),
+    /// Any key or index, from * or []
+    Any,
+}
+
+impl FieldStep {
+    fn matches_key(&self, key: &str) -> bool {
+        match self {
+            FieldStep::Key(k) if k.contains(['*', '?']) => glob_matches(k, key),
+            FieldStep::Key(k) => k == key,
+            FieldStep::Index(_) => false,
+            FieldStep::Any => true,
+        }
+    }
+
+    fn matches_index(&self, index: usize) -> bool {
+        match self {
+            FieldStep::Key(k) => k.parse::<usize>() == Ok(index),
+            FieldStep::Index(i) => *i == index,
+            FieldStep::Any => true,
+        }
+    }
+}
+
+impl std::str::FromStr for FieldPath {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (mut steps, mut key, mut chars) =
+            (vec![], String::new(), s.strip_prefix('.').unwrap_or(s).chars());
+        let step = |key: String| match key.as_str() {
+            "*" => FieldStep::Any,
+            _ => FieldStep::Key(key),
+        };
+        while let Some(c) = chars.next() {
+            match c {
+                '\\' => key.extend(chars.next()),
+                '.' | '[' => {
+                    if !key.is_empty() {
+                        steps.push(step(std::mem::take(&mut key)));
+                    }
+                    if c == '[' {
+                        let index: String = chars.by_ref().take_while(|c| *c != ']').collect();
+                        steps.push(match index.as_str() {
+                            "" | "*" => FieldStep::Any,
+                            i => FieldStep::Index(
+                                i.parse().map_err(|_| format!("invalid index [{i}] in {s:?}"))?,
+                            ),
+                        });
+                    }
+                }
+                _ => key.push(c),
+            }
+        }
+        if !key.is_empty() {
+            steps.push(step(key));
+        }
+        Ok(FieldPath(steps))
+    }
+}
+
+/// A jq variable set from an environment variable with --arg-env
+#[derive(Clone, PartialEq, Eq)]
+struct EnvArg {
+    name: String,
+    value: String,
+}
+
+impl std::str::FromStr for EnvArg {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, var) = s.split_once('=').unwrap_or((s, s));
+        if name.is_empty() || var.is_empty() {
+            return Err(format!("expected NAME or NAME=VAR, found {s:?}"));
+        }
+        match std::env::var(var) {
+            Ok(value) => Ok(EnvArg {
+                name: name.into(),
+                value,
+            }),
+            Err(e) => Err(format!("environment variable {var}: {e}")),
+        }
+    }
+}
+
+// values are usually secrets, so keep them out of debug logs
+impl std::fmt::Debug for EnvArg {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "EnvArg({})", self.name)
+    }
+}
+
+impl std::str::FromStr for DocSelection {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let index = |x: &str| {
+            x.trim()
+                .parse::<usize>()
+                .map_err(|e| format!("invalid index {x:?}: {e}"))
+        };
+        let mut ranges = vec![];
+        for part in s.split(',') {
+            let range = match part.split_once('-') {
+                Some((start, "")) => (index(start)?, None),
+                Some((start, end)) => {
+                    let (start, end) = (index(start)?, index(end)?);
+                    if start > end {
+                        return Err(format!("range {part:?} ends before it starts"));
+                    }
+                    (start, Some(end))
+                }
+                None => (index(part)?, Some(index(part)?)),
+            };
+            ranges.push(range);
+        }
+        Ok(DocSelection(ranges))
+    }
+}
+
+/// Side to take when both sides of a three-way merge changed a value differently
+#[derive(Copy, Debug, Clone, PartialEq, Eq, ValueEnum)]
+enum Prefer {
+    Ours,
+    Theirs,
+}
+
+/// What to do with input larger than --max-input-size
+#[derive(Copy, Debug, Default, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+enum LargeInput {
+    /// Fail without reading the input
+    #[default]
+    Error,
+    /// Evaluate the query on one document at a time (as with --each) without reading the input up front
+    Each,
+}
+
+/// Parse a size in bytes with an optional binary unit suffix (k, M, G, T)
+fn parse_size(s: &str) -> Result<u64, String> {
+    let digits = s.trim_end_matches(|c: char| c.is_ascii_alphabetic());
+    let scale = match s[digits.len()..].to_ascii_lowercase().trim_end_matches('b') {
+        "" => 1,
+        "k" | "ki" => 1 << 10,
+        "m" | "mi" => 1 << 20,
+        "g" | "gi" => 1 << 30,
+        "t" | "ti" => 1 << 40,
+        _ => return Err(format!("unknown size unit in {s:?} (expected k, M, G or T)")),
+    };
+    let n: u64 = digits
+        .parse()
+        .map_err(|_| format!("expected a size like 500M, found {s:?}"))?;
+    n.checked_mul(scale)
+        .ok_or_else(|| format!("size {s:?} is too large"))
+}
+
+/// Parse a --kubernetes-validate version like v1.29.0 (or master), as it becomes part of a path and url
+fn parse_kubernetes_version(s: &str) -> Result<String, String> {
+    let version = regex::Regex::new(r"^v?\d+\.\d+(\.\d+)?$").expect("valid regex");
+    if s == "master" || version.is_match(s) {
+        Ok(s.to_string())
+    } else {
+        Err(format!("expected a version like v1.29.0 or master, found {s:?}"))
+    }
+}
+
+/// Parse a single character csv delimiter or quote, accepting \t for tabs
+fn parse_csv_char(s: &str) -> Result<char, String> {
+    let mut chars = s.chars();
+    match (s, chars.next(), chars.next()) {
+        ("\\t" | "tab", _, _) => Ok('\t'),
+        (_, Some(c), None) if c != '\n' && c != '\r' => Ok(c),
+        _ => Err(format!("expected a single character, found {s:?}")),
+    }
+}
+
+/// Whether files written by lq end with a newline
+#[derive(Copy, Debug, Default, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+enum FinalNewline {
+    /// Always end files with a newline
+    Always,
+    /// Never end files with a newline
+    Never,
+    /// Keep what the file being overwritten had, ending new files with a newline
+    #[default]
+    Preserve,
+}
+
+/// How to read input that is not valid utf-8
+#[derive(Copy, Debug, Default, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+enum InvalidUtf8 {
+    /// Fail to parse the input
+    #[default]
+    Error,
+    /// Replace invalid bytes with the U+FFFD replacement character
+    Replace,
+    /// Read invalid bytes as latin-1 (ISO 8859-1) characters
+    Latin1,
+}
+
+/// What to query when the input contains no documents
+#[derive(Copy, Debug, Default, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+enum EmptyInput {
+    /// An empty object
+    #[default]
+    EmptyDoc,
+    /// null
+    Null,
+    /// Nothing; fail instead
+    Error,
+}
+
+#[derive(Copy, Debug, Default, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+enum MergeMode {
+    /// Recursively merge objects, later documents win on conflicts
+    #[default]
+    Deep,
+    /// Merge top level keys only, later documents win on conflicts
+    Shallow,
+}
+
+/// Naming convention for --rename-keys
+#[derive(Copy, Debug, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+enum KeyCase {
+    /// camelCase, as in Kubernetes manifests
+    Camel,
+    /// snake_case
+    Snake,
+    /// kebab-case
+    Kebab,
+    /// PascalCase
+    Pascal,
+}
+
+impl KeyCase {
+    /// Rewrite a key in this case, splitting words on _, -, spaces and case changes
+    fn convert(self, key: &str) -> String {
+        let chars: Vec<char> = key.chars().collect();
+        let mut words: Vec<String> = vec![];
+        let mut word = String::new();
+        for (i, &c) in chars.iter().enumerate() {
+            if matches!(c, '_' | '-' | ' ') {
+                if !word.is_empty() {
+                    words.push(std::mem::take(&mut word));
+                }
+                continue;
+            }
+            if c.is_uppercase() && !word.is_empty() {
+                let prev = chars[i - 1];
+                // the last capital of an acronym starts the next word: HTTPServer -> HTTP Server
+                let acronym_end = prev.is_uppercase() && chars.get(i + 1).is_some_and(|n| n.is_lowercase());
+                if prev.is_lowercase() || prev.is_numeric() || acronym_end {
+                    words.push(std::mem::take(&mut word));
+                }
+            }
+            word.push(c);
+        }
+        if !word.is_empty() {
+            words.push(word);
+        }
+        let capitalized = |w: &String| {
+            let lower = w.to_lowercase();
+            let mut chars = lower.chars();
+            chars
+                .next()
+                .map_or(String::new(), |c| c.to_uppercase().chain(chars).collect())
+        };
+        match self {
+            KeyCase::Snake => words
+                .iter()
+                .map(|w| w.to_lowercase())
+                .collect::<Vec<_>>()
+                .join("_"),
+            KeyCase::Kebab => words
+                .iter()
+                .map(|w| w.to_lowercase())
+                .collect::<Vec<_>>()
+                .join("-"),
+            KeyCase::Pascal => words.iter().map(capitalized).collect(),
+            KeyCase::Camel => words
+                .iter()
+                .enumerate()
+                .map(|(i, w)| if i == 0 { w.to_lowercase() } else { capitalized(w) })
+                .collect(),
+        }
+    }
+}
+
+/// Layout of arrays of objects in toml output
+#[derive(Copy, Debug, Default, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+enum TomlArrays {
+    /// As [[name]] array of tables sections
+    #[default]
+    Tables,
+    /// As name = [{ ... }, { ... }] inline arrays
+    Inline,
+}
+
+/// Values removed from the results by --prune
+#[derive(Copy, Debug, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+enum Prune {
+    /// Null values
+    Nulls,
+    /// Empty objects and arrays
+    Empty,
+    /// Null values, and objects and arrays that are empty (also after pruning)
+    Both,
+}
+
+#[derive(Copy, Debug, Default, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+enum PathsMode {
+    /// Only the paths
+    #[default]
+    Plain,
+    /// Paths as jq assignments of their values
+    Values,
+    /// Paths annotated with the type of their values
+    Types,
+}
+
+#[derive(Copy, Debug, Default, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+pub enum Output {
+    Yaml,
+    /// jq's output as is, which is not json with options like -r
+    #[default]
+    Jq,
+    /// jq's output parsed (failing when it is not json) and pretty printed with --indent, or compact with -c
+    Json,
+    Toml,
+    /// An html table of the objects in the results (or of the items of an array result)
+    Html,
+    /// Csv rows of the objects or arrays in the results (or of the items of an array result)
+    Csv,
+    /// An xml document for each result, which must be an object with a single key naming the root element
+    Xml,
+    /// A kdl document for each result, which must be an array of nodes (or a node) as read by --input=kdl
+    Kdl,
+}
+
+/// A lightweight and portable Rust implementation of a common jq wrapper
+///
+/// Allows doing arbitrary jq style queries editing on YAML documents.
+///
+/// yq '.[3].kind' < .github/dependabot.yaml
+///
+/// yq -y '.updates[0].schedule' .github/dependabot.yml
+///
+/// yq '.spec.template.spec.containers[].image' -r
+///
+/// yq '.[].kind' -r < manifest.yml
+///
+/// yq -y '.[2].metadata' < manifest.yml
+#[derive(Parser, Debug, Default, Clone)]
+#[command(author, version, about, disable_version_flag = true)]
+pub struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// Input format of the input file or stdin
+    ///
+    /// Subcommands taking files guess the format from the extension and fall back to this.
+    #[arg(long, value_enum, default_value_t, global = true)]
+    input: Input,
+    /// Output format to convert the jq output into
+    #[arg(long, value_enum, default_value_t)]
+    output: Output,
+
+    /// Write only the <table> element with --output=html, without the document and stylesheet around it
+    #[arg(long, default_value = "false")]
+    bare_html: bool,
+
+    /// Field separator of --input=csv and --output=csv (\t for tabs)
+    #[arg(long, value_name = "CHAR", default_value = ",", value_parser = parse_csv_char)]
+    delimiter: char,
+    /// Quote character of --input=csv and --output=csv
+    #[arg(long, value_name = "CHAR", default_value = "\"", value_parser = parse_csv_char)]
+    quote_char: char,
+    /// Csv has no header row: input rows become arrays, and output is written without one
+    #[arg(long, default_value = "false")]
+    no_headers: bool,
+    /// Keep every --input=csv cell a string rather than converting numbers and booleans
+    #[arg(long, default_value = "false")]
+    no_type_inference: bool,
+
+    /// Prefix of the keys holding xml attributes in --input=xml and --output=xml (e.g. _ for xml2json)
+    #[arg(long, value_name = "PREFIX", default_value = "@")]
+    xml_attribute_prefix: String,
+    /// Key holding the text of xml elements that also have attributes or children
+    #[arg(long, value_name = "KEY", default_value = "#text")]
+    xml_text_key: String,
+    /// Drop namespace prefixes from xml element and attribute names, and namespace declarations
+    #[arg(long, default_value = "false")]
+    xml_strip_namespaces: bool,
+    /// Always read these xml elements as arrays, even when they occur once (comma separated)
+    ///
+    /// Example: lq --input=xml --xml-force-list item '.rss.channel.item | length' feed.xml
+    #[arg(long, value_name = "NAMES", value_delimiter = ',')]
+    xml_force_list: Vec<String>,
+
+    /// Convert jq output to YAML (shortcut for --output=yaml)
+    #[arg(
+        short = 'y',
+        long,
+        default_value = "false",
+        conflicts_with = "toml_output",
+        conflicts_with = "output"
+    )]
+    yaml_output: bool,
+    /// Validate and re-serialize jq output as JSON (shortcut for --output=json)
+    #[arg(
+        short = 'J',
+        long,
+        default_value = "false",
+        conflicts_with_all = ["yaml_output", "toml_output", "output", "raw_output", "join_output"]
+    )]
+    json_output: bool,
+    /// Convert jq output to TOML (shortcut for --output=toml)
+    #[arg(
+        short = 't',
+        long,
+        default_value = "false",
+        conflicts_with = "yaml_output",
+        conflicts_with = "output"
+    )]
+    toml_output: bool,
+
+    /// Write nested tables inline (a = { b = 1 }) rather than as [a] sections (toml output only)
+    #[arg(long, default_value = "false")]
+    toml_inline_tables: bool,
+
+    /// How to write arrays of objects (toml output only)
+    #[arg(long, value_enum, value_name = "STYLE", default_value_t)]
+    toml_arrays: TomlArrays,
+
+    /// Sort keys alphabetically (toml output only)
+    ///
+    /// Plain values are always written before tables within a table, as toml requires.
+    #[arg(long, default_value = "false")]
+    toml_sort_keys: bool,
+
+    /// Write objects and arrays in flow style ({a: 1, b: [x, y]}) from this depth on (yaml output only)
+    ///
+    /// Without a depth, documents are written on a single line, e.g. for annotations and flags
+    /// taking inline yaml. With --flow=1, top level keys stay on their own lines, and so on.
+    ///
+    /// Example: lq -y --flow '.spec.template.metadata.labels' deploy.yaml
+    #[arg(
+        long,
+        value_name = "DEPTH",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "0"
+    )]
+    flow: Option<usize>,
+
+    /// Emit multiple results as a multidoc YAML stream instead of a sequence (yaml output only)
+    ///
+    /// Each result becomes its own document separated by ---, which can be applied by kubectl.
+    #[arg(long, default_value = "false")]
+    multidoc_output: bool,
+
+    /// Prefix each output document with a comment naming the file and document it came from (yaml output only)
+    ///
+    /// The query is evaluated against each document separately (as with --each), and results are
+    /// written as a multidoc stream with comments like "# source: app.yaml (doc 3)".
+    /// Archive members are named after the archive, as in "chart.tgz:chart/values.yaml (doc 0)".
+    #[arg(
+        long,
+        default_value = "false",
+        conflicts_with_all = ["unique", "unique_by", "sort_docs_by", "prune", "merge_docs", "split", "split_k8s", "stream", "quiet", "count", "paths", "template", "output_codec", "raw_output", "join_output", "in_place", "raw_input_slurp"]
+    )]
+    annotate_source: bool,
+
+    /// Edit the input file in place
+    #[arg(short, long, default_value = "false")]
+    in_place: bool,
+
+    /// Mask the values of keys matching these patterns with *** (comma separated)
+    ///
+    /// Patterns are case insensitive, and either globs (* and ? wildcards) or text to find anywhere
+    /// in a key. Values are masked before the query runs, so they do not leak through raw output either.
+    ///
+    /// Example: lq --redact 'password,*token*' -y . prod.yaml
+    #[arg(long, value_name = "PATTERNS", value_delimiter = ',', conflicts_with_all = ["in_place", "stream"])]
+    redact: Vec<String>,
+
+    /// Delete every object key matching this regular expression, anywhere in the documents, before querying
+    ///
+    /// Keys match when the expression is found anywhere in them. Prefix with (?i) to ignore case.
+    ///
+    /// Example: lq --delete-keys '(?i)password|secret|token' -y . values.yaml
+    #[arg(long, value_name = "REGEX", conflicts_with = "stream")]
+    delete_keys: Option<regex::Regex>,
+
+    /// Substitute $VAR and ${VAR} environment variable references in the input before parsing
+    ///
+    /// Works like envsubst: unset variables expand to nothing (or are errors with --strict).
+    #[arg(long, default_value = "false", conflicts_with = "in_place")]
+    expand_env: bool,
+
+    /// Only expand these variables with --expand-env, leaving other references as they are
+    ///
+    /// Example: --expand-env --env-allowlist IMAGE,TAG
+    #[arg(long, value_name = "VARS", value_delimiter = ',', requires = "expand_env")]
+    env_allowlist: Vec<String>,
+
+    /// Decrypt sops encrypted input before querying (and re-encrypt it with -i)
+    ///
+    /// Input without sops metadata is read as is. Requires the sops feature and the sops binary,
+    /// which re-encrypts edits with the keys of the original file.
+    ///
+    /// Example: lq --sops -i -y '.stringData.password = "hunter2"' secret.enc.yaml
+    #[arg(long, default_value = "false")]
+    sops: bool,
+
+    /// Write the output to a file instead of stdout
+    ///
+    /// The file is replaced atomically once all output has been converted.
+    #[arg(
+        short = 'o',
+        long,
+        value_name = "PATH",
+        value_hint = ValueHint::FilePath,
+        conflicts_with_all = ["in_place", "split", "quiet", "count", "paths"]
+    )]
+    output_file: Option<PathBuf>,
+
+    /// Register an external codec converting a format to and from json (repeatable)
+    ///
+    /// The program is run as `PROGRAM decode` with the format on stdin and json values on stdout, and as
+    /// `PROGRAM encode` with json values (one per line) on stdin and the format on stdout.
+    /// Input files with a .NAME extension are decoded with it. Codecs can also be set in the config file.
+    ///
+    /// Example: lq --codec ini=./ini-codec --output-codec ini '.server' app.ini
+    #[arg(long, value_name = "NAME=PROGRAM")]
+    codec: Vec<Codec>,
+
+    /// Decode the input with this --codec, regardless of the file extension
+    #[arg(long, value_name = "NAME")]
+    input_codec: Option<String>,
+
+    /// Encode the output with this --codec instead of serializing it as json, yaml or toml
+    #[arg(
+        long,
+        value_name = "NAME",
+        conflicts_with_all = ["template", "split", "stream", "quiet", "count", "paths", "raw_output", "join_output", "yaml_output", "toml_output"]
+    )]
+    output_codec: Option<String>,
+
+    /// Render each query result through a Jinja (Tera style) template instead of serializing it
+    ///
+    /// Keys of object results are available as variables, and the whole result as data.
+    /// Undefined variables are errors with --strict. Requires the template feature.
+    ///
+    /// Example: lq --template README.md.j2 '.' Chart.yaml
+    #[arg(
+        long,
+        value_name = "PATH",
+        value_hint = ValueHint::FilePath,
+        conflicts_with_all = ["in_place", "split", "stream", "quiet", "count", "paths", "raw_output", "join_output", "yaml_output", "toml_output"]
+    )]
+    template: Option<PathBuf>,
+
+    /// Do not terminate the output with a newline
+    ///
+    /// Useful for capturing a single raw value, e.g. VAL=$(lq --no-newline -r '.token' f.yaml).
+    /// Implied by -j, whose output is also written without a final newline.
+    #[arg(long, default_value = "false", conflicts_with_all = ["in_place", "split"])]
+    no_newline: bool,
+
+    /// Whether files written with --in-place, --output-file and --split end with a newline
+    #[arg(long = "final-newline", value_enum, value_name = "POLICY", default_value_t)]
+    newline_policy: FinalNewline,
+
+    /// Flush the output after each result instead of in blocks
+    ///
+    /// Shows results in real time when following a stream, e.g. tail -f events.yaml | lq --unbuffered --each.
+    #[arg(long, default_value = "false")]
+    unbuffered: bool,
+
+    /// Split the output by keys and serialize into a folder
+    ///
+    /// If set, this query is executed for each document against jq
+    /// to produce a filename to store the resulting document.
+    ///
+    /// This option only produces a key for each filename. The key
+    /// is evaluated against the base document and does not take the jq query
+    /// into consideration.
+    ///
+    /// Example: --split '"./" + (.metadata.name) + "_" + (.kind | ascii_downcase) + ".yaml"'
+    #[arg(
+        short,
+        long,
+        conflicts_with = "in_place",
+        // we need to be able to parse these back into documents to be able to split them
+        conflicts_with = "raw_output",
+        conflicts_with = "compact_output",
+        conflicts_with = "join_output"
+    )]
+    split: Option<String>,
+
+    /// Split Kubernetes manifests into DIR/namespace/kind_name files (a preset for --split)
+    ///
+    /// Cluster scoped resources (without a namespace) are written to DIR/kind_name, and the
+    /// file extension follows the output format. DIR defaults to the current directory.
+    ///
+    /// Example: kubectl get deploy,svc -o yaml | lq --unwrap-list -y --split-k8s=manifests
+    #[arg(
+        long,
+        value_name = "DIR",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = ".",
+        conflicts_with_all = ["split", "in_place", "raw_output", "compact_output", "join_output"]
+    )]
+    split_k8s: Option<String>,
+
+    /// Restrict processing to the documents at these indices of a multidoc stream
+    ///
+    /// Accepts a comma separated list of indices and ranges (open ended ranges run to the last document).
+    /// Top level arrays are treated as multidoc streams for json input.
+    ///
+    /// Example: --doc 0,2-4
+    #[arg(long, value_name = "INDICES")]
+    doc: Option<DocSelection>,
+
+    /// Evaluate the query against each document separately instead of against all documents at once
+    ///
+    /// Keeps document boundaries so that edits cannot see sibling documents.
+    /// Top level arrays are treated as multidoc streams for json input.
+    ///
+    /// Example: lq --each '.metadata.name' -r manifests.yaml
+    #[arg(long, default_value = "false")]
+    each: bool,
+
+    /// Flatten Kubernetes List objects (kind: List with items) into individual documents before querying
+    ///
+    /// Makes --split work directly on kubectl get -o yaml output.
+    #[arg(long, default_value = "false")]
+    unwrap_list: bool,
+
+    /// Strip fields populated by the Kubernetes api server from every document before querying
+    ///
+    /// Removes status, and metadata such as managedFields, uid, resourceVersion, creationTimestamp
+    /// and the last-applied-configuration annotation, so kubectl get output can be applied again.
+    ///
+    /// Example: kubectl get deploy -o yaml | lq --k8s-clean --unwrap-list -y --multidoc-output '.[]'
+    #[arg(long, default_value = "false", conflicts_with = "stream")]
+    k8s_clean: bool,
+
+    /// Set metadata.namespace on every namespaced Kubernetes document before querying
+    ///
+    /// Cluster scoped kinds (Namespace, ClusterRole, CustomResourceDefinition, etc) are left alone.
+    ///
+    /// Example: lq --set-namespace staging -y --multidoc-output '.[]' bundle.yaml
+    #[arg(long, value_name = "NAMESPACE", conflicts_with = "stream")]
+    set_namespace: Option<String>,
+
+    /// Add a label to the metadata of every Kubernetes document before querying (repeatable)
+    ///
+    /// Example: lq --add-label app.kubernetes.io/managed-by=lq -y '.' deploy.yaml
+    #[arg(long, value_name = "KEY=VALUE", conflicts_with = "stream")]
+    add_label: Vec<KeyValue>,
+
+    /// Add an annotation to the metadata of every Kubernetes document before querying (repeatable)
+    #[arg(long, value_name = "KEY=VALUE", conflicts_with = "stream")]
+    add_annotation: Vec<KeyValue>,
+
+    /// Also add the --add-label and --add-annotation metadata to pod templates of workloads
+    #[arg(long, default_value = "false")]
+    pod_templates: bool,
+
+    /// Deep merge every document over the contents of this file before querying
+    ///
+    /// Fills in optional fields consistently: values from the input win, objects are merged and
+    /// everything else (including arrays) is taken from the input when present there.
+    ///
+    /// Example: lq --defaults defaults.yaml -y . service.yaml
+    #[arg(long, value_name = "FILE", value_hint = ValueHint::FilePath, conflicts_with = "stream")]
+    defaults: Option<PathBuf>,
+
+    /// Recursively rename object keys to a naming convention before querying
+    ///
+    /// Example: lq --rename-keys snake -y '.' deploy.yaml
+    #[arg(long, value_enum, value_name = "CASE", conflicts_with = "stream")]
+    rename_keys: Option<KeyCase>,
+
+    /// Keep only these paths of every document before querying (comma separated)
+    ///
+    /// Paths are like .metadata.name or .spec.containers[0].image, where * (or []) matches any
+    /// key or index, keys can be globs like helm*, and dots within keys are escaped as \.
+    /// No query is needed.
+    ///
+    /// Example: lq --pick .metadata.name,.spec.replicas -y deploy.yaml
+    #[arg(long, value_name = "PATHS", value_delimiter = ',', conflicts_with = "stream")]
+    pick: Vec<FieldPath>,
+
+    /// Drop these paths from every document before querying (comma separated, as in --pick)
+    ///
+    /// Example: lq --omit '.metadata.annotations,.spec.template.spec.containers[].env' -y deploy.yaml
+    #[arg(long, value_name = "PATHS", value_delimiter = ',', conflicts_with = "stream")]
+    omit: Vec<FieldPath>,
+
+    /// Convert numeric strings to numbers, and "true" and "false" strings to booleans before querying
+    ///
+    /// For input where everything arrives as strings (e.g. csv, env or properties files through a
+    /// codec). Strings only become numbers that print back the same, so versions like 1.10, zero
+    /// padded codes and ids too long for a float stay strings.
+    #[arg(long, default_value = "false", conflicts_with = "stream")]
+    coerce_types: bool,
+
+    /// Paths to leave alone with --coerce-types (comma separated, as in --pick)
+    ///
+    /// Example: lq --coerce-types --coerce-exclude '.[].zip,.[].phone' . people.json
+    #[arg(long, value_name = "PATHS", value_delimiter = ',', requires = "coerce_types")]
+    coerce_exclude: Vec<FieldPath>,
+
+    /// Merge all documents into a single document before querying
+    ///
+    /// Useful for combining partial config fragments, e.g. cat conf.d/*.yaml | lq --merge-docs
+    /// Arrays and scalars from later documents replace earlier ones.
+    #[arg(
+        long,
+        value_enum,
+        value_name = "MODE",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "deep"
+    )]
+    merge_docs: Option<MergeMode>,
+
+    /// Apply the query only to documents matching this jq expression, passing others through unchanged
+    ///
+    /// The expression can be a condition or a select filter. Documents are evaluated one at a time.
+    ///
+    /// Example: --select '.kind == "Deployment"' '.spec.replicas = 3'
+    #[arg(long, value_name = "EXPR")]
+    select: Option<String>,
+
+    /// Validate documents against Kubernetes OpenAPI schemas before querying
+    ///
+    /// Takes an optional Kubernetes version (default master) and looks up
+    /// schemas for each document's apiVersion and kind in --kubernetes-schemas.
+    /// Missing schemas are downloaded from yannh/kubernetes-json-schema via curl (requires the http feature),
+    /// and kinds without one (or covered by --crd) are skipped.
+    ///
+    /// Example: --kubernetes-validate=v1.29.0
+    #[arg(
+        long,
+        value_name = "VERSION",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "master",
+        value_parser = parse_kubernetes_version
+    )]
+    kubernetes_validate: Option<String>,
+
+    /// Validate custom resources against the openAPIV3Schema of this CustomResourceDefinition file (repeatable)
+    ///
+    /// Documents whose apiVersion and kind match a served version of a CRD in the file are
+    /// checked against its schema, and violations are reported with their path. Other documents
+    /// are left alone.
+    ///
+    /// Example: lq --crd crds/certificate.yaml '.[].spec.dnsNames' certs.yaml
+    #[arg(long, value_name = "FILE", value_hint = ValueHint::FilePath, conflicts_with = "stream")]
+    crd: Vec<PathBuf>,
+
+    /// Directory containing (or caching) kubeconform style Kubernetes JSON schemas
+    ///
+    /// Default ~/.cache/lq/kubernetes-json-schema
+    #[arg(long, env = "LQ_KUBERNETES_SCHEMAS", value_name = "DIR", value_hint = ValueHint::DirPath)]
+    kubernetes_schemas: Option<PathBuf>,
+
+    /// Query to be sent to jq (see https://jqlang.github.io/jq/manual/)
+    ///
+    /// Default "."
+    #[arg()]
+    jq_query: Option<String>,
+
+    /// Optional file to read (instead of stdin) in the chosen --input format
+    ///
+    /// May also be an http(s) url to download (requires the http feature and curl).
+    #[arg(value_hint = ValueHint::AnyPath)]
+    file: Option<PathBuf>,
+
+    /// Only read the archive members whose path matches this glob (* and ? wildcards)
+    ///
+    /// Input files ending in .tar, .tar.gz, .tgz or .zip are read as a multidoc stream of the
+    /// documents in their members, by default those with a yaml, json or toml extension.
+    /// Requires the archive feature.
+    ///
+    /// Example: lq '.version' chart.tgz --archive-glob '*/Chart.yaml'
+    #[arg(long, value_name = "GLOB")]
+    archive_glob: Option<String>,
+
+    /// Only read this sheet of --input=xlsx workbooks, as an array of row objects
+    ///
+    /// Without it, the input is an object of every sheet by name.
+    #[arg(long, value_name = "NAME")]
+    sheet: Option<String>,
+
+    /// Extra header to send when the file is an http(s) url (repeatable)
+    ///
+    /// Example: --header 'Authorization: Bearer $TOKEN'
+    #[arg(long = "header", value_name = "HEADER", requires = "file")]
+    headers: Vec<String>,
+
+    /// Drop structurally duplicate documents from the query results
+    #[arg(long, default_value = "false", conflicts_with_all = ["raw_output", "join_output", "split"])]
+    unique: bool,
+
+    /// Drop documents from the query results whose key (evaluated by this jq expression) was already seen
+    ///
+    /// Example: --unique-by '[.kind, .metadata.namespace, .metadata.name]'
+    #[arg(
+        long,
+        value_name = "EXPR",
+        conflicts_with_all = ["unique", "raw_output", "join_output", "split"]
+    )]
+    unique_by: Option<String>,
+
+    /// Sort the query results by the key this jq expression evaluates to for each document
+    ///
+    /// The sort is stable and keys are compared like jq's sort. Pass k8s (or k8s-kind-order) to sort
+    /// Kubernetes manifests in apply order (Namespaces before Deployments before HPAs, etc).
+    ///
+    /// Example: --sort-docs-by '.metadata.name' or --sort-docs=k8s
+    #[arg(long, visible_alias = "sort-docs", value_name = "EXPR", conflicts_with_all = ["raw_output", "join_output", "split"])]
+    sort_docs_by: Option<String>,
+
+    /// Recursively remove null values and/or empty objects and arrays from the query results
+    ///
+    /// Values are removed from objects and arrays. Defaults to both when given without a value.
+    ///
+    /// Example: kubectl get cm app -o yaml | lq --k8s-clean --prune -y .
+    #[arg(
+        long,
+        value_enum,
+        value_name = "VALUES",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "both",
+        conflicts_with_all = ["raw_output", "join_output", "split"]
+    )]
+    prune: Option<Prune>,
+
+    /// Sort every array of objects in the query results by the key this jq expression evaluates to
+    ///
+    /// Keys are compared like jq's sort_by, so items without the key come first. Useful for
+    /// diffing normalized manifests, e.g. with env, ports or volumes lists in different orders.
+    ///
+    /// Example: lq --sort-arrays-by .name -y . deploy.yaml
+    #[arg(long, value_name = "EXPR")]
+    sort_arrays_by: Option<String>,
+
+    /// Exit 1 if the last result is null or false, and 4 if there were no results (like jq -e)
+    #[arg(short, long, default_value = "false", conflicts_with = "quiet")]
+    exit_status: bool,
+
+    /// Suppress all output and exit 1 unless the query produced a result other than null or false
+    ///
+    /// Example: if lq -q '.spec.replicas > 3' deploy.yaml; then ...
+    #[arg(
+        short,
+        long,
+        default_value = "false",
+        conflicts_with = "in_place",
+        conflicts_with = "split"
+    )]
+    quiet: bool,
+
+    /// Print only the number of results produced by the query
+    ///
+    /// Example: lq --count '.[] | select(.kind == "Deployment")' manifests.yaml
+    #[arg(
+        long,
+        default_value = "false",
+        conflicts_with = "in_place",
+        conflicts_with = "split",
+        conflicts_with = "quiet",
+        conflicts_with = "raw_output",
+        conflicts_with = "join_output"
+    )]
+    count: bool,
+
+    /// List every leaf path in the query results instead of the results themselves
+    ///
+    /// Paths are printed in jq notation, optionally with values (as jq assignments) or types.
+    ///
+    /// Example: lq --paths=values '.spec' deploy.yaml
+    #[arg(
+        long,
+        value_enum,
+        value_name = "MODE",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "plain",
+        conflicts_with_all = ["in_place", "split", "quiet", "count", "raw_output", "join_output"]
+    )]
+    paths: Option<PathsMode>,
+
+    /// Turn soft parsing issues into errors
+    ///
+    /// Rejects duplicate keys in JSON, non-string keys and tags in YAML, and jq output
+    /// that cannot be parsed back for --output conversion (instead of dropping it).
+    /// Also validates against the strict schemas (no unknown fields) with --kubernetes-validate.
+    #[arg(long, default_value = "false", global = true)]
+    strict: bool,
+
+    /// Read input files with buffered reads instead of memory mapping them
+    ///
+    /// Mapping speeds up parsing of large files, but the file must not be modified while lq runs.
+    #[arg(long, default_value = "false")]
+    no_mmap: bool,
+
+    /// Read the input from stdin even when stdin looks like a terminal
+    ///
+    /// Without an input file, stdin is only read when it is not a terminal (the help is printed otherwise),
+    /// which some CI sandboxes and shells misreport.
+    #[arg(long, default_value = "false", conflicts_with = "file")]
+    stdin: bool,
+
+    /// What to query when the input has no documents (e.g. an empty file)
+    ///
+    /// Use error to fail pipelines where empty input means something upstream went wrong.
+    #[arg(long, value_enum, value_name = "POLICY", default_value_t)]
+    empty_input: EmptyInput,
+
+    /// How to read input with bytes that are not valid utf-8 (e.g. stray latin-1 in legacy configs)
+    #[arg(long, value_enum, value_name = "POLICY", default_value_t)]
+    invalid_utf8: InvalidUtf8,
+
+    /// Fail on yaml documents expanding to more than N nodes, counting the nodes of every alias [default: 10000000]
+    ///
+    /// Protects against alias expansion bombs (billion laughs) in untrusted yaml.
+    #[arg(long, value_name = "N")]
+    yaml_max_nodes: Option<usize>,
+
+    /// Fail on yaml documents nested deeper than N levels [default: 128]
+    #[arg(long, value_name = "N")]
+    yaml_max_depth: Option<usize>,
+
+    /// Fail on input larger than SIZE (e.g. 500M or 2G) instead of reading it into memory
+    ///
+    /// Guards against running out of memory on huge files. Evaluating yaml documents one at a time
+    /// (--each, --stream) only reads a document at a time, and fails on a single document larger than SIZE.
+    #[arg(long, value_name = "SIZE", value_parser = parse_size)]
+    max_input_size: Option<u64>,
+
+    /// What to do with input files larger than --max-input-size
+    #[arg(
+        long,
+        value_enum,
+        value_name = "ACTION",
+        default_value_t,
+        requires = "max_input_size"
+    )]
+    large_input: LargeInput,
+
+    /// Increase the verbosity of diagnostic logs on stderr (-v for debug, -vv for trace)
+    ///
+    /// Overrides RUST_LOG. Note that -q is --quiet, not a log level.
+    #[arg(short, long, action = clap::ArgAction::Count, global = true, conflicts_with = "log_level")]
+    verbose: u8,
+
+    /// Filter for diagnostic logs on stderr (e.g. warn, debug, or lq=trace), overriding RUST_LOG
+    #[arg(long, value_name = "FILTER", global = true)]
+    log_level: Option<String>,
+
+    // ----- jq arguments
+    /// Compact instead of pretty-printed output (jq output only)
+    ///
+    /// This is unlikely to work with yaml or toml output because it requires
+    /// that the jq -c output is deserializable into the desired output format.
+    #[arg(short = 'c', long, default_value = "false")]
+    compact_output: bool,
+
+    /// Output strings without escapes and quotes (jq output only)
+    ///
+    /// This is unlikely to work with yaml or toml output because it requires
+    /// that the jq -r output is deserializable into the desired output format.
+    #[arg(short = 'r', long, default_value = "false")]
+    raw_output: bool,
+
+    /// Output strings without escapes and quotes, without newlines after each output (jq output only)
+    ///
+    /// This is unlikely to work with yaml or toml output because it requires
+    /// that the jq -r output is deserializable into the desired output format.
+    #[arg(short = 'j', long, default_value = "false")]
+    join_output: bool,
+
+    /// Escape non-ascii characters in strings (as jq -a)
+    ///
+    /// Applies to yaml and toml output as well, where such strings are written double quoted
+    /// with \u escapes.
+    #[arg(short = 'a', long, default_value = "false")]
+    ascii_output: bool,
+
+    /// Use null as the single input, leaving the documents to jq's input and inputs builtins
+    #[arg(short = 'n', long, default_value = "false")]
+    null_input: bool,
+
+    /// Pass the entire input to jq as a single string instead of parsing it (like jq -Rs)
+    ///
+    /// Lets free-form text be parsed in the query (e.g. with split or capture) while still
+    /// converting the results with --output.
+    ///
+    /// Example: lq --raw-input-slurp -y 'split("\n") | map(select(length > 0))' notes.txt
+    #[arg(
+        long,
+        default_value = "false",
+        conflicts_with_all = ["null_input", "each", "select", "doc", "unwrap_list", "merge_docs", "split", "stream", "redact", "in_place", "input_codec"]
+    )]
+    raw_input_slurp: bool,
+
+    /// Read and write RFC 7464 JSON text sequences (RS delimited json)
+    ///
+    /// Json input is read as a multidoc stream of json texts, and jq output is written with --seq.
+    #[arg(long, default_value = "false")]
+    seq: bool,
+
+    /// Feed jq the streaming form of the input ([path, leaf] and closing [path] events, as jq --stream)
+    ///
+    /// Lets huge single documents be filtered without materializing them in lq or jq.
+    /// Use fromstream and truncate_stream in the query to rebuild values from the events.
+    /// YAML merge keys are not expanded in this mode.
+    ///
+    /// Example: lq --stream -c 'fromstream(1 | truncate_stream(inputs))' -n huge.yaml
+    #[arg(
+        long,
+        default_value = "false",
+        conflicts_with_all = ["split", "each", "select", "doc", "unwrap_list", "merge_docs", "in_place", "kubernetes_validate", "seq"]
+    )]
+    stream: bool,
+
+    /// Re-run the query whenever the input file changes, replacing the previous output
+    ///
+    /// Runs until interrupted. Requires the watch feature.
+    #[arg(long, default_value = "false", requires = "file", conflicts_with_all = ["in_place", "output_file", "split"])]
+    watch: bool,
+
+    /// Read the query from a file instead of the first argument (repeatable, like jq -f)
+    ///
+    /// Multiple files are concatenated in order into one program, so a file of shared
+    /// definitions can be given before the file with the filter using them.
+    ///
+    /// Example: lq -f defs.jq -f images.jq deploy.yaml
+    #[arg(short = 'f', long, value_name = "FILE", value_hint = ValueHint::FilePath)]
+    from_file: Vec<PathBuf>,
+
+    /// Answer several queries in one run, outputting an object with each result under its name (repeatable)
+    ///
+    /// The input is parsed once, and the first argument is the input file rather than the query.
+    /// Queries producing several results yield one object per combination, as in jq object
+    /// construction, so wrap them in [...] to collect their results into an array.
+    ///
+    /// Example: lq --query name='.metadata.name' --query images='[.. | .image? // empty]' deploy.yaml
+    #[arg(long = "query", value_name = "NAME=QUERY", conflicts_with = "from_file")]
+    named_queries: Vec<NamedQuery>,
+
+    /// Set the jq variable $NAME to the value of the environment variable VAR (default NAME, repeatable)
+    ///
+    /// Keeps secrets out of shell history without quoting them into an --arg, and without
+    /// the query needing the whole environment through env.
+    ///
+    /// Example: lq --arg-env token=API_TOKEN '.auth.token = $token' config.yaml
+    #[arg(long, value_name = "NAME[=VAR]")]
+    arg_env: Vec<EnvArg>,
+
+    /// Set the jq variable $NAME to structured data parsed from an inline yaml string (repeatable)
+    ///
+    /// Example: lq --argyaml extra '{team: infra, tier: [a, b]}' '.metadata.labels += $extra' deploy.yaml
+    #[arg(long, num_args = 2, value_names = ["NAME", "YAML"])]
+    argyaml: Vec<String>,
+
+    /// Search jq modules from the directory (repeatable, searched in order)
+    #[arg(short = 'L', value_hint = ValueHint::DirPath)]
+    modules: Vec<PathBuf>,
+
+    /// Indent pretty-printed output with n spaces (jq and json output only)
+    #[arg(long, value_name = "N")]
+    indent: Option<u8>,
+
+    /// Colorize json output (jq output only)
+    ///
+    /// Colors are on by default when stdout is a terminal (and NO_COLOR is unset),
+    /// since jq itself only ever sees a pipe.
+    #[arg(short = 'C', long, default_value = "false")]
+    color_output: bool,
+
+    /// Never colorize json output
+    #[arg(short = 'M', long, default_value = "false", conflicts_with = "color_output")]
+    monochrome_output: bool,
+
+    /// Print version, along with the jq version and executable that would be used
+    #[arg(short = 'V', long, default_value = "false")]
+    version: bool,
+
+    /// The jq executable to run
+    ///
+    /// Default jq (from PATH)
+    #[arg(long, value_name = "PATH", value_hint = ValueHint::ExecutablePath)]
+    jq: Option<PathBuf>,
+
+    /// Number of documents read, exposed to jq as $__doc_count
+    #[arg(skip)]
+    doc_count: usize,
+
+    /// In-memory input used instead of a file or stdin when embedded as a library (and in tests)
+    #[arg(skip)]
+    source: Option<Vec<u8>>,
+
+    /// Whether the input was decrypted with sops
+    #[arg(skip)]
+    sops_encrypted: bool,
+
+    /// Archive member (and document within it) of each input document, for --annotate-source
+    #[arg(skip)]
+    member_sources: Vec<String>,
+
+    /// Input document index each selected document came from, for --annotate-source
+    #[arg(skip)]
+    doc_origins: Vec<usize>,
+
+    /// Contents of the --defaults file
+    #[arg(skip)]
+    default_values: Option<serde_json::Value>,
+
+    /// Query engine used in place of the jq executable when embedded as a library
+    #[arg(skip)]
+    engine: Option<engine::Shared>,
+}
+
+#[derive(Subcommand, Debug, Clone)]
+enum Command {
+    /// Check whether two files contain structurally equal documents
+    ///
+    /// Files can be in different formats. Key order, formatting and integral floats (1.0 vs 1)
+    /// are ignored. Exits 0 when equal, and 1 after printing the differing paths otherwise.
+    ///
+    /// Example: lq equal rendered.yaml expected.json --ignore-paths .metadata.annotations
+    Equal {
+        /// First file to compare
+        #[arg(value_hint = ValueHint::FilePath)]
+        a: PathBuf,
+        /// Second file to compare
+        #[arg(value_hint = ValueHint::FilePath)]
+        b: PathBuf,
+        /// Comma separated jq paths to delete from every document before comparing
+        #[arg(long, value_delimiter = ',')]
+        ignore_paths: Vec<String>,
+    },
+    /// Rewrite files into a canonical form with sorted keys and consistent style
+    ///
+    /// Files are re-serialized in their own format (guessed from the extension).
+    /// Comments are dropped, and YAML anchors, aliases and merge keys are expanded.
+    ///
+    /// Example: lq normalize -i manifests/*.yaml
+    Normalize {
+        /// Files to normalize
+        #[arg(required = true, value_hint = ValueHint::FilePath)]
+        files: Vec<PathBuf>,
+        /// Rewrite the files in place instead of printing them
+        #[arg(short, long, default_value = "false", conflicts_with = "check")]
+        in_place: bool,
+        /// Print files that are not normalized and exit 1 if there are any
+        #[arg(long, default_value = "false")]
+        check: bool,
+    },
+    /// Print a stable sha256 digest of the canonicalized structure of files
+    ///
+    /// Documents are hashed as compact json with sorted keys and integral floats
+    /// as integers, so semantically identical files hash identically regardless of
+    /// format, key order, comments or whitespace.
+    ///
+    /// Example: lq hash values.yaml values.json
+    Hash {
+        /// Files to hash
+        #[arg(required = true, value_hint = ValueHint::FilePath)]
+        files: Vec<PathBuf>,
+    },
+    /// Build a query interactively with a live preview of its results
+    ///
+    /// The output updates as the query is typed. Enter prints the output of the query
+    /// (and the query itself to stderr), escape quits, and up/down recall earlier queries.
+    ///
+    /// Example: lq -y repl deploy.yaml
+    Repl {
+        /// File to query (instead of stdin)
+        #[arg(value_hint = ValueHint::FilePath)]
+        file: Option<PathBuf>,
+    },
+    /// Serve queries over http
+    ///
+    /// POST a document to / with the query (default .), input, output, compact and raw url
+    /// parameters to get the result back, and GET /healthz for liveness checks.
+    /// Other options (e.g. --redact or --strict) apply to every request. Queries run without
+    /// lq's environment, and bodies over 64MiB are rejected with 413.
+    ///
+    /// Example: curl --data-binary @deploy.yaml 'localhost:8080/?query=.metadata&output=yaml'
+    Serve {
+        /// Address to listen on (host:port, or :port for localhost; use 0.0.0.0:port for all interfaces)
+        #[arg(long, default_value = "127.0.0.1:8080")]
+        listen: String,
+    },
+    /// Merge files structurally, deeply with later files winning, or three-way with --three-way
+    ///
+    /// With --three-way, the files are base, ours and theirs. Values changed on one side take that
+    /// change, and objects changed on both sides are merged by key. Values changed differently on
+    /// both sides are conflicts: resolved with --prefer, or else marked with an object holding both
+    /// sides under "<<<<<<< ours" and ">>>>>>> theirs" keys, listed on stderr, and exit 1.
+    ///
+    /// Example: lq -y merge --three-way base.yaml ours.yaml theirs.yaml
+    Merge {
+        /// Files to merge
+        #[arg(required = true, value_hint = ValueHint::FilePath)]
+        files: Vec<PathBuf>,
+        /// Three-way merge the files base, ours and theirs
+        #[arg(long, default_value = "false")]
+        three_way: bool,
+        /// Resolve three-way merge conflicts in favor of one side
+        #[arg(long, requires = "three_way")]
+        prefer: Option<Prefer>,
+    },
+    /// Merge Helm values files and --set overrides the way helm does
+    ///
+    /// Maps are merged deeply with later files winning, lists are replaced, and null deletes a key.
+    /// --set takes helm's path syntax (a.b[0].c=v, comma separated, {x,y} for lists, \. for
+    /// literal dots), with true, false, null and integers typed as in helm.
+    ///
+    /// Example: lq -y values values.yaml env/prod.yaml --set image.tag=1.2.3
+    Values {
+        /// Values files, in increasing order of precedence
+        #[arg(required = true, value_hint = ValueHint::FilePath)]
+        files: Vec<PathBuf>,
+        /// Set values on top of the files (repeatable)
+        #[arg(long, value_name = "PATH=VALUE")]
+        set: Vec<String>,
+    },
+    /// Print the paths of keys or values matching regular expressions, like grep for structured data
+    ///
+    /// Values match when they are scalars whose text (or json for numbers, booleans and null) contains
+    /// a match, and keys when they contain a match. With both, matches need a matching key and value.
+    /// Paths are jq paths into the input (with multiple documents as an array, as queries see them),
+    /// prefixed by the file name when searching several files. Exits 1 when nothing matches.
+    ///
+    /// Example: lq find --key-regex '^image$' --value-regex ':latest$' manifests/*.yaml
+    Find {
+        /// Files to search (instead of stdin)
+        #[arg(value_hint = ValueHint::FilePath)]
+        files: Vec<PathBuf>,
+        /// Regular expression to match scalar values against
+        #[arg(long, value_name = "REGEX", required_unless_present = "key_regex")]
+        value_regex: Option<regex::Regex>,
+        /// Regular expression to match object keys against
+        #[arg(long, value_name = "REGEX")]
+        key_regex: Option<regex::Regex>,
+        /// Print the matching values after their paths
+        #[arg(long, default_value = "false")]
+        values: bool,
+    },
+    /// Print the results of a query as an aligned text table, like kubectl get
+    ///
+    /// Rows are the objects the query outputs (or the items of a single array it outputs), and
+    /// columns are their keys in order of appearance unless picked with --columns. Strings are
+    /// printed as is, missing values and nulls as <none>, and other values as compact json.
+    ///
+    /// Example: lq table '.items[] | {name: .metadata.name, replicas: .spec.replicas}' deploys.json
+    Table {
+        /// Query producing the rows (default .)
+        query: Option<String>,
+        /// File to query (instead of stdin)
+        #[arg(value_hint = ValueHint::FilePath)]
+        file: Option<PathBuf>,
+        /// Comma separated keys to show as columns, in order
+        #[arg(long, value_delimiter = ',')]
+        columns: Vec<String>,
+        /// Leave out the header row
+        #[arg(long, default_value = "false")]
+        no_headers: bool,
+    },
+    /// Helpers for Kubernetes manifests
+    K8s {
+        #[command(subcommand)]
+        command: K8sCommand,
+    },
+    /// Print a shell completion script
+    ///
+    /// Example: lq completions zsh > ~/.zfunc/_lq
+    Completions {
+        /// Shell to generate completions for
+        #[arg(value_enum)]
+        shell: clap_complete::Shell,
+    },
+}
+
+#[derive(Subcommand, Debug, Clone)]
+enum K8sCommand {
+    /// List the container images referenced in manifests, or rewrite them with --set
+    ///
+    /// Covers containers, initContainers and ephemeralContainers anywhere in a document
+    /// (Pods, Deployments, StatefulSets, CronJobs, etc). Images are listed once each, in order.
+    ///
+    /// Example: lq k8s images -i --set registry.io/app:1.2.3 manifests/*.yaml
+    Images {
+        /// Manifest files to read
+        #[arg(required = true, value_hint = ValueHint::FilePath)]
+        files: Vec<PathBuf>,
+        /// Replace images of the same repository with this image (repeatable)
+        #[arg(long, value_name = "IMAGE:TAG")]
+        set: Vec<String>,
+        /// Rewrite the files in place instead of printing them (with --set)
+        #[arg(short, long, default_value = "false", requires = "set")]
+        in_place: bool,
+    },
+}
+
+impl Args {
+    /// Parse the command line, filling in defaults from the user config file
+    ///
+    /// The input format also defaults from the name lq is invoked as (e.g. tq for toml).
+    pub fn parse_with_config() -> Result<Self, Error> {
+        use clap::{CommandFactory, FromArgMatches};
+        let matches = Self::command().get_matches();
+        let mut args = Self::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+        if let Some(path) = config_path()? {
+            let config = std::fs::read_to_string(&path)
+                .map_err(anyhow::Error::from)
+                .and_then(|toml_str| toml_to_json(&toml_str))
+                .map_err(|e| e.context(format!("reading config {}", path.display())))?;
+            args.apply_config(&config, &matches)?;
+        }
+        let input_given = matches.value_source("input") == Some(clap::parser::ValueSource::CommandLine);
+        if let (Some(argv0), false) = (std::env::args_os().next(), input_given) {
+            if let Some(input) = Input::from_binary_name(std::path::Path::new(&argv0)) {
+                debug!("defaulting to {input:?} input from binary name");
+                args.input = input;
+            }
+        }
+        Ok(args)
+    }
+
+    /// Parse arguments for embedding lq, ignoring the environment variables that back some flags
+    ///
+    /// Library results then only depend on the arguments, not on the environment of the caller.
+    pub(crate) fn parse_without_env<I, T>(argv: I) -> Result<Self, clap::Error>
+    where
+        I: IntoIterator<Item = T>,
+        T: Into<std::ffi::OsString> + Clone,
+    {
+        use clap::{CommandFactory, FromArgMatches};
+        let matches = Self::command()
+            .mut_args(|arg| arg.env(None))
+            .try_get_matches_from(argv)?;
+        Self::from_arg_matches(&matches)
+    }
+
+    /// Apply config file defaults for everything not given on the command line
+    ///
+    /// Supports input, output, indent, color, jq, and an aliases table of named queries (used as @name).
+    fn apply_config(&mut self, config: &serde_json::Value, matches: &clap::ArgMatches) -> Result<()> {
+        use clap::parser::ValueSource;
+        let unset = |id: &str| matches.value_source(id) != Some(ValueSource::CommandLine);
+        let string = |key: &str| -> Result<Option<&str>> {
+            match config.get(key) {
+                None => Ok(None),
+                Some(serde_json::Value::String(s)) => Ok(Some(s)),
+                Some(x) => anyhow::bail!("config {key} must be a string, found {x}"),
+            }
+        };
+        if let (Some(input), true) = (string("input")?, unset("input")) {
+            self.input = Input::from_str(input, true).map_err(|e| anyhow::anyhow!("config input: {e}"))?;
+        }
+        if let (Some(output), true) = (string("output")?, unset("output")) {
+            if !self.yaml_output && !self.toml_output {
+                self.output =
+                    Output::from_str(output, true).map_err(|e| anyhow::anyhow!("config output: {e}"))?;
+            }
+        }
+        if let (Some(indent), None) = (config.get("indent"), self.indent) {
+            let indent = indent.as_u64().and_then(|n| u8::try_from(n).ok());
+            self.indent =
+                Some(indent.ok_or_else(|| anyhow::anyhow!("config indent must be a small integer"))?);
+        }
+        if let (Some(color), true) = (config.get("color"), unset("color_output")) {
+            self.color_output = color
+                .as_bool()
+                .ok_or_else(|| anyhow::anyhow!("config color must be a boolean"))?;
+        }
+        if let (Some(jq), None) = (string("jq")?, &self.jq) {
+            self.jq = Some(jq.into());
+        }
+        if let Some(codecs) = config.get("codecs") {
+            let codecs = codecs
+                .as_object()
+                .ok_or_else(|| anyhow::anyhow!("config codecs must be a table"))?;
+            for (name, program) in codecs {
+                let program = program
+                    .as_str()
+                    .ok_or_else(|| anyhow::anyhow!("config codec {name} must be a string"))?;
+                if !self.codec.iter().any(|c| c.name == *name) {
+                    self.codec.push(Codec {
+                        name: name.clone(),
+                        program: program.into(),
+                    });
+                }
+            }
+        }
+        // the first argument is the input file when the query is given by other means
+        let positional = self.from_file.is_empty() && self.named_queries.is_empty();
+        let query = self.jq_query.as_ref().filter(|_| positional);
+        if let Some(aliases) = config.get("aliases").and_then(|a| a.as_object()) {
+            // @name would otherwise be ambiguous with the format of jq by that name
+            if let Some(name) = aliases.keys().find(|name| JQ_FORMATS.contains(&name.as_str())) {
+                anyhow::bail!("config alias {name} shadows the jq format @{name}");
+            }
+            if let Some((query, alias)) = query.and_then(|q| Some((q, aliases.get(q.strip_prefix('@')?)?))) {
+                let alias = alias
+                    .as_str()
+                    .ok_or_else(|| anyhow::anyhow!("config alias {query} must be a string"))?;
+                debug!("expanding alias {query} to {alias}");
+                self.jq_query = Some(alias.to_string());
+            }
+        }
+        Ok(())
+    }
+
+    /// Version of lq and the jq it runs, e.g. lq 0.13.0 (jq-1.7.1 at /usr/bin/jq)
+    fn version_line(&self) -> String {
+        if cfg!(all(target_family = "wasm", feature = "jaq")) {
+            return format!("lq {} (jaq)", clap::crate_version!());
+        }
+        let program = self.jq_program();
+        let version = std::process::Command::new(program)
+            .arg("--version")
+            .output()
+            .ok()
+            .filter(|out| out.status.success())
+            .map(|out| String::from_utf8_lossy(&out.stdout).trim().to_string());
+        let jq = match (version, find_program(program)) {
+            (Some(version), Some(path)) => format!("{version} at {}", path.display()),
+            (Some(version), None) => version,
+            (None, _) => format!("{} not found", program.display()),
+        };
+        format!("lq {} ({jq})", clap::crate_version!())
+    }
+
+    /// The engine evaluating queries in place of the jq executable, if any
+    ///
+    /// wasm targets cannot spawn jq, so they evaluate queries with jaq unless given another engine.
+    fn query_engine(&self) -> Option<&dyn engine::Engine> {
+        match &self.engine {
+            Some(engine) => Some(engine),
+            #[cfg(all(target_family = "wasm", feature = "jaq"))]
+            None => Some(&engine::Jaq),
+            #[cfg(not(all(target_family = "wasm", feature = "jaq")))]
+            None => None,
+        }
+    }
+
+    /// The jq executable to run
+    fn jq_program(&self) -> &std::path::Path {
+        self.jq.as_deref().unwrap_or("jq".as_ref())
+    }
+
+    /// The tracing filter requested by --log-level or -v, if any
+    pub fn log_filter(&self) -> Option<String> {
+        match (&self.log_level, self.verbose) {
+            (Some(filter), _) => Some(filter.clone()),
+            (None, 0) => None,
+            (None, 1) => Some("debug".into()),
+            (None, _) => Some("trace".into()),
+        }
+    }
+
+    /// Run the cli pipeline; read input, evaluate it with jq, and write the converted output
+    pub fn run(self) -> Result<(), Error> {
+        Ok(self.run_cli()?)
+    }
+
+    fn run_cli(mut self) -> Result<()> {
+        self.resolve_output_shortcuts();
+        if self.annotate_source && self.output != Output::Yaml {
+            anyhow::bail!("--annotate-source requires yaml output");
+        }
+        if let Some(dir) = &self.split_k8s {
+            self.split = Some(k8s_split_expr(dir, self.output));
+        }
+        // jq writes into a pipe, so it must be told to color output that ends up on a terminal
+        if self.monochrome_output {
+            self.color_output = false;
+        } else if self.output == Output::Jq
+            && !self.buffers_output()
+            && std::io::stdout().is_terminal()
+            && std::env::var_os("NO_COLOR").is_none()
+        {
+            self.color_output = ansi_console();
+        } else if self.color_output {
+            let _ = ansi_console(); // enable escape codes on Windows consoles for an explicit -C
+        }
+        debug!("args: {:?}", self);
+        if self.version {
+            still_open(writeln!(std::io::stdout(), "{}", self.version_line()))?;
+            return Ok(());
+        }
+        if let Some(cmd) = &self.command {
+            return self.run_command(cmd);
+        }
+        self.read_query_files()?;
+        self.parse_yaml_args()?;
+        if let Some(path) = &self.defaults {
+            self.default_values = Some(collapse_docs(self.read_file_docs(path)?));
+        }
+        if self.watch {
+            return self.run_watch();
+        }
+        self.fetch_url()?;
+        self.decrypt_sops()?;
+        self.repair_input_utf8()?;
+        self.expand_input_env()?;
+        self.read_archive()?;
+        self.decode_codec()?;
+        self.check_input_size()?;
+        if let Some(split_args) = &self.jq_split_args() {
+            // File splitting mode. Requiring precise multidoc parsing and evaluation
+            let inputs = self.read_input_multidoc()?;
+            // Evaluate each document with the split expression against jq
+            // Later on, we match up the array of filenames with the corresponding output
+            for (i, json_doc) in inputs.iter().enumerate() {
+                let jq_args = self.jq_args(Some(i));
+                let data = serde_json::to_vec(&json_doc)?;
+                let splitout = self.shellout(&data, split_args)?;
+                let key = String::from_utf8_lossy(&splitout).trim_end().to_string();
+                // TODO: assert no linebreaks in keysplit - it should be used for a path construction
+                // keys use / as the separator on every platform, absolute keys stay absolute
+                let key = if cfg!(windows) {
+                    key.replace('/', "\\")
+                } else {
+                    key
+                };
+                let path = std::path::Path::new(".").join(key);
+
+                let stdout = self.shellout(&data, &jq_args)?;
+                let doc = self.output(stdout)?;
+                debug!("Writing {}", path.display());
+                if let Some(dir) = path.parent() {
+                    std::fs::create_dir_all(dir)?;
+                }
+                let doc = if self.file_newline(&path) { doc + "\n" } else { doc };
+                let mut f = std::fs::File::create(path)?;
+                if !still_open(f.write_all(doc.as_bytes()))? {
+                    return Ok(()); // SIGPIPE (split key naming a pipe like /dev/stdout)
+                }
+            }
+        } else if self.stream {
+            self.run_stream_events()?;
+        } else if self.streams() {
+            // per document mode without anything needing to see all documents
+            self.run_streaming()?;
+        } else {
+            // normal, single pass mode on blob of u8 serde_json values passed to jq
+            let input = self.read_input()?;
+            let jq_args = self.jq_args(if self.per_document() { None } else { Some(0) });
+            if self.output == Output::Jq && !self.buffers_output() {
+                // nothing to convert, so pass jq output through as it arrives
+                return self.shellout_streaming(&jq_args, |jq_stdin| {
+                    let _ = jq_stdin.write_all(&input); // jq may stop reading early
+                    Ok(())
+                });
+            }
+            let (stdout, jq_code) = self.shellout_status(&input, &jq_args)?;
+            let stdout = self.postprocess(stdout)?;
+            if self.quiet {
+                // grep style exit code on whether anything truthy came out of jq
+                let docs = self.parse_stdout_docs(&stdout)?;
+                let matched = docs
+                    .iter()
+                    .any(|x| !matches!(x, serde_json::Value::Null | serde_json::Value::Bool(false)));
+                debug!("quiet match: {matched}");
+                return if matched {
+                    Ok(())
+                } else {
+                    Err(Error::Status(1).into())
+                };
+            }
+            if let Some(mode) = self.paths {
+                let mut out = std::io::stdout().lock();
+                for doc in self.parse_stdout_docs(&stdout)? {
+                    let mut leaves = vec![];
+                    leaf_paths(&doc, "", &mut leaves);
+                    for (path, val) in leaves {
+                        let line = match mode {
+                            PathsMode::Plain => path,
+                            PathsMode::Values => format!("{path} = {val}"),
+                            PathsMode::Types => format!("{path}: {}", json_type_name(val)),
+                        };
+                        if !still_open(writeln!(out, "{line}"))? {
+                            return Ok(()); // SIGPIPE
+                        }
+                    }
+                }
+                return Ok(());
+            }
+            if self.count {
+                let docs = self.parse_stdout_docs(&stdout)?;
+                still_open(writeln!(std::io::stdout(), "{}", docs.len()))?;
+                return Ok(());
+            }
+            let output = match (&self.template, &self.output_codec) {
+                (Some(template), _) => self.render_template(template, &stdout)?,
+                (_, Some(codec)) => self.encode_codec(codec, &stdout)?,
+                // Lenient output mode (accept loose jq compact/join style output)
+                _ => self.output(stdout)?,
+            };
+            if let (true, Some(f), true) = (self.in_place, &self.file, self.sops_encrypted) {
+                let output = if self.file_newline(f) {
+                    output + "\n"
+                } else {
+                    output
+                };
+                sops_rewrite(f, output.as_bytes())?;
+            } else if let (true, Some(f)) = (self.in_place, &self.file) {
+                let output = if self.file_newline(f) {
+                    output + "\n"
+                } else {
+                    output
+                };
+                // keep the line endings of files edited on Windows
+                let output = if crlf_file(f) {
+                    output.replace("\r\n", "\n").replace('\n', "\r\n")
+                } else {
+                    output
+                };
+                write_atomic(f, output.as_bytes())?;
+            } else {
+                self.write_output(output)?;
+            }
+            if jq_code != 0 {
+                return Err(Error::JqFailed(jq_code).into());
+            }
+        }
+        Ok(())
+    }
+
+    /// Capture the -y, -t and -j shortcuts in --output
+    ///
+    /// Done manually due to https://github.com/clap-rs/clap/issues/3146
+    fn resolve_output_shortcuts(&mut self) {
+        if self.yaml_output {
+            self.output = Output::Yaml;
+        } else if self.toml_output {
+            self.output = Output::Toml
+        } else if self.json_output {
+            self.output = Output::Json
+        }
+    }
+
+    /// Write a value computed by lq in the --output format
+    fn write_value(&self, value: &serde_json::Value) -> Result<()> {
+        let json = if self.compact_output {
+            serde_json::to_vec(value)?
+        } else {
+            serde_json::to_vec_pretty(value)?
+        };
+        let output = self.output(json)?;
+        self.write_output(output)
+    }
+
+    /// Write converted output to --output-file or stdout
+    fn write_output(&self, output: String) -> Result<()> {
+        let Some(path) = &self.output_file else {
+            // write result to stdout ignoring SIGPIPE errors
+            // https://github.com/rust-lang/rust/issues/46016
+            let newline = if self.final_newline() { "\n" } else { "" };
+            let mut out = std::io::stdout().lock();
+            still_open(write!(out, "{output}{newline}").and_then(|_| out.flush()))?;
+            return Ok(());
+        };
+        debug!("Writing {}", path.display());
+        let contents = if output.is_empty() || !self.final_newline() || !self.file_newline(path) {
+            output
+        } else {
+            output + "\n"
+        };
+        write_atomic(path, contents.as_bytes())
+    }
+
+    /// Evaluate the query against in-memory input, returning the jq results as json values
+    ///
+    /// Runs the same pipeline as the cli (document selection, postprocessing) but collects
+    /// the results rather than writing them out. Non-json jq output (e.g. from -r) is skipped.
+    pub fn eval(&mut self, input: &[u8]) -> Result<Vec<serde_json::Value>, Error> {
+        self.source = Some(input.to_vec());
+        let input = self.read_input()?;
+        let jq_args = self.jq_args(if self.per_document() { None } else { Some(0) });
+        let stdout = self.shellout(&input, &jq_args)?;
+        let stdout = self.postprocess(stdout)?;
+        Ok(self.parse_stdout_docs(&stdout)?)
+    }
+
+    /// Build the query from the --from-file query files or the --query named queries
+    ///
+    /// As in jq -f, the first positional argument is then the input file rather than the query.
+    /// The same goes for a lone file argument with --pick or --omit, which need no query.
+    fn read_query_files(&mut self) -> Result<()> {
+        let picks = !self.pick.is_empty() || !self.omit.is_empty();
+        if picks
+            && self.file.is_none()
+            && self
+                .jq_query
+                .as_deref()
+                .is_some_and(|q| std::path::Path::new(q).is_file())
+        {
+            self.file = self.jq_query.take().map(PathBuf::from);
+        }
+        if self.from_file.is_empty() && self.named_queries.is_empty() {
+            return Ok(());
+        }
+        if self.file.is_some() {
+            anyhow::bail!("only one input file can be given with --from-file or --query");
+        }
+        self.file = self.jq_query.take().map(PathBuf::from);
+        if !self.named_queries.is_empty() {
+            let mut fields = vec![];
+            for named in std::mem::take(&mut self.named_queries) {
+                fields.push(format!(
+                    "{}: ({})",
+                    serde_json::to_string(&named.name)?,
+                    named.query
+                ));
+            }
+            self.jq_query = Some(format!("{{{}}}", fields.join(", ")));
+            return Ok(());
+        }
+        let mut query = String::new();
+        for path in std::mem::take(&mut self.from_file) {
+            let contents = std::fs::read_to_string(&path)
+                .map_err(|e| anyhow::anyhow!("failed to read query file {}: {e}", path.display()))?;
+            query.push_str(&contents);
+            query.push('\n');
+        }
+        self.jq_query = Some(query);
+        Ok(())
+    }
+
+    /// Convert the --argyaml values to json for jq --argjson
+    fn parse_yaml_args(&mut self) -> Result<()> {
+        let mut argyaml = std::mem::take(&mut self.argyaml);
+        for pair in argyaml.chunks_mut(2) {
+            let json = self
+                .yaml_doc_to_json(Deserializer::from_str(&pair[1]))
+                .map_err(|e| anyhow::anyhow!("invalid yaml for --argyaml {}: {e}", pair[0]))?;
+            pair[1] = serde_json::to_string(&json)?;
+        }
+        self.argyaml = argyaml;
+        Ok(())
+    }
+
+    /// Arguments for jq when evaluating the document at doc_index
+    ///
+    /// Without an index, the input is expected to be a stream of [index, document] pairs
+    /// (see --each), which the query is wrapped to destructure into $__doc_index.
+    fn jq_args(&self, doc_index: Option<usize>) -> Vec<String> {
+        let mut args = vec![];
+        if let Some(query) = self.query(doc_index.is_none()) {
+            args.push(query)
+        }
+        args.extend([
+            "--argjson".into(),
+            "__doc_count".into(),
+            self.doc_count.to_string(),
+        ]);
+        if let Some(i) = doc_index {
+            args.extend(["--argjson".into(), "__doc_index".into(), i.to_string()]);
+        }
+        for pair in self.argyaml.chunks(2) {
+            args.push("--argjson".into());
+            args.extend_from_slice(pair);
+        }
+        for arg in &self.arg_env {
+            args.extend(["--arg".into(), arg.name.clone(), arg.value.clone()]);
+        }
+        if self.compact_output {
+            args.push("-c".into());
+        }
+        if self.raw_output {
+            args.push("-r".into());
+        }
+        if self.join_output {
+            args.push("-j".into());
+        }
+        if self.ascii_output {
+            args.push("-a".into());
+        }
+        if self.null_input {
+            args.push("-n".into());
+        }
+        if self.unbuffered {
+            args.push("--unbuffered".into());
+        }
+        if self.jq_seq() {
+            args.push("--seq".into());
+        }
+        if self.stream && self.input != Input::Yaml {
+            // json is passed through as is for jq to stream (yaml events are made by lq)
+            args.push("--stream".into());
+        }
+        for dir in &self.modules {
+            args.push("-L".into());
+            args.push(format!("{}", dir.display()));
+        }
+        if let Some(n) = self.indent {
+            args.extend(["--indent".into(), n.to_string()]);
+        }
+        if self.color_output && self.output == Output::Jq {
+            args.push("-C".into());
+        }
+        if self.exit_status {
+            args.push("-e".into());
+        }
+        args
+    }
+    /// The query for jq, wrapped to only apply to documents matching --select, to sort arrays of its
+    /// results with --sort-arrays-by, and to destructure [index, document] pairs when evaluating
+    /// documents separately
+    fn query(&self, pairs: bool) -> Option<String> {
+        if !pairs && self.select.is_none() && self.sort_arrays_by.is_none() {
+            return self.jq_query.clone();
+        }
+        // module directives must stay at the start of the program
+        let (directives, query) = split_directives(self.jq_query.as_deref().unwrap_or("."));
+        let mut query = format!("({query})");
+        if let Some(select) = &self.select {
+            query = format!("(if any({select}; .) then {query} else . end)");
+        }
+        if let Some(key) = &self.sort_arrays_by {
+            let sorted = format!(
+                "if type == \"array\" and all(.[]; type == \"object\") then sort_by({key}) else . end"
+            );
+            query = format!("({query} | walk({sorted}))");
+        }
+        if pairs && self.annotate_source {
+            // keep the index with each result so it can be traced back to its source
+            query = format!(". as [$__doc_index, $__doc] | [$__doc_index, ($__doc | {query})]");
+        } else if pairs {
+            query = format!(". as [$__doc_index, $__doc] | $__doc | {query}");
+        }
+        Some(format!("{directives}{query}"))
+    }
+
+    fn jq_split_args(&self) -> Option<Vec<String>> {
+        let split_by = &self.split.as_ref()?;
+        let mut args = vec!["-r".into()]; // we expect single unquoted keys
+        args.push(split_by.to_string());
+        Some(args)
+    }
+
+    /// Reader for the input file, or stdin when no file is given
+    ///
+    /// Fails with [`Error::NoInput`] when there is no input to read.
+    fn input_reader(&self) -> Result<Box<dyn Read>> {
+        if let Some(data) = &self.source {
+            skip_bom(std::io::Cursor::new(data.clone()))
+        } else if let Some(f) = &self.file {
+            if !std::path::Path::new(&f).exists() {
+                return Err(Error::NoInput.into());
+            }
+            let file = std::fs::File::open(f)?;
+            // NB: can do everything async (via tokio + tokio_util) except this:
+            // serde only has a sync reader interface, so may as well do all sync.
+            skip_bom(file)
+        } else if self.stdin || !stdin().is_terminal() {
+            debug!("reading from stdin");
+            skip_bom(stdin())
+        } else {
+            Err(Error::NoInput.into())
+        }
+    }
+
+    /// The input file argument if it is an http(s) url
+    fn input_url(&self) -> Option<&str> {
+        let file = self.file.as_ref()?.to_str()?;
+        (file.starts_with("https://") || file.starts_with("http://")).then_some(file)
+    }
+
+    /// Download the input into memory if the input file is an http(s) url
+    #[cfg(feature = "http")]
+    fn fetch_url(&mut self) -> Result<()> {
+        let Some(url) = self.input_url() else {
+            return Ok(());
+        };
+        if self.in_place {
+            anyhow::bail!("cannot edit {url} in place");
+        }
+        debug!("downloading {url}");
+        match http_get(url, &self.headers)? {
+            Some(body) => self.source = Some(body),
+            None => anyhow::bail!("failed to download {url}: the server answered with an error status"),
+        }
+        Ok(())
+    }
+
+    #[cfg(not(feature = "http"))]
+    fn fetch_url(&mut self) -> Result<()> {
+        match self.input_url() {
+            Some(url) => anyhow::bail!("cannot read {url}: lq was built without the http feature"),
+            None => Ok(()),
+        }
+    }
+
+    #[cfg(not(feature = "sops"))]
+    fn decrypt_sops(&mut self) -> Result<()> {
+        if self.sops {
+            anyhow::bail!("--sops requires lq to be built with the sops feature");
+        }
+        Ok(())
+    }
+
+    #[cfg(not(feature = "archive"))]
+    fn read_archive(&mut self) -> Result<()> {
+        if self.archive_glob.is_some() {
+            anyhow::bail!("--archive-glob requires lq to be built with the archive feature");
+        }
+        Ok(())
+    }
+
+    /// Switch input files larger than --max-input-size to per document evaluation with --large-input=each
+    ///
+    /// Other large input fails once it is read (in input_bytes).
+    fn check_input_size(&mut self) -> Result<()> {
+        let (Some(max), LargeInput::Each, None, Some(f)) =
+            (self.max_input_size, self.large_input, &self.source, &self.file)
+        else {
+            return Ok(());
+        };
+        if std::fs::metadata(f).map_or(true, |meta| meta.len() <= max) || self.streams() {
+            return Ok(());
+        }
+        let mut each = self.clone();
+        each.each = true;
+        if !each.streams() {
+            anyhow::bail!(
+                "{} is larger than --max-input-size, and cannot be evaluated per document",
+                f.display()
+            );
+        }
+        debug!("evaluating {} per document", f.display());
+        self.each = true;
+        Ok(())
+    }
+
+    /// Decode input that is not valid utf-8 according to --invalid-utf8
+    fn repair_input_utf8(&mut self) -> Result<()> {
+        #[cfg(feature = "archive")]
+        if self.file.as_deref().and_then(Archive::from_path).is_some() {
+            return Ok(()); // repaired in each member instead
+        }
+        // input for codecs and spreadsheets can be a binary format
+        if self.invalid_utf8 == InvalidUtf8::Error
+            || self.input_codec_name().is_some()
+            || self.input == Input::Xlsx
+        {
+            return Ok(());
+        }
+        let data = self.input_bytes()?.to_vec();
+        self.source = Some(self.repaired_utf8(data));
+        Ok(())
+    }
+
+    /// Raw input with invalid utf-8 decoded according to --invalid-utf8
+    fn repaired_utf8(&self, data: Vec<u8>) -> Vec<u8> {
+        match (String::from_utf8(data), self.invalid_utf8) {
+            (Ok(text), _) => text.into_bytes(),
+            (Err(e), InvalidUtf8::Error) => e.into_bytes(),
+            (Err(e), InvalidUtf8::Replace) => String::from_utf8_lossy(e.as_bytes()).into_owned().into_bytes(),
+            (Err(e), InvalidUtf8::Latin1) => latin1_fallback(e.as_bytes()).into_bytes(),
+        }
+    }
+
+    /// Substitute environment variables in the input when --expand-env is set
+    fn expand_input_env(&mut self) -> Result<()> {
+        #[cfg(feature = "archive")]
+        if self.file.as_deref().and_then(Archive::from_path).is_some() {
+            return Ok(()); // expanded in each member instead
+        }
+        if self.expand_env {
+            let data = self.input_bytes()?.to_vec();
+            self.source = Some(self.expand_env_vars(data)?);
+        }
+        Ok(())
+    }
+
+    /// Substitute environment variables in raw input when --expand-env is set
+    fn expand_env_vars(&self, data: Vec<u8>) -> Result<Vec<u8>> {
+        if !self.expand_env {
+            return Ok(data);
+        }
+        let text =
+            String::from_utf8(data).map_err(|_| anyhow::anyhow!("--expand-env requires utf-8 input"))?;
+        let allowed = (!self.env_allowlist.is_empty()).then_some(&self.env_allowlist[..]);
+        let expanded = expand_vars(&text, allowed, |name| match std::env::var(name) {
+            Ok(value) => Ok(value),
+            Err(_) if self.strict => anyhow::bail!("environment variable {name} is not set"),
+            Err(_) => Ok(String::new()),
+        })?;
+        Ok(expanded.into_bytes())
+    }
+
+    /// Read all input, memory mapping input files unless --no-mmap is set
+    ///
+    /// Fails on input larger than --max-input-size.
+    fn input_bytes(&self) -> Result<Box<dyn std::ops::Deref<Target = [u8]>>> {
+        let max = self.max_input_size.unwrap_or(u64::MAX);
+        let too_large = || anyhow::anyhow!("the input is larger than --max-input-size ({max} bytes)");
+        if let (Some(f), None, false) = (&self.file, &self.source, self.no_mmap) {
+            if let Ok(file) = std::fs::File::open(f) {
+                // SAFETY: the mapping is only read, and --no-mmap exists for files modified concurrently
+                match unsafe { memmap2::Mmap::map(&file) } {
+                    Ok(mmap) if mmap.len() as u64 > max => return Err(too_large()),
+                    Ok(mmap) => return Ok(Box::new(Unmarked(mmap))),
+                    // not everything can be mapped (e.g. process substitution pipes)
+                    Err(e) => debug!("falling back to buffered read of {}: {e}", f.display()),
+                }
+            }
+        }
+        let mut buf = vec![];
+        self.input_reader()?
+            .take(max.saturating_add(1))
+            .read_to_end(&mut buf)?;
+        if buf.len() as u64 > max {
+            return Err(too_large());
+        }
+        Ok(Box::new(buf))
+    }
+
+    /// The --yaml-max-nodes and --yaml-max-depth limits, counting nodes in the given cell
+    fn yaml_limits<'a>(&self, nodes: &'a std::cell::Cell<usize>) -> YamlLimits<'a> {
+        YamlLimits {
+            nodes,
+            max_nodes: self.yaml_max_nodes.unwrap_or(10_000_000),
+            depth: 0,
+            max_depth: self.yaml_max_depth.unwrap_or(128),
+        }
+    }
+
+    /// Convert a single yaml document into json, expanding tags and merge keys
+    fn yaml_doc_to_json(&self, doc: Deserializer) -> Result<serde_json::Value> {
+        let nodes = std::cell::Cell::new(0);
+        let doc = Limited {
+            inner: doc,
+            limits: self.yaml_limits(&nodes),
+        };
+        let mut yaml_doc = <serde_yaml::Value as serde::Deserialize>::deserialize(doc)?;
+        if self.strict {
+            check_strict_yaml(&yaml_doc, "")?;
+        }
+        yaml_doc.apply_merge()?;
+        yaml_to_json(yaml_doc)
+    }
+
+    fn read_yaml_docs(&mut self) -> Result<Vec<serde_json::Value>> {
+        let data = self.input_bytes()?;
+        if yaml_is_blank(&data) {
+            // serde_yaml reads a null document from empty input
+            debug!("found no documents");
+            return Ok(vec![]);
+        }
+        let yaml_de = Deserializer::from_slice(&data);
+        let mut docs: Vec<serde_json::Value> = vec![];
+        for doc in yaml_de {
+            docs.push(self.yaml_doc_to_json(doc).map_err(|e| self.located(e, &data))?);
+        }
+        debug!("found {} documents", docs.len());
+        self.validate_kubernetes(&docs)?;
+        Ok(docs)
+    }
+
+    fn read_toml(&mut self) -> Result<serde_json::Value> {
+        let data = self.input_bytes()?;
+        let doc_as = toml_to_json(std::str::from_utf8(&data)?).map_err(|e| self.located(e, &data))?;
+        self.validate_kubernetes(std::slice::from_ref(&doc_as))?;
+        Ok(doc_as)
+    }
+
+    fn read_toml_docs(&mut self) -> Result<Vec<serde_json::Value>> {
+        let toml = self.read_toml()?;
+        // TODO: need toml crate to support multidoc +++ or something
+        // see https://github.com/toml-lang/toml/issues/511
+        // see https://github.com/toml-lang/toml/issues/583
+        Ok(vec![toml]) // assume single document for now
+    }
+
+    fn read_workbook(&mut self) -> Result<serde_json::Value> {
+        if self.in_place {
+            anyhow::bail!("cannot edit spreadsheets in place");
+        }
+        let data = self.input_bytes()?.to_vec();
+        workbook_to_json(data, self.sheet.as_deref())
+    }
+
+    fn read_workbook_docs(&mut self) -> Result<Vec<serde_json::Value>> {
+        Ok(vec![self.read_workbook()?])
+    }
+
+    fn read_csv(&mut self) -> Result<serde_json::Value> {
+        let data = self.input_bytes()?;
+        let records = parse_csv(std::str::from_utf8(&data)?, self.delimiter, self.quote_char)
+            .map_err(|e| self.located(e, &data))?;
+        let mut rows = records.into_iter().map(|record| {
+            serde_json::Value::Array(record.into_iter().map(serde_json::Value::String).collect())
+        });
+        let mut doc = if self.no_headers {
+            serde_json::Value::Array(rows.collect())
+        } else {
+            let headers: Vec<String> = match rows.next() {
+                Some(serde_json::Value::Array(header)) => header
+                    .into_iter()
+                    .map(|h| h.as_str().unwrap_or_default().to_string())
+                    .collect(),
+                _ => vec![],
+            };
+            // a repeated column would silently replace the earlier one in each row object
+            let mut seen = std::collections::HashSet::new();
+            if let Some(duplicate) = headers.iter().find(|h| !seen.insert(*h)) {
+                anyhow::bail!("invalid csv: duplicate column {duplicate:?} in the header row (read rows as arrays with --no-headers)");
+            }
+            let objects = rows.map(|row| match row {
+                serde_json::Value::Array(cells) => {
+                    serde_json::Value::Object(headers.iter().cloned().zip(cells).collect())
+                }
+                row => row,
+            });
+            serde_json::Value::Array(objects.collect())
+        };
+        if !self.no_type_inference {
+            coerce_types(&mut doc, &[]);
+        }
+        Ok(doc)
+    }
+
+    fn read_csv_docs(&mut self) -> Result<Vec<serde_json::Value>> {
+        Ok(vec![self.read_csv()?])
+    }
+
+    fn read_xml(&mut self) -> Result<serde_json::Value> {
+        let data = self.input_bytes()?;
+        let text = std::str::from_utf8(&data)?;
+        let doc = xml_to_json(text, &self.xml_style()).map_err(|e| self.located(e, &data))?;
+        self.validate_kubernetes(std::slice::from_ref(&doc))?;
+        Ok(doc)
+    }
+
+    fn read_xml_docs(&mut self) -> Result<Vec<serde_json::Value>> {
+        Ok(vec![self.read_xml()?])
+    }
+
+    fn read_edn(&mut self) -> Result<serde_json::Value> {
+        Ok(collapse_docs(self.read_edn_docs()?))
+    }
+
+    fn read_edn_docs(&mut self) -> Result<Vec<serde_json::Value>> {
+        let data = self.input_bytes()?;
+        let text = std::str::from_utf8(&data)?;
+        let docs = edn_to_json(text).map_err(|e| self.located(e, &data))?;
+        debug!("found {} documents", docs.len());
+        self.validate_kubernetes(&docs)?;
+        Ok(docs)
+    }
+
+    fn read_kdl(&mut self) -> Result<serde_json::Value> {
+        let data = self.input_bytes()?;
+        let text = std::str::from_utf8(&data)?;
+        let doc = kdl_to_json(text).map_err(|e| self.located(e, &data))?;
+        self.validate_kubernetes(std::slice::from_ref(&doc))?;
+        Ok(doc)
+    }
+
+    /// The json mapping of xml given by the --xml-* flags
+    fn xml_style(&self) -> XmlStyle {
+        XmlStyle {
+            attribute_prefix: self.xml_attribute_prefix.clone(),
+            text_key: self.xml_text_key.clone(),
+            strip_namespaces: self.xml_strip_namespaces,
+            force_list: self.xml_force_list.clone(),
+        }
+    }
+
+    fn read_json(&mut self) -> Result<serde_json::Value> {
+        let data = self.input_bytes()?;
+        let json_value = match self.parse_json(&data) {
+            // sequence errors are relative to their json text rather than the input
+            Err(e) if !self.seq => return Err(self.located(e, &data)),
+            res => res?,
+        };
+        match &json_value {
+            serde_json::Value::Array(docs) => self.validate_kubernetes(docs)?,
+            doc => self.validate_kubernetes(std::slice::from_ref(doc))?,
+        }
+        Ok(json_value)
+    }
+
+    fn parse_json(&self, data: &[u8]) -> Result<serde_json::Value> {
+        if self.seq {
+            // RFC 7464 sequences are returned as an array of their json texts
+            let mut docs = vec![];
+            for text in data.split(|b| *b == RS) {
+                if text.iter().all(u8::is_ascii_whitespace) {
+                    continue;
+                }
+                let text_args = Args {
+                    seq: false,
+                    strict: self.strict,
+                    ..Default::default()
+                };
+                match text_args.parse_json(text) {
+                    Ok(doc) => docs.push(doc),
+                    // truncated texts should be skipped as per the rfc
+                    Err(e) if !self.strict => warn!("skipping invalid json text in sequence: {e}"),
+                    Err(e) => return Err(e),
+                }
+            }
+            return Ok(docs.into());
+        }
+        let stream = serde_json::Deserializer::from_slice(data);
+        let mut values = if self.strict {
+            stream
+                .into_iter::<StrictJson>()
+                .map(|x| x.map(|x| x.0))
+                .collect::<Result<Vec<_>, _>>()?
+        } else {
+            stream.into_iter().collect::<Result<Vec<_>, _>>()?
+        };
+        match values.len() {
+            // report empty input like a single value would
+            0 => Ok(serde_json::from_slice(data)?),
+            1 => Ok(values.remove(0)),
+            // back to back values (as jq reads them) are a multidoc stream of one document each
+            _ => Ok(values.into()),
+        }
+    }
+
+    // multidoc equivalent reader interface for json
+    fn read_json_docs(&mut self) -> Result<Vec<serde_json::Value>> {
+        let json = self.read_json()?;
+        // outermost is array? equivalent to multidoc
+        if let serde_json::Value::Array(ary) = json {
+            return Ok(ary);
+        }
+        // otherwise, it's 1 doc
+        Ok(vec![json])
+    }
+
+    fn read_input(&mut self) -> Result<Vec<u8>> {
+        if self.raw_input_slurp {
+            let text = String::from_utf8(self.input_bytes()?.to_vec())
+                .map_err(|_| anyhow::anyhow!("--raw-input-slurp requires utf-8 input"))?;
+            self.doc_count = 1;
+            let mut ser = serde_json::to_vec(&text)?;
+            if self.jq_seq() {
+                ser.insert(0, RS);
+            }
+            return Ok(ser);
+        }
+        if self.per_document() {
+            // pass documents as a stream of [index, document] pairs so jq evaluates them one at a time
+            let mut ser = vec![];
+            for (i, doc) in self.read_input_multidoc()?.iter().enumerate() {
+                if self.jq_seq() {
+                    ser.push(RS);
+                }
+                serde_json::to_writer(&mut ser, &(i, doc))?;
+                ser.push(b'\n');
+            }
+            debug!("input decoded as json stream: {}", String::from_utf8_lossy(&ser));
+            return Ok(ser);
+        }
+        let mut ser = match self.input {
+            // yaml is multidoc parsed by default, so flatten when <2 docs to conform to jq interface
+            Input::Yaml => {
+                let docs = self.read_input_multidoc()?;
+                serde_json::to_vec(&self.collapse_input(docs))?
+            }
+            // other formats are passed verbatim unless we need to operate on their documents
+            _ if self.selects_docs() => {
+                let docs = self.read_input_multidoc()?;
+                serde_json::to_vec(&self.collapse_input(docs))?
+            }
+            Input::Toml => serde_json::to_vec(&self.read_toml()?)?,
+            Input::Json => serde_json::to_vec(&self.read_json()?)?,
+            Input::Xlsx => serde_json::to_vec(&self.read_workbook()?)?,
+            Input::Csv => serde_json::to_vec(&self.read_csv()?)?,
+            Input::Xml => serde_json::to_vec(&self.read_xml()?)?,
+            Input::Edn => serde_json::to_vec(&self.read_edn()?)?,
+            Input::Kdl => serde_json::to_vec(&self.read_kdl()?)?,
+        };
+        self.doc_count = self.doc_count.max(1);
+        if self.jq_seq() {
+            ser.insert(0, RS);
+        }
+        debug!("input decoded as json: {}", String::from_utf8_lossy(&ser));
+        Ok(ser)
+    }
+    fn read_input_multidoc(&mut self) -> Result<Vec<serde_json::Value>> {
+        let ser = match self.input {
+            Input::Yaml => self.read_yaml_docs()?,
+            Input::Toml => self.read_toml_docs()?,
+            Input::Json => self.read_json_docs()?,
+            Input::Xlsx => self.read_workbook_docs()?,
+            Input::Csv => self.read_csv_docs()?,
+            Input::Xml => self.read_xml_docs()?,
+            Input::Edn => self.read_edn_docs()?,
+            Input::Kdl => vec![self.read_kdl()?],
+        };
+        //debug!("input decoded as json: {}", String::from_utf8_lossy(&ser));
+        if ser.is_empty() && self.empty_input == EmptyInput::Error {
+            anyhow::bail!("the input has no documents");
+        }
+        let (origins, docs): (Vec<_>, Vec<_>) = self.select_docs(ser)?.into_iter().unzip();
+        self.doc_count = docs.len();
+        self.doc_origins = origins;
+        Ok(docs)
+    }
+
+    /// Point a parse error of the input at its location, with a snippet of the offending line
+    ///
+    /// Errors without a location (and from other sources than the parsers) are returned as is.
+    fn located(&self, err: anyhow::Error, data: &[u8]) -> anyhow::Error {
+        self.located_at(err, data, 0)
+    }
+
+    /// Locate a parse error like located, in `data` starting after `first_line` lines of the input
+    fn located_at(&self, err: anyhow::Error, data: &[u8], first_line: usize) -> anyhow::Error {
+        let location = if let Some(e) = err.downcast_ref::<serde_yaml::Error>() {
+            // an error we cannot place is still reported as invalid input, just without a snippet
+            Some(yaml_error_location(e, data).unwrap_or_else(|| (0, 0, strip_location(&e.to_string()))))
+        } else if let Some(e) = err.downcast_ref::<serde_json::Error>() {
+            (e.line() > 0).then(|| {
+                let (mut line, mut column) = (e.line(), e.column());
+                if column == 0 && line > 1 {
+                    // serde_json puts errors found at a newline in column 0 of the next line
+                    line -= 1;
+                    let text = String::from_utf8_lossy(data);
+                    column = text.lines().nth(line - 1).map_or(0, |l| l.chars().count()) + 1;
+                }
+                (line, column, strip_location(&e.to_string()))
+            })
+        } else if let Some(e) = err.downcast_ref::<SyntaxError>() {
+            Some(e.location(data))
+        } else if self.input == Input::Xml {
+            xml_error_location(&err)
+        } else {
+            toml_error_location(&err, data)
+        };
+        let Some((line, column, message)) = location else {
+            return err;
+        };
+        let name = self
+            .file
+            .as_ref()
+            .map_or_else(|| "stdin".to_string(), |f| f.display().to_string());
+        let (line, snippet) = match line {
+            0 => (0, String::new()),
+            line => {
+                let text = String::from_utf8_lossy(data);
+                let source = text.lines().nth(line - 1).unwrap_or_default();
+                let line = first_line + line;
+                (line, error_snippet(&name, source, line, column))
+            }
+        };
+        Error::Parse {
+            format: self.input,
+            name,
+            line,
+            column,
+            message,
+            snippet,
+        }
+        .into()
+    }
+
+    /// Flatten the input documents for jq, standing in for no documents according to --empty-input
+    fn collapse_input(&self, docs: Vec<serde_json::Value>) -> serde_json::Value {
+        match (docs.is_empty(), self.empty_input) {
+            (true, EmptyInput::Null) => serde_json::Value::Null,
+            _ => collapse_docs(docs),
+        }
+    }
+
+    /// Where the selected document at index i came from, for --annotate-source
+    fn source_label(&self, i: usize) -> String {
+        let origin = self.doc_origins.get(i).copied().unwrap_or(i);
+        let file = self
+            .file
+            .as_ref()
+            .map_or_else(|| "stdin".to_string(), |f| f.display().to_string());
+        match self.member_sources.get(origin) {
+            Some(member) => format!("{file}:{member}"),
+            None => format!("{file} (doc {origin})"),
+        }
+    }
+
+    /// Whether jq reads and writes json text sequences
+    fn jq_seq(&self) -> bool {
+        self.seq && self.output == Output::Jq
+    }
+
+    /// Whether the query is evaluated against each document separately
+    fn per_document(&self) -> bool {
+        self.each || self.select.is_some() || self.annotate_source
+    }
+
+    /// Whether documents are filtered or transformed before being passed to jq
+    fn selects_docs(&self) -> bool {
+        (self.seq && self.input == Input::Json)
+            || self.doc.is_some()
+            || self.unwrap_list
+            || self.merge_docs.is_some()
+            || !self.redact.is_empty()
+            || self.delete_keys.is_some()
+            || self.k8s_clean
+            || self.set_namespace.is_some()
+            || !self.add_label.is_empty()
+            || !self.add_annotation.is_empty()
+            || self.rename_keys.is_some()
+            || !self.pick.is_empty()
+            || !self.omit.is_empty()
+            || self.coerce_types
+            || self.defaults.is_some()
+    }
+
+    /// Filter documents according to the document selection arguments
+    ///
+    /// Selected documents are paired with the index of the input document they came from.
+    fn select_docs(&self, docs: Vec<serde_json::Value>) -> Result<Vec<(usize, serde_json::Value)>> {
+        let docs: Vec<_> = if self.unwrap_list {
+            docs.into_iter()
+                .enumerate()
+                .flat_map(|(i, doc)| unwrap_list(doc).into_iter().map(move |item| (i, item)))
+                .collect()
+        } else {
+            docs.into_iter().enumerate().collect()
+        };
+        let docs: Vec<_> = match &self.doc {
+            Some(selection) => {
+                let count = docs.len();
+                let selected: Vec<_> = docs
+                    .into_iter()
+                    .enumerate()
+                    .filter(|(i, _)| selection.contains(*i))
+                    .map(|(_, doc)| doc)
+                    .collect();
+                if selected.is_empty() && count > 0 {
+                    anyhow::bail!("--doc selects none of the {count} input documents");
+                }
+                selected
+            }
+            None => docs,
+        };
+        let docs = match self.merge_docs {
+            Some(mode) => {
+                let mut merged = serde_json::json!({});
+                for (_, doc) in docs {
+                    match mode {
+                        MergeMode::Deep => deep_merge(&mut merged, doc),
+                        MergeMode::Shallow => shallow_merge(&mut merged, doc),
+                    }
+                }
+                vec![(0, merged)]
+            }
+            None => docs,
+        };
+        Ok(docs
+            .into_iter()
+            .map(|(i, doc)| (i, self.transformed(doc)))
+            .collect())
+    }
+
+    /// Apply the per document transformations: --defaults, --k8s-clean, --set-namespace, --add-label,
+    /// --add-annotation, --redact, --delete-keys, --pick, --omit, --coerce-types and --rename-keys
+    fn transformed(&self, mut doc: serde_json::Value) -> serde_json::Value {
+        if let Some(defaults) = &self.default_values {
+            // empty documents are filled in entirely
+            let mut merged = defaults.clone();
+            if !doc.is_null() {
+                deep_merge(&mut merged, doc);
+            }
+            doc = merged;
+        }
+        if self.k8s_clean {
+            k8s_clean(&mut doc);
+        }
+        if let Some(namespace) = &self.set_namespace {
+            set_namespace(&mut doc, namespace);
+        }
+        if !self.add_label.is_empty() || !self.add_annotation.is_empty() {
+            add_metadata(
+                &mut doc,
+                &self.add_label,
+                &self.add_annotation,
+                self.pod_templates,
+            );
+        }
+        if !self.redact.is_empty() {
+            let patterns: Vec<_> = self.redact.iter().map(|p| p.to_lowercase()).collect();
+            redact(&mut doc, &patterns);
+        }
+        if let Some(pattern) = &self.delete_keys {
+            delete_keys(&mut doc, pattern);
+        }
+        if !self.pick.is_empty() {
+            let paths: Vec<_> = self.pick.iter().map(|p| p.0.as_slice()).collect();
+            doc = pick_paths(&doc, &paths).unwrap_or_default();
+        }
+        if !self.omit.is_empty() {
+            let paths: Vec<_> = self.omit.iter().map(|p| p.0.as_slice()).collect();
+            omit_paths(&mut doc, &paths);
+        }
+        if self.coerce_types {
+            let excludes: Vec<_> = self.coerce_exclude.iter().map(|p| p.0.as_slice()).collect();
+            coerce_types(&mut doc, &excludes);
+        }
+        if let Some(case) = self.rename_keys {
+            rename_keys(&mut doc, case);
+        }
+        doc
+    }
+
+    /// Arguments for reading a file other than the main input, guessing its format from the extension
+    fn file_args(&self, path: &std::path::Path) -> Result<Args> {
+        if !path.exists() {
+            anyhow::bail!("{} does not exist", path.display());
+        }
+        let mut args = self.clone();
+        args.input = Input::from_path(path).unwrap_or(self.input);
+        args.file = Some(path.into());
+        Ok(args)
+    }
+
+    /// Read all documents from a file, guessing its format from the extension
+    fn read_file_docs(&self, path: &std::path::Path) -> Result<Vec<serde_json::Value>> {
+        self.file_args(path)?.read_input_multidoc()
+    }
+
+    /// Re-serialize a file in its own format with sorted keys
+    fn normalize_file(&self, path: &std::path::Path) -> Result<String> {
+        let mut args = self.file_args(path)?;
+        let normalized = match args.input {
+            Input::Yaml => {
+                let docs = args
+                    .read_yaml_docs()?
+                    .into_iter()
+                    .map(|doc| serde_yaml::to_string(&sort_keys(doc)))
+                    .collect::<Result<Vec<_>, _>>()?;
+                docs.join("---\n")
+            }
+            Input::Json => serde_json::to_string_pretty(&sort_keys(args.read_json()?))? + "\n",
+            Input::Toml => json_to_toml(&sort_keys(args.read_toml()?))?,
+            Input::Xlsx => anyhow::bail!("cannot write spreadsheets ({})", path.display()),
+            Input::Csv => {
+                let rows = args.read_csv()?.as_array().cloned().unwrap_or_default();
+                csv_table(rows, args.delimiter, args.quote_char, !args.no_headers)?
+            }
+            Input::Xml => json_to_xml(&sort_keys(args.read_xml()?), &args.xml_style())?,
+            Input::Edn => anyhow::bail!("cannot write edn ({})", path.display()),
+            Input::Kdl => json_to_kdl(&sort_keys(args.read_kdl()?))?,
+        };
+        Ok(normalized)
+    }
+
+    /// Delete jq paths from every document via `del`
+    fn delete_paths(&self, docs: Vec<serde_json::Value>, paths: &[String]) -> Result<Vec<serde_json::Value>> {
+        if paths.is_empty() {
+            return Ok(docs);
+        }
+        let filter = format!("map(del({}))", paths.join(", "));
+        let stdout = self.shellout(&serde_json::to_vec(&docs)?, &["-c".into(), filter])?;
+        Ok(serde_json::from_slice(&stdout)?)
+    }
+
+    fn run_command(&self, cmd: &Command) -> Result<()> {
+        match cmd {
+            Command::Equal { a, b, ignore_paths } => {
+                let left = self.delete_paths(self.read_file_docs(a)?, ignore_paths)?;
+                let right = self.delete_paths(self.read_file_docs(b)?, ignore_paths)?;
+                let mut diffs = vec![];
+                diff_paths(
+                    &normalize_numbers(collapse_docs(left)),
+                    &normalize_numbers(collapse_docs(right)),
+                    "",
+                    &mut diffs,
+                );
+                for diff in &diffs {
+                    still_open(writeln!(std::io::stdout(), "{diff}"))?;
+                }
+                debug!("found {} differences", diffs.len());
+                if !diffs.is_empty() {
+                    return Err(Error::Status(1).into());
+                }
+            }
+            Command::Normalize {
+                files,
+                in_place,
+                check,
+            } => {
+                let mut unnormalized = 0;
+                for file in files {
+                    let normalized = self.normalize_file(file)?;
+                    if *check {
+                        if std::fs::read_to_string(file)? != normalized {
+                            still_open(writeln!(std::io::stdout(), "{}", file.display()))?;
+                            unnormalized += 1;
+                        }
+                    } else if *in_place {
+                        debug!("Writing {}", file.display());
+                        write_atomic(file, normalized.as_bytes())?;
+                    } else {
+                        still_open(write!(std::io::stdout(), "{normalized}"))?;
+                    }
+                }
+                if unnormalized > 0 {
+                    return Err(Error::Status(1).into());
+                }
+            }
+            Command::Hash { files } => {
+                use sha2::{Digest, Sha256};
+                for file in files {
+                    let canonical = sort_keys(normalize_numbers(collapse_docs(self.read_file_docs(file)?)));
+                    let digest = Sha256::digest(serde_json::to_vec(&canonical)?);
+                    let hex: String = digest.iter().map(|b| format!("{b:02x}")).collect();
+                    still_open(writeln!(std::io::stdout(), "{hex}  {}", file.display()))?;
+                }
+            }
+            Command::Completions { shell } => {
+                let mut cmd = <Self as clap::CommandFactory>::command();
+                let name = cmd.get_name().to_string();
+                // generate panics on write errors, so buffer the script to survive a closed stdout
+                let mut script = vec![];
+                clap_complete::generate(*shell, &mut cmd, name, &mut script);
+                still_open(std::io::stdout().write_all(&script))?;
+            }
+            Command::Merge {
+                files,
+                three_way,
+                prefer,
+            } => {
+                let mut docs = vec![];
+                for file in files {
+                    docs.push(collapse_docs(self.read_file_docs(file)?));
+                }
+                let mut conflicts = vec![];
+                let merged = if *three_way {
+                    let [base, ours, theirs] = <[_; 3]>::try_from(docs)
+                        .map_err(|_| anyhow::anyhow!("--three-way takes the files base, ours and theirs"))?;
+                    merge_three_way(Some(&base), Some(ours), Some(theirs), *prefer, "", &mut conflicts)
+                        .unwrap_or_default()
+                } else {
+                    docs.into_iter().fold(serde_json::json!({}), |mut merged, doc| {
+                        deep_merge(&mut merged, doc);
+                        merged
+                    })
+                };
+                self.write_value(&merged)?;
+                for path in &conflicts {
+                    eprintln!("conflict at {path}");
+                }
+                if !conflicts.is_empty() && prefer.is_none() {
+                    return Err(Error::Status(1).into());
+                }
+            }
+            Command::Values { files, set } => {
+                let mut values = serde_json::json!({});
+                for file in files {
+                    helm_merge(&mut values, collapse_docs(self.read_file_docs(file)?));
+                }
+                for assignments in set {
+                    helm_set(&mut values, assignments)?;
+                }
+                self.write_value(&values)?;
+            }
+            Command::K8s {
+                command: K8sCommand::Images { files, set, in_place },
+            } => self.k8s_images(files, set, *in_place)?,
+            Command::Find {
+                files,
+                value_regex,
+                key_regex,
+                values,
+            } => {
+                let mut inputs = vec![];
+                if files.is_empty() {
+                    inputs.push((String::new(), collapse_docs(self.clone().read_input_multidoc()?)));
+                }
+                for file in files {
+                    let prefix = if files.len() > 1 {
+                        format!("{}:", file.display())
+                    } else {
+                        String::new()
+                    };
+                    inputs.push((prefix, collapse_docs(self.read_file_docs(file)?)));
+                }
+                let mut found = 0;
+                for (prefix, doc) in &inputs {
+                    let mut matches = vec![];
+                    find_matches(
+                        doc,
+                        "",
+                        None,
+                        key_regex.as_ref(),
+                        value_regex.as_ref(),
+                        &mut matches,
+                    );
+                    for (path, value) in matches {
+                        let path = if path.is_empty() { "." } else { &path };
+                        let line = if *values {
+                            format!("{prefix}{path} = {value}")
+                        } else {
+                            format!("{prefix}{path}")
+                        };
+                        still_open(writeln!(std::io::stdout(), "{line}"))?;
+                        found += 1;
+                    }
+                }
+                debug!("found {found} matches");
+                if found == 0 {
+                    return Err(Error::Status(1).into());
+                }
+            }
+            Command::Table {
+                query,
+                file,
+                columns,
+                no_headers,
+            } => self.print_table(query, file.as_deref(), columns, !no_headers)?,
+            Command::Repl { file } => self.run_repl(file.as_deref())?,
+            Command::Serve { listen } => self.run_serve(listen)?,
+        }
+        Ok(())
+    }
+
+    /// Run a query and print its results as a table for lq table
+    fn print_table(
+        &self,
+        query: &Option<String>,
+        file: Option<&std::path::Path>,
+        columns: &[String],
+        headers: bool,
+    ) -> Result<()> {
+        let mut args = match file {
+            Some(path) => self.file_args(path)?,
+            None => self.clone(),
+        };
+        args.jq_query = query.clone();
+        args.raw_output = false;
+        let input = args.read_input()?;
+        let jq_args = args.jq_args(if args.per_document() { None } else { Some(0) });
+        let results = args.parse_stdout_docs(&args.shellout(&input, &jq_args)?)?;
+        let table = text_table(&table_rows(results)?, columns, headers);
+        still_open(write!(std::io::stdout(), "{table}"))?;
+        Ok(())
+    }
+
+    #[cfg(not(feature = "repl"))]
+    fn run_repl(&self, _: Option<&std::path::Path>) -> Result<()> {
+        anyhow::bail!("lq repl requires lq to be built with the repl feature")
+    }
+
+    #[cfg(not(feature = "serve"))]
+    fn run_serve(&self, _: &str) -> Result<()> {
+        anyhow::bail!("lq serve requires lq to be built with the serve feature")
+    }
+
+    /// Pass json encoded bytes to jq with arguments for jq
+    fn shellout(&self, input: &[u8], args: &[String]) -> Result<Vec<u8>> {
+        Ok(self.shellout_status(input, args)?.0)
+    }
+
+    /// Pass json encoded bytes to jq like shellout, also returning the --exit-status code of jq
+    fn shellout_status(&self, input: &[u8], args: &[String]) -> Result<(Vec<u8>, i32)> {
+        debug!("jq args: {:?}", &args);
+        if let Some(engine) = self.query_engine() {
+            return engine::run_engine(engine, input, args);
+        }
+        let output = run_jq(self.jq_program(), args, input)?;
+        let code = self.jq_exit_code(output.status)?;
+        debug!("jq stdout: {}", String::from_utf8_lossy(&output.stdout));
+        Ok((output.stdout, code))
+    }
+
+    /// The exit code of a finished jq, failing with it unless it reports on the results for --exit-status
+    fn jq_exit_code(&self, status: std::process::ExitStatus) -> Result<i32> {
+        match status.code() {
+            Some(0) => Ok(0),
+            Some(code @ (1 | 4)) if self.exit_status => Ok(code),
+            Some(code) => Err(Error::JqFailed(code).into()),
+            None => anyhow::bail!("arguments rejected by jq: {status}"),
+        }
+    }
+
+    /// Run jq like shellout, but with a deadline and a cap on the output read, failing with jq's errors
+    ///
+    /// For evaluating arbitrary queries on behalf of others (lq repl, lq serve) without hanging on them.
+    /// With `clear_env`, jq runs without our environment, for queries from untrusted callers.
+    #[cfg(any(feature = "repl", feature = "serve"))]
+    fn shellout_bounded(
+        &self,
+        input: &[u8],
+        args: &[String],
+        timeout: std::time::Duration,
+        limit: u64,
+        clear_env: bool,
+    ) -> Result<Vec<u8>> {
+        debug!("jq args: {:?}", &args);
+        let mut child = spawn_jq(self.jq_program(), args, std::process::Stdio::piped(), clear_env)?;
+        let mut stdin = child.stdin.take().unwrap();
+        let stdout = child.stdout.take().unwrap();
+        let stderr = child.stderr.take().unwrap();
+        let (stdout, stderr, status) = std::thread::scope(|scope| {
+            scope.spawn(move || {
+                // jq can exit without reading everything (e.g. on errors), reported by its status
+                let _ = stdin.write_all(input);
+            });
+            let stdout = scope.spawn(move || {
+                let mut buf = vec![];
+                let _ = stdout.take(limit).read_to_end(&mut buf);
+                buf
+            });
+            let stderr = scope.spawn(move || {
+                let mut buf = String::new();
+                let _ = stderr.take(limit).read_to_string(&mut buf);
+                buf
+            });
+            let deadline = std::time::Instant::now() + timeout;
+            let status = loop {
+                match child.try_wait() {
+                    Ok(Some(status)) => break Some(status),
+                    Ok(None) if std::time::Instant::now() < deadline => {
+                        std::thread::sleep(std::time::Duration::from_millis(5))
+                    }
+                    _ => {
+                        let _ = child.kill();
+                        let _ = child.wait();
+                        break None;
+                    }
+                }
+            };
+            (stdout.join().unwrap(), stderr.join().unwrap(), status)
+        });
+        match status {
+            None => anyhow::bail!("timed out after {}s", timeout.as_secs()),
+            Some(status) if !status.success() => match stderr.trim() {
+                "" => anyhow::bail!("jq failed: {status}"),
+                msg => anyhow::bail!("{msg}"),
+            },
+            Some(_) => Ok(stdout),
+        }
+    }
+
+    /// Whether the output should be terminated by a newline (not with -j or --no-newline)
+    fn final_newline(&self) -> bool {
+        !self.no_newline && !self.join_output
+    }
+
+    /// Whether a file written by lq should end with a newline, according to --final-newline
+    fn file_newline(&self, path: &std::path::Path) -> bool {
+        match self.newline_policy {
+            FinalNewline::Always => true,
+            FinalNewline::Never => false,
+            FinalNewline::Preserve => ends_with_newline(path).unwrap_or(true),
+        }
+    }
+
+    /// Whether jq output must be collected in full before anything is written
+    fn buffers_output(&self) -> bool {
+        (self.no_newline && self.output != Output::Jq)
+            || self.template.is_some()
+            || self.output_codec.is_some()
+            || self.in_place
+            || self.output_file.is_some()
+            || self.unique
+            || self.unique_by.is_some()
+            || self.sort_docs_by.is_some()
+            || self.prune.is_some()
+            || self.flow.is_some()
+            || self.quiet
+            || self.exit_status
+            || self.count
+            || self.paths.is_some()
+    }
+
+    /// Whether documents can be streamed through jq one at a time instead of being read up front
+    ///
+    /// Only possible when evaluating documents separately and nothing needs to see the whole stream.
+    fn streams(&self) -> bool {
+        let mentions_count =
+            |expr: &Option<String>| expr.as_deref().unwrap_or_default().contains("$__doc_count");
+        self.per_document()
+            && matches!(self.input, Input::Yaml | Input::Json)
+            && matches!(self.output, Output::Jq | Output::Json | Output::Yaml)
+            && !self.seq
+            && self.split.is_none()
+            && self.kubernetes_validate.is_none()
+            && self.crd.is_empty()
+            && !self.annotate_source
+            && self.merge_docs.is_none()
+            && !self.buffers_output()
+            && !mentions_count(&self.jq_query)
+            && !mentions_count(&self.select)
+            // empty yaml streams look like a null document until all of the input is read
+            && !(self.input == Input::Yaml && self.empty_input == EmptyInput::Error)
+    }
+
+    /// Stream documents through a single jq process, emitting results as jq produces them
+    ///
+    /// Keeps memory use to about one document (raw and parsed for yaml) regardless of input size.
+    fn run_streaming(&self) -> Result<()> {
+        let args = self.jq_args(None);
+        self.shellout_streaming(&args, |jq_stdin| {
+            self.feed_documents(self.input_reader()?, jq_stdin)
+        })
+    }
+
+    /// Feed the documents read from `reader` to jq one at a time, as [index, document] pairs
+    fn feed_documents(&self, reader: impl Read, jq_stdin: &mut dyn Write) -> Result<()> {
+        let (mut read, mut selected) = (0, 0);
+        // feed a document to jq as an [index, document] pair, returning false once jq stops reading
+        let mut feed = |doc: serde_json::Value| -> Result<bool> {
+            let docs = if self.unwrap_list {
+                unwrap_list(doc)
+            } else {
+                vec![doc]
+            };
+            for doc in docs {
+                if self
+                    .doc
+                    .as_ref()
+                    .map_or(true, |selection| selection.contains(read))
+                {
+                    let doc = self.transformed(doc);
+                    let mut line = serde_json::to_vec(&(selected, &doc))?;
+                    line.push(b'\n');
+                    if jq_stdin.write_all(&line).is_err() || (self.unbuffered && jq_stdin.flush().is_err()) {
+                        return Ok(false);
+                    }
+                    selected += 1;
+                }
+                read += 1;
+            }
+            Ok(true)
+        };
+        match self.input {
+            Input::Yaml => {
+                // parse documents as they arrive, as serde_yaml reads a reader in full before the first one
+                let max = self.max_input_size.unwrap_or(u64::MAX);
+                read_yaml_chunks(BufReader::new(reader), max, |chunk, first_line| {
+                    for doc in Deserializer::from_slice(chunk) {
+                        let doc = self
+                            .yaml_doc_to_json(doc)
+                            .map_err(|e| self.located_at(e, chunk, first_line))?;
+                        if !feed(doc)? {
+                            return Ok(false);
+                        }
+                    }
+                    Ok(true)
+                })?;
+            }
+            _ => {
+                let de = serde_json::Deserializer::from_reader(reader);
+                let values: Box<dyn Iterator<Item = serde_json::Result<serde_json::Value>>> = if self.strict {
+                    Box::new(de.into_iter::<StrictJson>().map(|x| x.map(|x| x.0)))
+                } else {
+                    Box::new(de.into_iter())
+                };
+                let mut values = values.enumerate().peekable();
+                while let Some((i, value)) = values.next() {
+                    // a lone top level array is a multidoc stream as in non-streaming mode,
+                    // while every value of a stream of several is a document of its own as in jq
+                    let docs = match value? {
+                        serde_json::Value::Array(docs) if i == 0 && values.peek().is_none() => docs,
+                        doc => vec![doc],
+                    };
+                    if !docs
+                        .into_iter()
+                        .try_fold(true, |fed, doc| Ok::<_, anyhow::Error>(fed && feed(doc)?))?
+                    {
+                        break;
+                    }
+                }
+            }
+        }
+        debug!("streamed {selected} of {read} documents");
+        if read == 0 && self.empty_input == EmptyInput::Error {
+            anyhow::bail!("the input has no documents");
+        }
+        Ok(())
+    }
+
+    /// Evaluate the query again every time the input file changes
+    ///
+    /// Each evaluation runs in a child process so its output replaces the previous one in a single write.
+    #[cfg(feature = "watch")]
+    fn run_watch(&self) -> Result<()> {
+        use notify::Watcher;
+        let Some(file) = self.file.as_ref().filter(|_| self.input_url().is_none()) else {
+            anyhow::bail!("--watch requires a local input file");
+        };
+        let file = std::fs::canonicalize(file)?;
+        let (tx, rx) = std::sync::mpsc::channel();
+        let mut watcher = notify::recommended_watcher(move |event| {
+            let _ = tx.send(event);
+        })?;
+        // watch the directory since editors often replace the file rather than writing to it
+        let dir = file.parent().unwrap_or(std::path::Path::new("/"));
+        watcher.watch(dir, notify::RecursiveMode::NonRecursive)?;
+
+        let mut args = std::env::args_os();
+        let argv0 = args.next().unwrap_or_default();
+        let args: Vec<_> = args.filter(|arg| arg != "--watch").collect();
+        let mut cmd = std::process::Command::new(std::env::current_exe()?);
+        #[cfg(unix)]
+        std::os::unix::process::CommandExt::arg0(&mut cmd, argv0); // keep binary name defaults
+        cmd.args(&args).stdin(std::process::Stdio::null());
+        loop {
+            debug!("evaluating {}", file.display());
+            let output = cmd.output()?;
+            let mut out = std::io::stdout().lock();
+            let clear = if out.is_terminal() {
+                &b"\x1b[H\x1b[2J"[..]
+            } else {
+                b""
+            };
+            if !still_open(
+                out.write_all(&[clear, &output.stdout].concat())
+                    .and_then(|_| out.flush()),
+            )? {
+                return Ok(()); // SIGPIPE
+            }
+            let _ = std::io::stderr().write_all(&output.stderr);
+            // wait for a modification of the file, then let the burst of events from a save settle
+            loop {
+                let event: notify::Event = rx.recv()??;
+                if !event.kind.is_access() && event.paths.iter().any(|p| p == &file) {
+                    break;
+                }
+            }
+            while rx.recv_timeout(std::time::Duration::from_millis(50)).is_ok() {}
+        }
+    }
+
+    #[cfg(not(feature = "watch"))]
+    fn run_watch(&self) -> Result<()> {
+        anyhow::bail!("--watch requires lq to be built with the watch feature")
+    }
+
+    /// Run jq over the streaming event form of the input without materializing documents
+    fn run_stream_events(&self) -> Result<()> {
+        let args = self.jq_args(Some(0));
+        let feed = |jq_stdin: &mut dyn Write| -> Result<()> {
+            match self.input {
+                Input::Yaml => {
+                    let max = self.max_input_size.unwrap_or(u64::MAX);
+                    read_yaml_chunks(BufReader::new(self.input_reader()?), max, |chunk, first_line| {
+                        for doc in Deserializer::from_slice(chunk) {
+                            let mut path = vec![];
+                            let events = StreamEvents {
+                                path: &mut path,
+                                out: &mut *jq_stdin,
+                            };
+                            let nodes = std::cell::Cell::new(0);
+                            let doc = Limited {
+                                inner: doc,
+                                limits: self.yaml_limits(&nodes),
+                            };
+                            if let Err(e) = serde::de::DeserializeSeed::deserialize(events, doc) {
+                                if e.to_string().contains(STREAM_CLOSED) {
+                                    return Ok(false); // jq stopped reading
+                                }
+                                return Err(self.located_at(e.into(), chunk, first_line));
+                            }
+                        }
+                        Ok(true)
+                    })?;
+                }
+                Input::Json => {
+                    let _ = std::io::copy(&mut self.input_reader()?, jq_stdin);
+                }
+                Input::Toml => {
+                    let doc = self.clone().read_toml()?;
+                    let _ = jq_stdin.write_all(&serde_json::to_vec(&doc)?);
+                }
+                Input::Xlsx => {
+                    let doc = self.clone().read_workbook()?;
+                    let _ = jq_stdin.write_all(&serde_json::to_vec(&doc)?);
+                }
+                Input::Csv => {
+                    let doc = self.clone().read_csv()?;
+                    let _ = jq_stdin.write_all(&serde_json::to_vec(&doc)?);
+                }
+                Input::Xml => {
+                    let doc = self.clone().read_xml()?;
+                    let _ = jq_stdin.write_all(&serde_json::to_vec(&doc)?);
+                }
+                Input::Edn => {
+                    for doc in self.clone().read_edn_docs()? {
+                        let _ = jq_stdin.write_all(&serde_json::to_vec(&doc)?);
+                    }
+                }
+                Input::Kdl => {
+                    let doc = self.clone().read_kdl()?;
+                    let _ = jq_stdin.write_all(&serde_json::to_vec(&doc)?);
+                }
+            }
+            Ok(())
+        };
+        if !matches!(
+            self.output,
+            Output::Toml | Output::Html | Output::Csv | Output::Xml | Output::Kdl
+        ) && self.flow.is_none()
+            && self.output_file.is_none()
+        {
+            return self.shellout_streaming(&args, feed);
+        }
+        // toml, html, csv, xml, kdl and flow style yaml output need the whole jq output to convert
+        let mut input = vec![];
+        feed(&mut input)?;
+        let stdout = self.shellout(&input, &args)?;
+        self.write_output(self.output(stdout)?)
+    }
+
+    /// Shellout to jq, emitting its output to stdout as it arrives while `feed` writes its input
+    fn shellout_streaming(
+        &self,
+        args: &[String],
+        feed: impl FnOnce(&mut dyn Write) -> Result<()>,
+    ) -> Result<()> {
+        match self.shellout_streaming_to(args, feed, std::io::stdout())? {
+            0 => Ok(()),
+            code => Err(Error::JqFailed(code).into()),
+        }
+    }
+
+    /// Shellout to jq, writing its output to `out` as it arrives while `feed` writes its input
+    ///
+    /// Returns jq's exit code, which is 0 when `out` is closed before jq is done.
+    fn shellout_streaming_to(
+        &self,
+        args: &[String],
+        feed: impl FnOnce(&mut dyn Write) -> Result<()>,
+        mut out: impl Write + Send,
+    ) -> Result<i32> {
+        debug!("streaming jq args: {:?}", &args);
+        if let Some(engine) = self.query_engine() {
+            // engines evaluate whole batches of documents, so the output follows all of the input
+            let mut input = vec![];
+            feed(&mut input)?;
+            let (stdout, code) = engine::run_engine(engine, &input, args)?;
+            return Ok(if self.emit_stream(&stdout[..], &mut out)? {
+                code
+            } else {
+                0
+            });
+        }
+        let mut child = spawn_jq(self.jq_program(), args, std::process::Stdio::inherit(), false)?;
+        let jq_stdout = child.stdout.take().unwrap();
+        std::thread::scope(|scope| {
+            let emitter = scope.spawn(|| self.emit_stream(jq_stdout, &mut out));
+            let mut jq_stdin = std::io::BufWriter::new(child.stdin.take().unwrap());
+            if let Err(e) = feed(&mut jq_stdin) {
+                let _ = child.kill();
+                return Err(e);
+            }
+            // close jq's stdin (ignoring broken pipes from jq exiting early) and wait for it
+            let _ = jq_stdin.flush();
+            drop(jq_stdin);
+            let status = child.wait()?;
+            let completed = emitter.join().expect("output thread panicked")?;
+            // jq is killed by SIGPIPE when our output closes early, which is not an error
+            if completed {
+                self.jq_exit_code(status)
+            } else {
+                Ok(0)
+            }
+        })
+    }
+
+    /// Write jq output to `out` as it arrives, converting documents one at a time for yaml output
+    ///
+    /// Returns false if `out` was closed before all output was written.
+    fn emit_stream(&self, mut jq_stdout: impl Read, out: &mut impl Write) -> Result<bool> {
+        if self.output == Output::Json {
+            for doc in serde_json::Deserializer::from_reader(BufReader::new(jq_stdout)).into_iter() {
+                let chunk = self.json_string(&doc?)? + "\n";
+                if !self.write_chunk(out, chunk.as_bytes())? {
+                    return Ok(false); // SIGPIPE
+                }
+            }
+            return still_open(out.flush());
+        }
+        if self.output == Output::Jq {
+            // passed through verbatim, holding back a trailing newline with --no-newline
+            let (mut buf, mut held) = ([0; 8192], false);
+            loop {
+                let n = jq_stdout.read(&mut buf)?;
+                if n == 0 {
+                    break;
+                }
+                let mut chunk = &buf[..n];
+                if held && !still_open(out.write_all(b"\n"))? {
+                    return Ok(false); // SIGPIPE
+                }
+                held = false;
+                if self.no_newline {
+                    if let Some(rest) = chunk.strip_suffix(b"\n") {
+                        (chunk, held) = (rest, true);
+                    }
+                }
+                if !self.write_chunk(out, chunk)? {
+                    return Ok(false); // SIGPIPE
+                }
+            }
+            return still_open(out.flush());
+        }
+        // a single result is emitted as is, so hold on to the first until we know there are more
+        let (mut first, mut count) = (None, 0);
+        let stream = serde_json::Deserializer::from_reader(BufReader::new(jq_stdout)).into_iter();
+        for doc in stream {
+            let doc: serde_json::Value = match doc {
+                Ok(doc) => doc,
+                Err(e) if self.strict => return Err(e.into()),
+                Err(_) => break,
+            };
+            count += 1;
+            let chunk = if self.multidoc_output {
+                let sep = if count > 1 { "---\n" } else { "" };
+                format!("{sep}{}", self.yaml_string(&doc)?)
+            } else if count == 1 {
+                first = Some(doc);
+                continue;
+            } else {
+                // emit sequence items one at a time
+                let head = match first.take() {
+                    Some(first) => self.yaml_string(&[first])?,
+                    None => String::new(),
+                };
+                head + &self.yaml_string(&[doc])?
+            };
+            if !self.write_chunk(out, chunk.as_bytes())? {
+                return Ok(false); // SIGPIPE
+            }
+        }
+        let rest = match (count, first) {
+            (0, _) => serde_yaml::to_string(&serde_json::json!({}))?,
+            (_, Some(doc)) => self.yaml_string(&doc)?,
+            _ => String::new(),
+        };
+        still_open(out.write_all(rest.as_bytes()).and_then(|()| out.flush()))
+    }
+
+    /// Write a chunk of streamed output, flushing it with --unbuffered
+    ///
+    /// Returns false if `out` was closed.
+    fn write_chunk(&self, out: &mut impl Write, chunk: &[u8]) -> Result<bool> {
+        still_open(
+            out.write_all(chunk)
+                .and_then(|()| if self.unbuffered { out.flush() } else { Ok(()) }),
+        )
+    }
+
+    /// Render each jq result through the --template file
+    #[cfg(feature = "template")]
+    fn render_template(&self, path: &std::path::Path, stdout: &[u8]) -> Result<String> {
+        use anyhow::Context;
+        let source =
+            std::fs::read_to_string(path).with_context(|| format!("failed to read {}", path.display()))?;
+        let mut env = minijinja::Environment::new();
+        env.set_keep_trailing_newline(true);
+        if self.strict {
+            env.set_undefined_behavior(minijinja::UndefinedBehavior::Strict);
+        }
+        let name = path.to_string_lossy();
+        env.add_template(&name, &source)?;
+        let template = env.get_template(&name)?;
+        let mut rendered = String::new();
+        for doc in self.parse_stdout_docs(stdout)? {
+            let mut context = match &doc {
+                serde_json::Value::Object(map) => map.clone(),
+                _ => serde_json::Map::new(),
+            };
+            context.insert("data".into(), doc);
+            rendered += &template.render(&context)?;
+        }
+        // the final newline is added on output like for other formats
+        Ok(rendered.strip_suffix('\n').unwrap_or(&rendered).to_string())
+    }
+
+    #[cfg(not(feature = "template"))]
+    fn render_template(&self, _: &std::path::Path, _: &[u8]) -> Result<String> {
+        anyhow::bail!("--template requires lq to be built with the template feature")
+    }
+
+    /// Evaluate a jq expression against each document, returning one key per document
+    fn doc_keys(&self, docs: &[serde_json::Value], expr: &str) -> Result<Vec<serde_json::Value>> {
+        let mut input = vec![];
+        for doc in docs {
+            serde_json::to_writer(&mut input, doc)?;
+            input.push(b'\n');
+        }
+        // wrap in an array to get exactly one output line per document
+        let stdout = self.shellout(&input, &["-c".into(), format!("[{expr}]")])?;
+        let keys = self.parse_stdout_docs(&stdout)?;
+        if keys.len() != docs.len() {
+            anyhow::bail!("expected {} keys from {expr:?}, found {}", docs.len(), keys.len());
+        }
+        Ok(keys)
+    }
+
+    /// Reorder, drop or prune documents from the jq output, re-serializing them in jq style
+    fn postprocess(&self, stdout: Vec<u8>) -> Result<Vec<u8>> {
+        if !self.unique && self.unique_by.is_none() && self.sort_docs_by.is_none() && self.prune.is_none() {
+            return Ok(stdout);
+        }
+        let mut docs = self.parse_stdout_docs(&stdout)?;
+        if let Some(mode) = self.prune {
+            docs.iter_mut().for_each(|doc| prune(doc, mode));
+        }
+        if self.unique {
+            let mut seen = std::collections::HashSet::new();
+            docs.retain(|doc| seen.insert(sort_keys(doc.clone()).to_string()));
+        } else if let Some(expr) = &self.unique_by {
+            let keys = self.doc_keys(&docs, expr)?;
+            let mut seen = std::collections::HashSet::new();
+            let mut keys = keys.iter();
+            docs.retain(|_| {
+                keys.next()
+                    .map_or(true, |key| seen.insert(sort_keys(key.clone()).to_string()))
+            });
+        }
+        if let Some(expr) = &self.sort_docs_by {
+            let keys = if expr == "k8s" || expr == "k8s-kind-order" {
+                docs.iter().map(|doc| kind_order(doc).into()).collect()
+            } else {
+                self.doc_keys(&docs, expr)?
+            };
+            let mut keyed: Vec<_> = keys.into_iter().zip(docs).collect();
+            keyed.sort_by(|(a, _), (b, _)| cmp_json(a, b));
+            docs = keyed.into_iter().map(|(_, doc)| doc).collect();
+        }
+        let mut out = vec![];
+        for doc in &docs {
+            if self.jq_seq() {
+                out.push(RS);
+            }
+            if self.compact_output {
+                serde_json::to_writer(&mut out, doc)?;
+            } else {
+                serde_json::to_writer_pretty(&mut out, doc)?;
+            }
+            out.push(b'\n');
+        }
+        if self.ascii_output {
+            out = escape_non_ascii(&String::from_utf8(out)?).into_bytes();
+        }
+        Ok(out)
+    }
+
+    /// Serialize a value as yaml, escaping non-ascii strings with --ascii-output
+    fn yaml_string<T: serde::Serialize>(&self, value: &T) -> Result<String> {
+        let serialize = |v: &serde_json::Value| match self.flow {
+            Some(depth) => flow_yaml(v, depth),
+            None => Ok(serde_yaml::to_string(v)?),
+        };
+        if self.ascii_output {
+            to_ascii_string(&serde_json::to_value(value)?, serialize)
+        } else if self.flow.is_some() {
+            serialize(&serde_json::to_value(value)?)
+        } else {
+            Ok(serde_yaml::to_string(value)?)
+        }
+    }
+
+    /// Serialize a value as json with --indent spaces (or compactly with -c), escaping non-ascii strings with --ascii-output
+    fn json_string(&self, value: &serde_json::Value) -> Result<String> {
+        use serde::Serialize;
+        let indent = self.indent.unwrap_or(2);
+        let mut out = vec![];
+        if self.compact_output || indent == 0 {
+            serde_json::to_writer(&mut out, value)?;
+        } else {
+            let indent = " ".repeat(indent.into());
+            let formatter = serde_json::ser::PrettyFormatter::with_indent(indent.as_bytes());
+            value.serialize(&mut serde_json::Serializer::with_formatter(&mut out, formatter))?;
+        }
+        let json = String::from_utf8(out)?;
+        Ok(if self.ascii_output {
+            escape_non_ascii(&json)
+        } else {
+            json
+        })
+    }
+
+    /// Serialize a value as toml in the --toml-* style, escaping non-ascii strings with --ascii-output
+    fn toml_string(&self, value: &serde_json::Value) -> Result<String> {
+        let sorted;
+        let value = if self.toml_sort_keys {
+            sorted = sort_keys(value.clone());
+            &sorted
+        } else {
+            value
+        };
+        let serialize =
+            |value: &serde_json::Value| json_to_styled_toml(value, self.toml_inline_tables, self.toml_arrays);
+        if self.ascii_output {
+            to_ascii_string(value, serialize)
+        } else {
+            serialize(value)
+        }
+    }
+
+    // Parse jq stdout as a stream of json documents (skipping unparseable output unless --strict)
+    fn parse_stdout_docs(&self, stdout: &[u8]) -> Result<Vec<serde_json::Value>> {
+        let stdout: Vec<u8> = stdout.iter().copied().filter(|b| *b != RS).collect();
+        let stream = serde_json::Deserializer::from_slice(&stdout).into_iter::<serde_json::Value>();
+        let docs = if self.strict {
+            stream.collect::<Result<Vec<_>, _>>()?
+        } else {
+            stream.flatten().collect::<Vec<_>>()
+        };
+        debug!("parsed {} documents", docs.len());
+        Ok(docs)
+    }
+
+    // Convert stdout into one of the Output formats verbatim as a single string
+    fn output(&self, stdout: Vec<u8>) -> Result<String> {
+        self.convert_output(stdout)
+            .map_err(|e| Error::Serialization(e).into())
+    }
+
+    fn convert_output(&self, stdout: Vec<u8>) -> Result<String> {
+        match self.output {
+            // Only jq output is guaranteed to succeed because it's not parsed as a format
+            // if people pass -r to jq, then this can strip formats
+            Output::Jq => {
+                // NB: stdout here is not always json - users can pass -r to jq
+                Ok(String::from_utf8_lossy(&stdout).trim_end().to_string())
+            }
+            // Other outputs are speculatively parsed as the requested formats
+            Output::Yaml if self.annotate_source => {
+                let mut annotated = vec![];
+                for result in self.parse_stdout_docs(&stdout)? {
+                    let (i, doc): (usize, serde_json::Value) = serde_json::from_value(result)?;
+                    annotated.push(format!(
+                        "# source: {}\n{}",
+                        self.source_label(i),
+                        self.yaml_string(&doc)?
+                    ));
+                }
+                Ok(annotated.join("---\n").trim_end().to_string())
+            }
+            Output::Yaml => {
+                // handle multidoc from jq output (e.g. '.[].name' type queries on multidoc input)
+                let docs = self.parse_stdout_docs(&stdout)?;
+                let output = match docs.as_slice() {
+                    [x] => self.yaml_string(x)?,
+                    [] => serde_yaml::to_string(&serde_json::json!({}))?,
+                    xs if self.multidoc_output => {
+                        let docs = xs
+                            .iter()
+                            .map(|x| self.yaml_string(x))
+                            .collect::<Result<Vec<_>, _>>()?;
+                        docs.join("---\n")
+                    }
+                    xs => self.yaml_string(&xs)?,
+                };
+                Ok(output.trim_end().to_string())
+            }
+            Output::Toml => {
+                let val: serde_json::Value = serde_json::from_slice(&stdout)?;
+                Ok(self.toml_string(&val)?.trim_end().to_string())
+            }
+            Output::Json => {
+                let stdout: Vec<u8> = stdout.into_iter().filter(|b| *b != RS).collect();
+                let docs = serde_json::Deserializer::from_slice(&stdout)
+                    .into_iter()
+                    .map(|doc| self.json_string(&doc?))
+                    .collect::<Result<Vec<_>>>()?;
+                Ok(docs.join("\n"))
+            }
+            Output::Html => {
+                let rows = table_rows(self.parse_stdout_docs(&stdout)?)?;
+                Ok(html_table(&rows, self.bare_html))
+            }
+            Output::Csv => {
+                let rows = self.parse_stdout_docs(&stdout)?;
+                let table = csv_table(rows, self.delimiter, self.quote_char, !self.no_headers)?;
+                Ok(table.trim_end_matches('\n').to_string())
+            }
+            Output::Xml => {
+                let docs = self
+                    .parse_stdout_docs(&stdout)?
+                    .iter()
+                    .map(|doc| json_to_xml(doc, &self.xml_style()))
+                    .collect::<Result<Vec<_>>>()?;
+                Ok(docs.concat().trim_end().to_string())
+            }
+            Output::Kdl => {
+                let docs = self
+                    .parse_stdout_docs(&stdout)?
+                    .iter()
+                    .map(json_to_kdl)
+                    .collect::<Result<Vec<_>>>()?;
+                Ok(docs.concat().trim_end().to_string())
+            }
+        }
+    }
+    // Convert stdout into one of the Output formats verbatim as multidoc strings
+    // NB: not actually needed atm
+    #[allow(unused)] // refactor later maybe
+    fn output_matched(&self, stdout: Vec<u8>) -> Result<Vec<String>> {
+        let docs = self.parse_stdout_docs(&stdout)?;
+        let mut res = vec![];
+        // All formats are strictly parsed as the requested formats
+        for x in docs.as_slice() {
+            let str_doc: String = match self.output {
+                // We even need jq output to be valid json in this case to allow multidoc to be matched up
+                Output::Jq => serde_json::to_string_pretty(&x)?,
+                Output::Json => self.json_string(x)?,
+                Output::Yaml => serde_yaml::to_string(&x)?,
+                Output::Toml => json_to_toml(x)?,
+                Output::Html => html_table(&table_rows(vec![x.clone()])?, self.bare_html),
+                Output::Csv => csv_table(vec![x.clone()], self.delimiter, self.quote_char, !self.no_headers)?,
+                Output::Xml => json_to_xml(x, &self.xml_style())?,
+                Output::Kdl => json_to_kdl(x)?,
+            };
+            res.push(str_doc.trim_end().to_string());
+        }
+        Ok(res)
+    }
+}
+
+/// Decode text with valid utf-8 sequences kept and other bytes read as latin-1 characters
+fn latin1_fallback(mut data: &[u8]) -> String {
+    let mut text = String::with_capacity(data.len());
+    loop {
+        match std::str::from_utf8(data) {
+            Ok(valid) => {
+                text.push_str(valid);
+                return text;
+            }
+            Err(e) => {
+                let (valid, rest) = data.split_at(e.valid_up_to());
+                text.push_str(std::str::from_utf8(valid).unwrap_or_default());
+                let invalid = e.error_len().unwrap_or(rest.len());
+                text.extend(rest[..invalid].iter().map(|b| char::from(*b)));
+                data = &rest[invalid..];
+            }
+        }
+    }
+}
+
+/// Remove the " at line X column Y" that serde parsers put in their messages
+fn strip_location(message: &str) -> String {
+    let Some((head, rest)) = message.split_once(" at line ") else {
+        return message.into();
+    };
+    let rest = rest.split_once(" column ").map_or("", |(_, rest)| {
+        rest.trim_start_matches(|c: char| c.is_ascii_digit())
+    });
+    format!("{head}{rest}")
+}
+
+/// A rustc style snippet pointing a caret at a (1-based) line and column of the text
+fn error_snippet(name: &str, source: &str, line: usize, column: usize) -> String {
+    // keep tabs so the caret lines up with the source line
+    let indent: String = source
+        .chars()
+        .take(column.saturating_sub(1))
+        .map(|c| if c == '\t' { '\t' } else { ' ' })
+        .collect();
+    let gutter = " ".repeat(line.to_string().len());
+    format!("{gutter}--> {name}:{line}:{column}\n{gutter} |\n{line} | {source}\n{gutter} | {indent}^")
+}
+
+/// Resolve a program name to the executable that would run (via PATH unless it is a path)
+fn find_program(program: &std::path::Path) -> Option<PathBuf> {
+    if program.components().count() > 1 {
+        return Some(program.to_path_buf());
+    }
+    let path = std::env::var_os("PATH")?;
+    std::env::split_paths(&path)
+        .flat_map(|dir| {
+            // windows executables are run without their .exe suffix
+            let exe = dir.join(program);
+            let mut suffixed = exe.clone().into_os_string();
+            suffixed.push(std::env::consts::EXE_SUFFIX);
+            [exe, suffixed.into()]
+        })
+        .find(|p| p.is_file())
+}
+
+/// Whether the console understands ansi color codes, enabling them on Windows consoles
+#[cfg(not(windows))]
+fn ansi_console() -> bool {
+    true
+}
+
+/// Whether the console understands ansi color codes, enabling them on Windows consoles
+#[cfg(all(windows, feature = "repl"))]
+fn ansi_console() -> bool {
+    crossterm::ansi_support::supports_ansi()
+}
+
+/// Whether the console understands ansi color codes (Windows Terminal does, the legacy console does not)
+#[cfg(all(windows, not(feature = "repl")))]
+fn ansi_console() -> bool {
+    std::env::var_os("WT_SESSION").is_some()
+}
+
+/// The utf-8 byte order mark, which Windows editors like to start files with
+const UTF8_BOM: &[u8] = b"\xEF\xBB\xBF";
+
+/// Skip a leading byte order mark, which the json and toml parsers reject
+fn skip_bom<'a>(reader: impl Read + 'a) -> Result<Box<dyn Read + 'a>> {
+    let mut reader = BufReader::new(reader);
+    if reader.fill_buf()?.starts_with(UTF8_BOM) {
+        reader.consume(UTF8_BOM.len());
+    }
+    Ok(Box::new(reader))
+}
+
+/// Memory mapped input without its byte order mark
+struct Unmarked(memmap2::Mmap);
+
+impl std::ops::Deref for Unmarked {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        self.0.strip_prefix(UTF8_BOM).unwrap_or(&self.0)
+    }
+}
+
+/// Whether a non-empty file ends with a newline, if it exists
+fn ends_with_newline(path: &std::path::Path) -> Option<bool> {
+    use std::io::{Seek, SeekFrom};
+    let mut file = std::fs::File::open(path).ok()?;
+    file.seek(SeekFrom::End(-1)).ok()?;
+    let mut last = [0];
+    file.read_exact(&mut last).ok()?;
+    Some(last[0] == b'\n')
+}
+
+/// Whether a file uses Windows (CRLF) line endings, judging by its first line
+fn crlf_file(path: &std::path::Path) -> bool {
+    let Ok(file) = std::fs::File::open(path) else {
+        return false;
+    };
+    let mut line = vec![];
+    let _ = BufReader::new(file).read_until(b'\n', &mut line);
+    line.ends_with(b"\r\n")
+}
+
+/// Match a path against a glob where * matches any characters (including /) and ? any one character
+fn glob_matches(glob: &str, path: &str) -> bool {
+    let (glob, path): (Vec<char>, Vec<char>) = (glob.chars().collect(), path.chars().collect());
+    let (mut g, mut p) = (0, 0);
+    // position of the last * and the path position it is currently matched up to
+    let mut star = None;
+    while p < path.len() {
+        match glob.get(g) {
+            Some('*') => {
+                star = Some((g, p));
+                g += 1;
+            }
+            Some(&c) if c == '?' || c == path[p] => {
+                g += 1;
+                p += 1;
+            }
+            _ => match star {
+                // let the last * swallow one more character
+                Some((sg, sp)) => {
+                    star = Some((sg, sp + 1));
+                    (g, p) = (sg + 1, sp + 1);
+                }
+                None => return false,
+            },
+        }
+    }
+    glob[g..].iter().all(|&c| c == '*')
+}
+
+/// Replace the values of object keys matching any of the (lowercase) patterns with "***"
+fn redact(value: &mut serde_json::Value, patterns: &[String]) {
+    match value {
+        serde_json::Value::Object(map) => {
+            for (key, value) in map.iter_mut() {
+                let key = key.to_lowercase();
+                let matches = |pattern: &String| match pattern.contains(['*', '?']) {
+                    true => glob_matches(pattern, &key),
+                    false => key.contains(pattern.as_str()),
+                };
+                if patterns.iter().any(matches) {
+                    *value = "***".into();
+                } else {
+                    redact(value, patterns);
+                }
+            }
+        }
+        serde_json::Value::Array(items) => items.iter_mut().for_each(|item| redact(item, patterns)),
+        _ => {}
+    }
+}
+
+/// Keep only the parts of a value at any of the paths, or None if none of them exist in it
+///
+/// Objects and arrays keep their order, and arrays are compacted to the picked items.
+fn pick_paths(value: &serde_json::Value, paths: &[&[FieldStep]]) -> Option<serde_json::Value> {
+    use serde_json::Value;
+    if paths.is_empty() {
+        return None;
+    }
+    if paths.iter().any(|p| p.is_empty()) {
+        return Some(value.clone());
+    }
+    let rest = |matches: &dyn Fn(&FieldStep) -> bool| -> Vec<&[FieldStep]> {
+        paths.iter().filter(|p| matches(&p[0])).map(|p| &p[1..]).collect()
+    };
+    let picked = match value {
+        Value::Object(map) => Value::Object(
+            map.iter()
+                .filter_map(|(key, val)| {
+                    pick_paths(val, &rest(&|step| step.matches_key(key))).map(|val| (key.clone(), val))
+                })
+                .collect(),
+        ),
+        Value::Array(items) => Value::Array(
+            items
+                .iter()
+                .enumerate()
+                .filter_map(|(i, item)| pick_paths(item, &rest(&|step| step.matches_index(i))))
+                .collect(),
+        ),
+        _ => return None,
+    };
+    match &picked {
+        Value::Object(map) if map.is_empty() => None,
+        Value::Array(items) if items.is_empty() => None,
+        _ => Some(picked),
+    }
+}
+
+/// Remove the values at any of the paths
+fn omit_paths(value: &mut serde_json::Value, paths: &[&[FieldStep]]) {
+    use serde_json::Value;
+    if paths.is_empty() {
+        return;
+    }
+    let rest = |matches: &dyn Fn(&FieldStep) -> bool| -> Vec<&[FieldStep]> {
+        paths
+            .iter()
+            .filter(|p| p.len() > 1 && matches(&p[0]))
+            .map(|p| &p[1..])
+            .collect()
+    };
+    let omitted = |matches: &dyn Fn(&FieldStep) -> bool| paths.iter().any(|p| p.len() == 1 && matches(&p[0]));
+    match value {
+        Value::Object(map) => {
+            map.retain(|key, _| !omitted(&|step| step.matches_key(key)));
+            for (key, val) in map.iter_mut() {
+                omit_paths(val, &rest(&|step| step.matches_key(key)));
+            }
+        }
+        Value::Array(items) => {
+            for (i, item) in items.iter_mut().enumerate() {
+                omit_paths(item, &rest(&|step| step.matches_index(i)));
+            }
+            let mut i = 0;
+            items.retain(|_| {
+                i += 1;
+                !omitted(&|step| step.matches_index(i - 1))
+            });
+        }
+        _ => {}
+    }
+}
+
+/// Convert strings that look like numbers or booleans to them, except at the excluded paths
+fn coerce_types(value: &mut serde_json::Value, excludes: &[&[FieldStep]]) {
+    use serde_json::Value;
+    if excludes.iter().any(|p| p.is_empty()) {
+        return;
+    }
+    let rest = |matches: &dyn Fn(&FieldStep) -> bool| -> Vec<&[FieldStep]> {
+        excludes
+            .iter()
+            .filter(|p| matches(&p[0]))
+            .map(|p| &p[1..])
+            .collect()
+    };
+    match value {
+        Value::String(s) if s == "true" || s == "false" => *value = Value::Bool(s == "true"),
+        Value::String(s) => {
+            // only exact json numbers, that survive the conversion unchanged
+            if let Ok(number) = serde_json::from_str::<serde_json::Number>(s) {
+                if number.to_string() == *s {
+                    *value = Value::Number(number);
+                }
+            }
+        }
+        Value::Object(map) => {
+            for (key, val) in map.iter_mut() {
+                coerce_types(val, &rest(&|step| step.matches_key(key)));
+            }
+        }
+        Value::Array(items) => {
+            for (i, item) in items.iter_mut().enumerate() {
+                coerce_types(item, &rest(&|step| step.matches_index(i)));
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Remove nulls and/or empty containers from objects and arrays, innermost first
+fn prune(value: &mut serde_json::Value, mode: Prune) {
+    use serde_json::Value;
+    let pruned = |value: &Value| match value {
+        Value::Null => mode != Prune::Empty,
+        Value::Object(map) => map.is_empty() && mode != Prune::Nulls,
+        Value::Array(items) => items.is_empty() && mode != Prune::Nulls,
+        _ => false,
+    };
+    match value {
+        Value::Object(map) => {
+            map.values_mut().for_each(|val| prune(val, mode));
+            map.retain(|_, val| !pruned(val));
+        }
+        Value::Array(items) => {
+            items.iter_mut().for_each(|item| prune(item, mode));
+            items.retain(|item| !pruned(item));
+        }
+        _ => {}
+    }
+}
+
+/// Rename the keys of all objects to the given case, keeping their order
+///
+/// When several keys map to the same name, the last one wins.
+fn rename_keys(value: &mut serde_json::Value, case: KeyCase) {
+    match value {
+        serde_json::Value::Object(map) => {
+            *map = std::mem::take(map)
+                .into_iter()
+                .map(|(key, mut value)| {
+                    rename_keys(&mut value, case);
+                    (case.convert(&key), value)
+                })
+                .collect();
+        }
+        serde_json::Value::Array(items) => items.iter_mut().for_each(|item| rename_keys(item, case)),
+        _ => {}
+    }
+}
+
+/// Remove object keys matching the pattern at any depth
+fn delete_keys(value: &mut serde_json::Value, pattern: &regex::Regex) {
+    match value {
+        serde_json::Value::Object(map) => {
+            map.retain(|key, _| !pattern.is_match(key));
+            map.values_mut().for_each(|value| delete_keys(value, pattern));
+        }
+        serde_json::Value::Array(items) => items.iter_mut().for_each(|item| delete_keys(item, pattern)),
+        _ => {}
+    }
+}
+
+/// Substitute $VAR and ${VAR} references like envsubst, only for allowed names if given
+///
+/// Anything else starting with $ (including unterminated braces) is kept as is.
+fn expand_vars(
+    text: &str,
+    allowed: Option<&[String]>,
+    lookup: impl Fn(&str) -> Result<String>,
+) -> Result<String> {
+    let is_name = |name: &str| {
+        name.chars()
+            .next()
+            .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+            && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+    };
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(i) = rest.find('$') {
+        out.push_str(&rest[..i]);
+        let after = &rest[i + 1..];
+        // the name and the length of the whole reference
+        let reference = match after.strip_prefix('{') {
+            Some(braced) => braced.find('}').map(|end| (&braced[..end], end + 3)),
+            None => {
+                let end = after
+                    .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                    .unwrap_or(after.len());
+                Some((&after[..end], end + 1))
+            }
+        };
+        match reference {
+            Some((name, len))
+                if is_name(name) && allowed.map_or(true, |names| names.iter().any(|n| n == name)) =>
+            {
+                out.push_str(&lookup(name)?);
+                rest = &rest[i + len..];
+            }
+            _ => {
+                out.push('$');
+                rest = after;
+            }
+        }
+    }
+    out.push_str(rest);
+    Ok(out)
+}
+
+#[cfg(not(feature = "sops"))]
+fn sops_rewrite(_: &std::path::Path, _: &[u8]) -> Result<()> {
+    unreachable!("input is only decrypted with the sops feature")
+}
+
+/// Replace a file by renaming a fully written temporary file next to it over the target
+///
+/// Keeps the permissions of an existing file, and leaves it untouched if anything fails.
+fn write_atomic(path: &std::path::Path, contents: &[u8]) -> Result<()> {
+    use anyhow::Context;
+    let name = path
+        .file_name()
+        .with_context(|| format!("not a file path: {}", path.display()))?;
+    let tmp = path.with_file_name(format!(".{}.{}.tmp", name.to_string_lossy(), std::process::id()));
+    let write = || -> std::io::Result<()> {
+        let mut f = std::fs::File::create(&tmp)?;
+        f.write_all(contents)?;
+        if let Ok(meta) = std::fs::metadata(path) {
+            f.set_permissions(meta.permissions())?;
+        }
+        f.sync_all()?;
+        std::fs::rename(&tmp, path)
+    };
+    write().map_err(|e| {
+        let _ = std::fs::remove_file(&tmp);
+        anyhow::Error::from(e).context(format!("writing {}", path.display()))
+    })
+}
+
+/// Names of the jq formats (@csv etc), which config aliases cannot take
+const JQ_FORMATS: &[&str] = &[
+    "text", "json", "html", "uri", "csv", "tsv", "sh", "base32", "base32d", "base64", "base64d",
+];
+
+/// The user config file from LQ_CONFIG, or lq/config.toml in the config directory if it exists
+fn config_path() -> Result<Option<PathBuf>> {
+    if let Some(path) = std::env::var_os("LQ_CONFIG") {
+        return Ok(Some(path.into()));
+    }
+    let dir = match (std::env::var_os("XDG_CONFIG_HOME"), std::env::var_os("HOME")) {
+        (Some(dir), _) => PathBuf::from(dir),
+        (None, Some(home)) => PathBuf::from(home).join(".config"),
+        (None, None) => return Ok(None),
+    };
+    let path = dir.join("lq").join("config.toml");
+    Ok(path.exists().then_some(path))
+}
+
+/// Download an http(s) url with curl, or None if the server answers with an error status
+///
+/// Headers usually carry credentials, so they are passed on curl's stdin rather than its arguments.
+#[cfg(feature = "http")]
+fn http_get(url: &str, headers: &[String]) -> Result<Option<Vec<u8>>> {
+    use std::process::Stdio;
+    let mut curl = std::process::Command::new("curl");
+    curl.args(["-fsSL", "--proto", "=http,https"]);
+    if !headers.is_empty() {
+        curl.args(["-H", "@-"]);
+    }
+    let mut child = curl
+        .arg(url)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| match e.kind() {
+            std::io::ErrorKind::NotFound => anyhow::anyhow!("curl not found on PATH"),
+            _ => anyhow::Error::new(e).context("failed to run curl"),
+        })?;
+    let mut stdin = child.stdin.take().unwrap();
+    for header in headers {
+        writeln!(stdin, "{header}")?;
+    }
+    drop(stdin);
+    let output = child.wait_with_output()?;
+    match output.status.code() {
+        Some(0) => Ok(Some(output.stdout)),
+        // curl's exit code for http statuses of 400 and above with -f
+        Some(22) => Ok(None),
+        _ => anyhow::bail!(
+            "failed to download {url}: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        ),
+    }
+}
+
+#[cfg(not(feature = "http"))]
+fn http_get(url: &str, _: &[String]) -> Result<Option<Vec<u8>>> {
+    anyhow::bail!("cannot download {url}: lq was built without the http feature")
+}
+
+/// Spawn jq with piped stdin and stdout, and the given stderr
+///
+/// With `clear_env`, jq gets an empty environment, so `env` and `$ENV` reveal nothing of ours.
+#[cfg(not(target_family = "wasm"))]
+fn spawn_jq(
+    program: &std::path::Path,
+    args: &[String],
+    stderr: std::process::Stdio,
+    clear_env: bool,
+) -> Result<std::process::Child> {
+    use std::process::Stdio;
+    let mut command = match clear_env {
+        // resolved against our PATH first, as the child no longer has one
+        true => std::process::Command::new(find_program(program).unwrap_or_else(|| program.to_path_buf())),
+        false => std::process::Command::new(program),
+    };
+    if clear_env {
+        command.env_clear();
+    }
+    command
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(stderr)
+        .spawn()
+        .map_err(|e| match e.kind() {
+            std::io::ErrorKind::NotFound => Error::JqNotFound(program.to_path_buf()).into(),
+            _ => Error::JqSpawn {
+                program: program.to_path_buf(),
+                source: e,
+            }
+            .into(),
+        })
+}
+
+/// Run jq with json encoded bytes as its stdin, gathering its stdout (its stderr is inherited)
+fn run_jq(program: &std::path::Path, args: &[String], input: &[u8]) -> Result<std::process::Output> {
+    let mut child = spawn_jq(program, args, std::process::Stdio::inherit(), false)?;
+    // pass input as stdin from another thread while gathering output,
+    // so neither pipe can fill up and block jq (and us) on large payloads
+    let mut stdin = child.stdin.take().unwrap();
+    let output = std::thread::scope(|scope| {
+        scope.spawn(move || {
+            // jq can exit without reading everything (e.g. on errors), reported by its status
+            let _ = stdin.write_all(input);
+        });
+        child.wait_with_output()
+    })?;
+    Ok(output)
+}
+
+/// WASI cannot spawn processes, so queries are evaluated with jaq there instead (see Args::query_engine)
+#[cfg(target_family = "wasm")]
+fn spawn_jq(
+    _: &std::path::Path,
+    _: &[String],
+    _: std::process::Stdio,
+    _: bool,
+) -> Result<std::process::Child> {
+    anyhow::bail!(
+        "running jq is not possible on wasm targets (build with the jaq feature to evaluate queries)"
+    )
+}
+
+/// Run a jq filter over yaml, json, or toml input, returning the results as json values
+///
+/// Equivalent to `lq --input <input> <filter>` on `input_data` with default flags.
+pub fn query(input: Input, input_data: &[u8], filter: &str) -> Result<Vec<serde_json::Value>, Error> {
+    let mut args = Args::parse_without_env(["lq", "--", filter]).map_err(|e| Error::Other(e.into()))?;
+    args.input = input;
+    args.eval(input_data)
+}
+
+/// Query documents read from `input`, writing the converted results to `output` as jq produces them
+///
+/// When the options evaluate documents one at a time (--each or --select on yaml or json input,
+/// with jq, json or yaml output), documents are parsed and passed to jq as they are read, so
+/// only the current document is held in memory rather than all of the input or results, and
+/// results are written as jq flushes them (after each one with --unbuffered). Other queries need all of the
+/// input first, and only jq output is then passed through as it arrives; output formats like toml
+/// need all of the results, as on the command line.
+///
+/// ```no_run
+/// use clap::Parser;
+/// let opts = lq::Args::try_parse_from(["lq", "--each", "-c", ".metadata.name"])?;
+/// lq::run_streaming(std::io::stdin(), std::io::stdout(), &opts)?;
+/// # Ok::<(), anyhow::Error>(())
+/// ```
+pub fn run_streaming(mut input: impl Read, mut output: impl Write + Send, opts: &Args) -> Result<(), Error> {
+    let mut args = opts.clone();
+    args.resolve_output_shortcuts();
+    if args.query_engine().is_some() {
+        // engines evaluate whole batches of documents
+        return Pipeline::from(args).run(input, output);
+    } else if args.streams() {
+        let jq_args = args.jq_args(None);
+        let feed = |jq_stdin: &mut dyn Write| args.feed_documents(skip_bom(input)?, jq_stdin);
+        args.shellout_streaming_to(&jq_args, feed, &mut output)?;
+    } else if args.output == Output::Jq && !args.buffers_output() {
+        let mut data = vec![];
+        input.read_to_end(&mut data)?;
+        args.source = Some(data);
+        let input = args.read_input()?;
+        let jq_args = args.jq_args(if args.per_document() { None } else { Some(0) });
+        let feed = |jq_stdin: &mut dyn Write| {
+            let _ = jq_stdin.write_all(&input); // jq may stop reading early
+            Ok(())
+        };
+        args.shellout_streaming_to(&jq_args, feed, &mut output)?;
+    } else {
+        return Pipeline::from(args).run(input, output);
+    }
+    Ok(output.flush()?)
+}
+
+/// A conversion and query pipeline, for embedding lq without spawning the `lq` binary
+///
+/// Reads input in one format, runs a jq query over it, and writes the results in another format,
+/// like `lq --input <input> --output <output> <query>`. Start from [`Pipeline::new`] for the
+/// default flags, or convert parsed [`Args`] for anything the builder methods do not cover:
+///
+/// ```no_run
+/// let mut out = vec![];
+/// lq::Pipeline::new()
+///     .input_format(lq::Input::Toml)
+///     .query(".package.name")
+///     .output_format(lq::Output::Yaml)
+///     .run(&b"[package]\nname = 'lq'"[..], &mut out)?;
+/// assert_eq!(out, b"lq\n");
+/// # Ok::<(), anyhow::Error>(())
+/// ```
+#[derive(Debug, Clone)]
+pub struct Pipeline {
+    args: Args,
+}
+
+impl Default for Pipeline {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl From<Args> for Pipeline {
+    fn from(args: Args) -> Self {
+        Self { args }
+    }
+}
+
+impl Pipeline {
+    /// A pipeline with the default flags: yaml input, the identity query and jq's json output
+    pub fn new() -> Self {
+        Self {
+            args: Args::parse_without_env(["lq"]).expect("no arguments are valid"),
+        }
+    }
+
+    /// Set the format of the input (--input)
+    pub fn input_format(mut self, input: Input) -> Self {
+        self.args.input = input;
+        self
+    }
+
+    /// Set the jq query to run over the input
+    pub fn query(mut self, query: impl Into<String>) -> Self {
+        self.args.jq_query = Some(query.into());
+        self
+    }
+
+    /// Set the format of the results (--output)
+    pub fn output_format(mut self, output: Output) -> Self {
+        self.args.output = output;
+        self.args.yaml_output = false;
+        self.args.toml_output = false;
+        self.args.json_output = false;
+        self
+    }
+
+    /// Evaluate the query with an [`Engine`](engine::Engine) instead of the jq executable
+    ///
+    /// Engines get the query with `--arg` style variables bound at its start, so options needing
+    /// more of jq (--stream, -L) fail at run time.
+    pub fn engine(mut self, engine: impl engine::Engine + 'static) -> Self {
+        self.args.engine = Some(engine::Shared(std::sync::Arc::new(engine)));
+        self
+    }
+
+    /// Read all of `reader`, query it, and write the converted results to `writer`
+    ///
+    /// Output ends with a newline unless -j or --no-newline were given, as on the command line.
+    /// See [`run_streaming`] for large inputs and outputs.
+    pub fn run(&self, mut reader: impl Read, mut writer: impl Write) -> Result<(), Error> {
+        let mut args = self.args.clone();
+        args.resolve_output_shortcuts();
+        let mut data = vec![];
+        reader.read_to_end(&mut data)?;
+        args.source = Some(data);
+        let input = args.read_input()?;
+        let jq_args = args.jq_args(if args.per_document() { None } else { Some(0) });
+        let stdout = args.shellout(&input, &jq_args)?;
+        let stdout = args.postprocess(stdout)?;
+        let output = args.output(stdout)?;
+        let newline = if args.final_newline() && !output.is_empty() {
+            "\n"
+        } else {
+            ""
+        };
+        write!(writer, "{output}{newline}")?;
+        Ok(writer.flush()?)
+    }
+}
+
+/// Append an object key to a jq style path, quoting keys that are not plain identifiers
+fn push_key(path: &str, key: &str) -> String {
+    let plain = key
+        .chars()
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+    if plain {
+        format!("{path}.{key}")
+    } else if path.is_empty() {
+        format!(".[{}]", serde_json::Value::from(key))
+    } else {
+        format!("{path}[{}]", serde_json::Value::from(key))
+    }
+}
+
+/// Append an array index to a jq style path
+fn push_index(path: &str, i: usize) -> String {
+    if path.is_empty() {
+        format!(".[{i}]")
+    } else {
+        format!("{path}[{i}]")
+    }
+}
+
+/// Split leading module directives (include/import) off a jq program so the rest can be wrapped
+fn split_directives(query: &str) -> (&str, &str) {
+    let is_directive = |s: &str| {
+        let s = s.trim_start();
+        let keyword = s.strip_prefix("include").or_else(|| s.strip_prefix("import"));
+        keyword.is_some_and(|rest| rest.starts_with(char::is_whitespace))
+    };
+    let mut end = 0;
+    while is_directive(&query[end..]) {
+        // directives end at the first ; outside of a string
+        let (mut in_string, mut escaped) = (false, false);
+        let terminator = query[end..].char_indices().find(|&(_, c)| {
+            match (in_string, escaped, c) {
+                (true, true, _) => escaped = false,
+                (true, false, '\\') => escaped = true,
+                (true, false, '"') | (false, _, '"') => in_string = !in_string,
+                (false, _, ';') => return true,
+                _ => {}
+            }
+            false
+        });
+        match terminator {
+            Some((i, _)) => end += i + 1,
+            None => break,
+        }
+    }
+    query.split_at(end)
+}
+
+/// Flatten a multidoc stream into a single value to conform to the jq interface
+///
+/// 1 or 0 documents are not returned as nested documents.
+fn collapse_docs(mut docs: Vec<serde_json::Value>) -> serde_json::Value {
+    match docs.len() {
+        1 => docs.remove(0),
+        0 => serde_json::json!({}),
+        _ => docs.into(),
+    }
+}
+
+/// Split a Kubernetes List (or typed list like DeploymentList) into its items
+///
+/// Items of typed lists are given the apiVersion and kind of the list when they lack them.
+fn unwrap_list(mut doc: serde_json::Value) -> Vec<serde_json::Value> {
+    use serde_json::Value;
+    let kind = match doc["kind"].as_str().and_then(|k| k.strip_suffix("List")) {
+        Some(kind) if doc["items"].is_array() => kind.to_string(),
+        _ => return vec![doc],
+    };
+    let api_version = doc["apiVersion"].clone();
+    let Some(Value::Array(items)) = doc.get_mut("items").map(Value::take) else {
+        return vec![doc];
+    };
+    items
+        .into_iter()
+        .map(|mut item| {
+            if let (Value::Object(obj), false) = (&mut item, kind.is_empty()) {
+                obj.entry("kind").or_insert_with(|| kind.clone().into());
+                obj.entry("apiVersion").or_insert_with(|| api_version.clone());
+            }
+            item
+        })
+        .collect()
+}
+
+/// Check a write to stdout (or another pipe), returning false if the reader went away
+///
+/// Rust ignores SIGPIPE, so e.g. `lq ... | head -n1` closing the pipe surfaces as an EPIPE error
+/// on our next write, which should end the output quietly rather than fail.
+fn still_open(res: std::io::Result<()>) -> Result<bool> {
+    match res {
+        Ok(()) => Ok(true),
+        Err(e) if e.kind() == std::io::ErrorKind::BrokenPipe => Ok(false),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Recursively merge objects from overlay into base, with overlay winning on conflicts
+fn deep_merge(base: &mut serde_json::Value, overlay: serde_json::Value) {
+    use serde_json::Value;
+    match (base, overlay) {
+        (Value::Object(base), Value::Object(overlay)) => {
+            for (key, val) in overlay {
+                match base.get_mut(&key) {
+                    Some(existing) => deep_merge(existing, val),
+                    None => {
+                        base.insert(key, val);
+                    }
+                }
+            }
+        }
+        (base, overlay) => *base = overlay,
+    }
+}
+
+/// Three-way merge ours and theirs relative to base, where None is a missing value
+///
+/// Paths changed differently on both sides are collected into conflicts, and resolved by prefer
+/// or marked with an object holding both sides. Returns None when the value is deleted.
+fn merge_three_way(
+    base: Option<&serde_json::Value>,
+    ours: Option<serde_json::Value>,
+    theirs: Option<serde_json::Value>,
+    prefer: Option<Prefer>,
+    path: &str,
+    conflicts: &mut Vec<String>,
+) -> Option<serde_json::Value> {
+    use serde_json::Value;
+    if ours == theirs || theirs.as_ref() == base {
+        return ours;
+    }
+    if ours.as_ref() == base {
+        return theirs;
+    }
+    if let (Some(Value::Object(o)), Some(Value::Object(t))) = (&ours, &theirs) {
+        let b = base.and_then(Value::as_object);
+        let keys: Vec<&String> = o.keys().chain(t.keys().filter(|k| !o.contains_key(*k))).collect();
+        let mut merged = serde_json::Map::new();
+        for key in keys {
+            let value = merge_three_way(
+                b.and_then(|b| b.get(key)),
+                o.get(key).cloned(),
+                t.get(key).cloned(),
+                prefer,
+                &push_key(path, key),
+                conflicts,
+            );
+            if let Some(value) = value {
+                merged.insert(key.clone(), value);
+            }
+        }
+        return Some(Value::Object(merged));
+    }
+    conflicts.push(if path.is_empty() { ".".into() } else { path.into() });
+    match prefer {
+        Some(Prefer::Ours) => ours,
+        Some(Prefer::Theirs) => theirs,
+        None => Some(serde_json::json!({"<<<<<<< ours": ours, ">>>>>>> theirs": theirs})),
+    }
+}
+
+/// Merge helm values, like deep_merge but with null in overlay deleting the key from base
+fn helm_merge(base: &mut serde_json::Value, overlay: serde_json::Value) {
+    use serde_json::Value;
+    match (base, overlay) {
+        (Value::Object(base), Value::Object(overlay)) => {
+            for (key, val) in overlay {
+                match (base.get_mut(&key), val) {
+                    (_, Value::Null) => {
+                        base.remove(&key);
+                    }
+                    (Some(existing), val) => helm_merge(existing, val),
+                    (None, val) => {
+                        base.insert(key, val);
+                    }
+                }
+            }
+        }
+        (base, overlay) => *base = overlay,
+    }
+}
+
+/// A step in a helm --set path
+#[derive(Debug, PartialEq)]
+enum SetPath {
+    Key(String),
+    Index(usize),
+}
+
+/// Apply comma separated helm --set assignments (a.b[0]=v,c={x,y}) to values
+fn helm_set(values: &mut serde_json::Value, assignments: &str) -> Result<()> {
+    // split on commas outside of {} lists, keeping escaped characters escaped
+    let (mut parts, mut part, mut depth, mut chars) = (vec![], String::new(), 0, assignments.chars());
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => {
+                part.push(c);
+                part.extend(chars.next());
+            }
+            ',' if depth == 0 => parts.push(std::mem::take(&mut part)),
+            '{' | '}' => {
+                depth += if c == '{' { 1 } else { -1 };
+                part.push(c);
+            }
+            _ => part.push(c),
+        }
+    }
+    parts.push(part);
+    for assignment in parts {
+        let Some((path, value)) = assignment.split_once('=') else {
+            anyhow::bail!("expected PATH=VALUE for --set, found {assignment}");
+        };
+        let value = match value.strip_prefix('{').and_then(|v| v.strip_suffix('}')) {
+            Some("") => serde_json::Value::Array(vec![]),
+            Some(items) => items.split(',').map(helm_typed).collect(),
+            None => helm_typed(value),
+        };
+        set_helm_path(values, &parse_set_path(path)?, value);
+    }
+    Ok(())
+}
+
+/// Parse a helm --set path like a.b\.c[0].d into its keys and indices
+fn parse_set_path(path: &str) -> Result<Vec<SetPath>> {
+    let (mut steps, mut key, mut chars) = (vec![], String::new(), path.chars());
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => key.extend(chars.next()),
+            '.' => steps.push(SetPath::Key(std::mem::take(&mut key))),
+            '[' => {
+                if !key.is_empty() {
+                    steps.push(SetPath::Key(std::mem::take(&mut key)));
+                }
+                let index: String = chars.by_ref().take_while(|c| *c != ']').collect();
+                let index = index
+                    .parse()
+                    .map_err(|_| anyhow::anyhow!("invalid list index [{index}] in --set {path}"))?;
+                steps.push(SetPath::Index(index));
+                // an index is followed by another index, a dot, or the end
+                if chars.as_str().starts_with('.') {
+                    chars.next();
+                }
+            }
+            _ => key.push(c),
+        }
+    }
+    if !key.is_empty() || steps.is_empty() {
+        steps.push(SetPath::Key(key));
+    }
+    if steps.iter().any(|step| *step == SetPath::Key(String::new())) {
+        anyhow::bail!("empty key in --set path {path}");
+    }
+    Ok(steps)
+}
+
+/// Type a helm --set value: true, false, null and integers are typed, anything else is a string
+fn helm_typed(value: &str) -> serde_json::Value {
+    match value {
+        "true" => true.into(),
+        "false" => false.into(),
+        "null" => serde_json::Value::Null,
+        _ => value
+            .parse::<i64>()
+            .map_or_else(|_| value.replace('\\', "").into(), Into::into),
+    }
+}
+
+/// Set the value at a --set path, creating objects and lists on the way (null deletes the key)
+fn set_helm_path(values: &mut serde_json::Value, path: &[SetPath], value: serde_json::Value) {
+    use serde_json::Value;
+    let Some((step, rest)) = path.split_first() else {
+        *values = value;
+        return;
+    };
+    match step {
+        SetPath::Key(key) => {
+            if !values.is_object() {
+                *values = serde_json::json!({});
+            }
+            let Value::Object(obj) = values else {
+                unreachable!()
+            };
+            if rest.is_empty() && value.is_null() {
+                obj.remove(key);
+                return;
+            }
+            let next = obj.entry(key.clone()).or_insert(Value::Null);
+            set_helm_path(next, rest, value);
+        }
+        SetPath::Index(i) => {
+            if !values.is_array() {
+                *values = Value::Array(vec![]);
+            }
+            let Value::Array(items) = values else {
+                unreachable!()
+            };
+            if items.len() <= *i {
+                items.resize(i + 1, Value::Null);
+            }
+            set_helm_path(&mut items[*i], rest, value);
+        }
+    }
+}
+
+/// Merge the top level keys of overlay into base, with overlay winning on conflicts
+fn shallow_merge(base: &mut serde_json::Value, overlay: serde_json::Value) {
+    use serde_json::Value;
+    match (base, overlay) {
+        (Value::Object(base), Value::Object(overlay)) => base.extend(overlay),
+        (base, overlay) => *base = overlay,
+    }
+}
+
+/// Record separator preceding each json text in RFC 7464 sequences
+const RS: u8 = 0x1e;
+
+/// Order json values like jq: null < false < true < numbers < strings < arrays < objects
+fn cmp_json(a: &serde_json::Value, b: &serde_json::Value) -> std::cmp::Ordering {
+    use serde_json::Value;
+    let rank = |v: &Value| match v {
+        Value::Null => 0,
+        Value::Bool(false) => 1,
+        Value::Bool(true) => 2,
+        Value::Number(_) => 3,
+        Value::String(_) => 4,
+        Value::Array(_) => 5,
+        Value::Object(_) => 6,
+    };
+    match (a, b) {
+        (Value::Number(x), Value::Number(y)) => {
+            let (x, y) = (x.as_f64().unwrap_or_default(), y.as_f64().unwrap_or_default());
+            x.total_cmp(&y)
+        }
+        (Value::String(x), Value::String(y)) => x.cmp(y),
+        (Value::Array(xs), Value::Array(ys)) => {
+            let pairwise = xs.iter().zip(ys).map(|(x, y)| cmp_json(x, y)).find(|o| o.is_ne());
+            pairwise.unwrap_or_else(|| xs.len().cmp(&ys.len()))
+        }
+        (Value::Object(x), Value::Object(y)) => {
+            // jq compares the sorted key sets first, then the values key by key
+            let mut keys: Vec<_> = x.keys().collect();
+            let mut other_keys: Vec<_> = y.keys().collect();
+            keys.sort();
+            other_keys.sort();
+            let values = || {
+                keys.iter()
+                    .map(|k| cmp_json(&x[k.as_str()], &y[k.as_str()]))
+                    .find(|o| o.is_ne())
+            };
+            keys.cmp(&other_keys)
+                .then_with(|| values().unwrap_or(std::cmp::Ordering::Equal))
+        }
+        _ => rank(a).cmp(&rank(b)),
+    }
+}
+
+/// Escape non-ascii characters in json text as \u escapes (surrogate pairs outside the bmp)
+///
+/// Non-ascii characters can only occur inside json strings, so the text stays valid json.
+fn escape_non_ascii(json: &str) -> String {
+    let mut out = String::with_capacity(json.len());
+    for c in json.chars() {
+        if c.is_ascii() {
+            out.push(c);
+        } else {
+            for unit in c.encode_utf16(&mut [0; 2]) {
+                out.push_str(&format!("\\u{unit:04x}"));
+            }
+        }
+    }
+    out
+}
+
+/// Serialize a document with strings containing non-ascii characters written as escaped double quoted strings
+///
+/// Such strings are swapped for placeholders before serializing, and substituted afterwards,
+/// which works for yaml and toml as both accept \u and \U escapes in double quoted strings.
+fn to_ascii_string(
+    doc: &serde_json::Value,
+    serialize: impl Fn(&serde_json::Value) -> Result<String>,
+) -> Result<String> {
+    fn hold(value: &serde_json::Value, held: &mut Vec<String>) -> serde_json::Value {
+        use serde_json::Value;
+        let mut placeholder = |s: &String| {
+            if s.is_ascii() {
+                return s.clone();
+            }
+            held.push(s.clone());
+            format!("__lq_ascii_{}__", held.len() - 1)
+        };
+        match value {
+            Value::String(s) => Value::String(placeholder(s)),
+            Value::Array(xs) => Value::Array(xs.iter().map(|x| hold(x, held)).collect()),
+            Value::Object(obj) => {
+                let keys: Vec<_> = obj.keys().map(&mut placeholder).collect();
+                Value::Object(
+                    keys.into_iter()
+                        .zip(obj.values().map(|v| hold(v, held)))
+                        .collect(),
+                )
+            }
+            x => x.clone(),
+        }
+    }
+    let mut held = vec![];
+    let mut out = serialize(&hold(doc, &mut held))?;
+    for (i, s) in held.iter().enumerate().rev() {
+        let mut quoted = String::new();
+        // json escapes for quotes and control characters are valid in yaml and toml strings
+        for c in serde_json::to_string(s)?.chars() {
+            match c as u32 {
+                0..=0x7f => quoted.push(c),
+                n @ 0x80..=0xffff => quoted.push_str(&format!("\\u{n:04x}")),
+                n => quoted.push_str(&format!("\\U{n:08x}")),
+            }
+        }
+        let placeholder = format!("__lq_ascii_{i}__");
+        out = out
+            .replace(&format!("\"{placeholder}\""), &quoted)
+            .replace(&placeholder, &quoted);
+    }
+    Ok(out)
+}
+
+/// Recursively sort object keys (objects otherwise keep their document order)
+fn sort_keys(value: serde_json::Value) -> serde_json::Value {
+    use serde_json::Value;
+    match value {
+        Value::Array(xs) => xs.into_iter().map(sort_keys).collect(),
+        Value::Object(map) => {
+            let mut entries: Vec<_> = map.into_iter().collect();
+            entries.sort_by(|(a, _), (b, _)| a.cmp(b));
+            entries.into_iter().map(|(k, v)| (k, sort_keys(v))).collect()
+        }
+        x => x,
+    }
+}
+
+/// Convert floats with integral values to integers so that 1.0 and 1 compare equal
+fn normalize_numbers(value: serde_json::Value) -> serde_json::Value {
+    use serde_json::Value;
+    match value {
+        Value::Number(n) => match n.as_f64() {
+            Some(f) if n.is_f64() && f.fract() == 0.0 && f.abs() < i64::MAX as f64 => (f as i64).into(),
+            _ => Value::Number(n),
+        },
+        Value::Array(xs) => xs.into_iter().map(normalize_numbers).collect(),
+        Value::Object(map) => map.into_iter().map(|(k, v)| (k, normalize_numbers(v))).collect(),
+        x => x,
+    }
+}
+
+/// Collect the jq style paths (and values) where the key and/or scalar value match the patterns
+fn find_matches<'a>(
+    value: &'a serde_json::Value,
+    path: &str,
+    key: Option<&str>,
+    keys: Option<&regex::Regex>,
+    values: Option<&regex::Regex>,
+    found: &mut Vec<(String, &'a serde_json::Value)>,
+) {
+    use serde_json::Value;
+    let key_matches = keys.map_or(true, |re| key.is_some_and(|k| re.is_match(k)));
+    let value_matches = values.map_or(true, |re| match value {
+        Value::Object(_) | Value::Array(_) => false,
+        Value::String(s) => re.is_match(s),
+        scalar => re.is_match(&scalar.to_string()),
+    });
+    if key_matches && value_matches && (keys.is_some() || values.is_some()) {
+        found.push((path.to_string(), value));
+    }
+    match value {
+        Value::Object(map) => {
+            for (k, v) in map {
+                find_matches(v, &push_key(path, k), Some(k), keys, values, found);
+            }
+        }
+        Value::Array(items) => {
+            for (i, item) in items.iter().enumerate() {
+                find_matches(item, &push_index(path, i), None, keys, values, found);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// The rows of a table: the objects in the results, or the items of a single array result
+fn table_rows(
+    mut results: Vec<serde_json::Value>,
+) -> Result<Vec<serde_json::Map<String, serde_json::Value>>> {
+    if let [serde_json::Value::Array(items)] = results.as_mut_slice() {
+        results = std::mem::take(items);
+    }
+    results
+        .into_iter()
+        .map(|row| match row {
+            serde_json::Value::Object(map) => Ok(map),
+            other => Err(anyhow::anyhow!("table rows must be objects, found {other}")),
+        })
+        .collect()
+}
+
+/// The given columns of a table, or else the keys of all rows in order of appearance
+fn table_columns(rows: &[serde_json::Map<String, serde_json::Value>], columns: &[String]) -> Vec<String> {
+    let mut columns = columns.to_vec();
+    if columns.is_empty() {
+        for key in rows.iter().flat_map(|row| row.keys()) {
+            if !columns.contains(key) {
+                columns.push(key.clone());
+            }
+        }
+    }
+    columns
+}
+
+/// The text of a table cell: strings as is and other values as json, or None for missing values and nulls
+fn table_cell(row: &serde_json::Map<String, serde_json::Value>, column: &str) -> Option<String> {
+    match row.get(column)? {
+        serde_json::Value::Null => None,
+        serde_json::Value::String(s) => Some(s.clone()),
+        value => Some(value.to_string()),
+    }
+}
+
+/// Align rows of objects into columns separated by three spaces, with uppercase headers
+fn text_table(
+    rows: &[serde_json::Map<String, serde_json::Value>],
+    columns: &[String],
+    headers: bool,
+) -> String {
+    let columns = table_columns(rows, columns);
+    let mut lines: Vec<Vec<String>> = vec![];
+    if headers {
+        lines.push(columns.iter().map(|c| c.to_uppercase()).collect());
+    }
+    for row in rows {
+        let cell = |column: &String| table_cell(row, column).unwrap_or_else(|| "<none>".into());
+        lines.push(columns.iter().map(cell).collect());
+    }
+    let widths: Vec<usize> = (0..columns.len())
+        .map(|i| {
+            lines
+                .iter()
+                .map(|line| line[i].chars().count())
+                .max()
+                .unwrap_or(0)
+        })
+        .collect();
+    let mut table = String::new();
+    for line in lines {
+        let mut text = String::new();
+        for (cell, width) in line.iter().zip(&widths) {
+            text.push_str(&format!("{cell:width$}   "));
+        }
+        table.push_str(text.trim_end());
+        table.push('\n');
+    }
+    table
+}
+
+/// Collect the jq style paths where two values differ
+fn diff_paths(a: &serde_json::Value, b: &serde_json::Value, path: &str, diffs: &mut Vec<String>) {
+    use serde_json::Value;
+    let at = if path.is_empty() { "." } else { path };
+    match (a, b) {
+        (Value::Object(x), Value::Object(y)) => {
+            for (key, val) in x {
+                match y.get(key) {
+                    Some(other) => diff_paths(val, other, &push_key(path, key), diffs),
+                    None => diffs.push(format!("{}: only in first", push_key(path, key))),
+                }
+            }
+            for key in y.keys().filter(|k| !x.contains_key(*k)) {
+                diffs.push(format!("{}: only in second", push_key(path, key)));
+            }
+        }
+        (Value::Array(xs), Value::Array(ys)) if xs.len() == ys.len() => {
+            for (i, (x, y)) in xs.iter().zip(ys).enumerate() {
+                diff_paths(x, y, &push_index(path, i), diffs);
+            }
+        }
+        (Value::Array(xs), Value::Array(ys)) => {
+            diffs.push(format!("{at}: {} items != {} items", xs.len(), ys.len()));
+        }
+        (x, y) if x != y => diffs.push(format!("{at}: {x} != {y}")),
+        _ => {}
+    }
+}
+
+/// Collect every leaf (scalar or empty container) of a value with its jq style path
+fn leaf_paths<'a>(
+    value: &'a serde_json::Value,
+    path: &str,
+    leaves: &mut Vec<(String, &'a serde_json::Value)>,
+) {
+    use serde_json::Value;
+    match value {
+        Value::Object(map) if !map.is_empty() => {
+            for (key, val) in map {
+                leaf_paths(val, &push_key(path, key), leaves);
+            }
+        }
+        Value::Array(xs) if !xs.is_empty() => {
+            for (i, x) in xs.iter().enumerate() {
+                leaf_paths(x, &push_index(path, i), leaves);
+            }
+        }
+        leaf => {
+            let path = if path.is_empty() { ".".into() } else { path.into() };
+            leaves.push((path, leaf));
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    #[test]
+    fn file_input_both_outputs() -> Result<()> {
+        let mut args = Args {
+            jq_query: Some(".[2].metadata".into()),
+            compact_output: true,
+            output: Output::Jq,
+            file: Some("test/deploy.yaml".into()),
+            ..Default::default()
+        };
+        println!("have stdin? {}", !std::io::stdin().is_terminal());
+        let data = args.read_input().unwrap();
+        println!("debug args: {:?}", args);
+        let res = args.shellout(&data, &args.jq_args(Some(0))).unwrap();
+        let out = args.output(res)?;
+        assert_eq!(out, "{\"name\":\"controller\"}");
+        args.output = Output::Yaml;
+        let res2 = args.shellout(&data, &args.jq_args(Some(0)))?;
+        let out2 = args.output(res2)?;
+        assert_eq!(out2, "name: controller");
+        Ok(())
+    }
+
+    #[test]
+    fn embedded_query() -> Result<()> {
+        let names = query(
+            Input::Yaml,
+            b"metadata: {name: a}\n---\nmetadata: {name: b}",
+            ".[].metadata.name",
+        )?;
+        assert_eq!(names, vec![serde_json::json!("a"), serde_json::json!("b")]);
+        let mut args = Args::try_parse_from(["lq", "--each", "--input=json", ".x + 1"])?;
+        assert_eq!(args.eval(b"[{\"x\": 1}, {\"x\": 2}]")?, vec![2, 3]);
+        Ok(())
+    }
+
+    #[test]
+    fn streaming_api() -> Result<()> {
+        let input = b"a: 1\n---\na: 2\n---\na: 3\n";
+        let mut out = vec![];
+        let opts = Args::try_parse_from(["lq", "--each", "-y", ".a"])?;
+        assert!(opts.clone().streams());
+        run_streaming(&input[..], &mut out, &opts)?;
+        assert_eq!(String::from_utf8(out)?, "- 1\n- 2\n- 3\n");
+        let mut out = vec![];
+        run_streaming(
+            &input[..],
+            &mut out,
+            &Args::try_parse_from(["lq", "-c", "map(.a)"])?,
+        )?;
+        assert_eq!(out, b"[1,2,3]\n");
+        let mut out = vec![];
+        run_streaming(&input[..], &mut out, &Args::try_parse_from(["lq", "-t", ".[0]"])?)?;
+        assert_eq!(out, b"a = 1\n");
+        Ok(())
+    }
+
+    #[test]
+    fn streaming_before_input_ends() -> Result<()> {
+        use std::sync::atomic::{AtomicBool, Ordering};
+        use std::sync::Arc;
+        /// Gives a first document, then holds back the rest of the input until output was seen
+        struct Follow {
+            reads: usize,
+            seen: Arc<AtomicBool>,
+            seen_early: Arc<AtomicBool>,
+        }
+        impl Read for Follow {
+            fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+                self.reads += 1;
+                let chunk: &[u8] = match self.reads {
+                    1 => b"a: 1\n---\n",
+                    2 => {
+                        for _ in 0..1000 {
+                            if self.seen.load(Ordering::SeqCst) {
+                                break;
+                            }
+                            std::thread::sleep(std::time::Duration::from_millis(10));
+                        }
+                        self.seen_early
+                            .store(self.seen.load(Ordering::SeqCst), Ordering::SeqCst);
+                        b"a: 2\n"
+                    }
+                    _ => b"",
+                };
+                buf[..chunk.len()].copy_from_slice(chunk);
+                Ok(chunk.len())
+            }
+        }
+        struct Seen(Arc<AtomicBool>, Vec<u8>);
+        impl Write for Seen {
+            fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+                self.1.extend_from_slice(buf);
+                self.0.store(true, Ordering::SeqCst);
+                Ok(buf.len())
+            }
+            fn flush(&mut self) -> std::io::Result<()> {
+                Ok(())
+            }
+        }
+        let (seen, seen_early) = (Arc::new(AtomicBool::new(false)), Arc::new(AtomicBool::new(false)));
+        let input = Follow {
+            reads: 0,
+            seen: seen.clone(),
+            seen_early: seen_early.clone(),
+        };
+        let mut out = Seen(seen, vec![]);
+        let opts = Args::try_parse_from(["lq", "--each", "--unbuffered", "-c", ".a"])?;
+        run_streaming(input, &mut out, &opts)?;
+        assert_eq!(out.1, b"1\n2\n");
+        assert!(
+            seen_early.load(Ordering::SeqCst),
+            "no output before the input ended"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn error_kinds() {
+        let err = query(Input::Json, b"{\n  \"a\": [1,\n}", ".").unwrap_err();
+        assert!(
+            matches!(
+                err,
+                Error::Parse {
+                    format: Input::Json,
+                    line: 3,
+                    ..
+                }
+            ),
+            "{err:?}"
+        );
+        assert!(matches!(
+            query(Input::Yaml, b"a: 1", ".a |||"),
+            Err(Error::JqFailed(3))
+        ));
+        let mut args = Args::try_parse_from(["lq", "--jq", "/nonexistent/jq", "."]).unwrap();
+        assert!(matches!(args.eval(b"a: 1"), Err(Error::JqNotFound(_))));
+        let err = Error::from(anyhow::Error::from(Error::JqFailed(5)).context("evaluating"));
+        assert_eq!(err.to_string(), "arguments rejected by jq: exit status: 5");
+    }
+
+    #[test]
+    fn pipeline_builder() -> Result<()> {
+        let mut out = vec![];
+        Pipeline::new()
+            .input_format(Input::Json)
+            .query(".items | map(.name)")
+            .output_format(Output::Yaml)
+            .run(
+                &b"{\"items\": [{\"name\": \"a\"}, {\"name\": \"b\"}]}"[..],
+                &mut out,
+            )?;
+        assert_eq!(String::from_utf8(out)?, "- a\n- b\n");
+        let args = Args::try_parse_from(["lq", "-c", "."])?;
+        let mut out = vec![];
+        Pipeline::from(args).run(&b"a: [1]"[..], &mut out)?;
+        assert_eq!(out, b"{\"a\":[1]}\n");
+        // set only around the parse, as other tests parse arguments with their environment
+        std::env::set_var("LQ_KUBERNETES_SCHEMAS", "/nonexistent");
+        let schemas = Pipeline::new().args.kubernetes_schemas;
+        std::env::remove_var("LQ_KUBERNETES_SCHEMAS");
+        assert!(schemas.is_none());
+        let mut args = Args::parse_without_env(["lq", "-q", ".missing"])?;
+        args.source = Some(b"a: 1".to_vec());
+        assert!(matches!(args.run(), Err(Error::Status(1))));
+        Ok(())
+    }
+
+    #[test]
+    fn structural_diff() {
+        let a = serde_json::json!({"a": [1, 2.0], "b": {"c": "x"}});
+        let b = serde_json::json!({"a": [1.0, 2], "b": {"d": "x"}});
+        let mut diffs = vec![];
+        diff_paths(
+            &normalize_numbers(a.clone()),
+            &normalize_numbers(a.clone()),
+            "",
+            &mut diffs,
+        );
+        assert!(diffs.is_empty());
+        diff_paths(&normalize_numbers(a), &normalize_numbers(b), "", &mut diffs);
+        assert_eq!(diffs, vec![".b.c: only in first", ".b.d: only in second"]);
+    }
+
+    #[test]
+    fn document_ordering() {
+        use serde_json::json;
+        let mut xs = vec![
+            json!({"a": 1}),
+            json!("b"),
+            json!([1]),
+            json!(2),
+            json!(null),
+            json!(true),
+            json!("a"),
+        ];
+        xs.sort_by(cmp_json);
+        assert_eq!(
+            xs,
+            vec![
+                json!(null),
+                json!(true),
+                json!(2),
+                json!("a"),
+                json!("b"),
+                json!([1]),
+                json!({"a": 1})
+            ]
+        );
+        let mut docs = [
+            json!({"kind": "Foo"}),
+            json!({"kind": "Deployment"}),
+            json!({"kind": "Namespace"}),
+        ];
+        docs.sort_by_key(kind_order);
+        assert_eq!(docs[0]["kind"], "Namespace");
+        assert_eq!(docs[2]["kind"], "Foo");
+    }
+
+    #[test]
+    fn module_directives() {
+        assert_eq!(split_directives(".foo"), ("", ".foo"));
+        assert_eq!(split_directives("include \"k\"; gvk"), ("include \"k\";", " gvk"));
+        let query = "import \"a;b\" as a; include \"k\" {search: \"./\"};\n.[] | a::f";
+        assert_eq!(split_directives(query).1, "\n.[] | a::f");
+        assert_eq!(split_directives("important"), ("", "important"));
+    }
+
+    #[test]
+    fn named_query_args() {
+        let named: NamedQuery = "tag=.image | split(\":\")[1]".parse().unwrap();
+        assert_eq!(
+            (named.name.as_str(), named.query.as_str()),
+            ("tag", ".image | split(\":\")[1]")
+        );
+        assert!("tag=".parse::<NamedQuery>().is_err());
+    }
+
+    #[test]
+    fn doc_selection_args() {
+        let selection: DocSelection = "0,2-4,6-".parse().unwrap();
+        assert_eq!(
+            selection,
+            DocSelection(vec![(0, Some(0)), (2, Some(4)), (6, None)])
+        );
+        assert!(selection.contains(3) && selection.contains(9) && !selection.contains(5));
+        assert!("2-2".parse::<DocSelection>().is_ok());
+        assert!("2-0".parse::<DocSelection>().is_err());
+        assert!("x".parse::<DocSelection>().is_err());
+    }
+
+    #[test]
+    fn env_args() {
+        std::env::set_var("LQ_TEST_ENV_ARG", "s3cret");
+        let arg: EnvArg = "token=LQ_TEST_ENV_ARG".parse().unwrap();
+        assert_eq!((arg.name.as_str(), arg.value.as_str()), ("token", "s3cret"));
+        assert!(!format!("{arg:?}").contains("s3cret"));
+        let arg: EnvArg = "LQ_TEST_ENV_ARG".parse().unwrap();
+        assert_eq!(arg.name, "LQ_TEST_ENV_ARG");
+        assert!("x=LQ_TEST_UNSET_ENV_ARG".parse::<EnvArg>().is_err());
+        assert!("=LQ_TEST_ENV_ARG".parse::<EnvArg>().is_err());
+    }
+
+    #[test]
+    fn three_way_merge() {
+        use serde_json::json;
+        let base = json!({"replicas": 1, "image": {"tag": "1"}, "env": ["A"], "old": true});
+        let ours = json!({"replicas": 2, "image": {"tag": "1", "pull": "Always"}, "env": ["A", "B"]});
+        let theirs = json!({"replicas": 1, "image": {"tag": "2"}, "env": ["C"], "old": true});
+        let mut conflicts = vec![];
+        let merged = merge_three_way(
+            Some(&base),
+            Some(ours.clone()),
+            Some(theirs.clone()),
+            None,
+            "",
+            &mut conflicts,
+        );
+        let expected = json!({
+            "replicas": 2,
+            "image": {"tag": "2", "pull": "Always"},
+            "env": {"<<<<<<< ours": ["A", "B"], ">>>>>>> theirs": ["C"]},
+        });
+        assert_eq!(merged, Some(expected));
+        assert_eq!(conflicts, [".env"]);
+
+        let mut conflicts = vec![];
+        let merged = merge_three_way(
+            Some(&base),
+            Some(ours),
+            Some(theirs),
+            Some(Prefer::Theirs),
+            "",
+            &mut conflicts,
+        );
+        assert_eq!(merged.unwrap()["env"], json!(["C"]));
+    }
+
+    #[test]
+    fn helm_values() -> Result<()> {
+        use serde_json::json;
+        let mut values = json!({"image": {"repo": "app", "tag": "1"}, "ports": [80, 443], "debug": true});
+        helm_merge(
+            &mut values,
+            json!({"image": {"tag": "2"}, "ports": [8080], "debug": null}),
+        );
+        assert_eq!(
+            values,
+            json!({"image": {"repo": "app", "tag": "2"}, "ports": [8080]})
+        );
+
+        helm_set(&mut values, "image.tag=3,replicas=2,hosts={a,b},ports[1]=9090")?;
+        helm_set(
+            &mut values,
+            r"annotations.a\.io/x=true,env[0].name=X\,Y,image.repo=null",
+        )?;
+        let expected = json!({
+            "image": {"tag": 3},
+            "ports": [8080, 9090],
+            "replicas": 2,
+            "hosts": ["a", "b"],
+            "annotations": {"a.io/x": true},
+            "env": [{"name": "X,Y"}],
+        });
+        assert_eq!(values, expected);
+        assert!(helm_set(&mut values, "novalue").is_err());
+        assert!(helm_set(&mut values, "a[x]=1").is_err());
+        assert!(helm_set(&mut values, "a..b=1").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn parse_error_snippets() {
+        let mut args = Args {
+            file: Some("broken.yaml".into()),
+            source: Some(b"a: 1\nb: c: d\n".to_vec()),
+            ..Default::default()
+        };
+        let err = args.read_yaml_docs().unwrap_err().to_string();
+        assert!(
+            err.starts_with("invalid yaml in broken.yaml: mapping values"),
+            "{err}"
+        );
+        assert!(
+            err.contains(" --> broken.yaml:2:5\n  |\n2 | b: c: d\n  |     ^"),
+            "{err}"
+        );
+        assert_eq!(
+            error_snippet("x.json", "  \"a\": tru", 2, 8),
+            " --> x.json:2:8\n  |\n2 |   \"a\": tru\n  |        ^"
+        );
+        assert_eq!(
+            strip_location("expected value at line 1 column 5"),
+            "expected value"
+        );
+        let mut located = |source: &[u8]| {
+            args.source = Some(source.to_vec());
+            match args.read_yaml_docs().unwrap_err().downcast::<Error>() {
+                Ok(Error::Parse {
+                    line,
+                    column,
+                    message,
+                    ..
+                }) => (line, column, message),
+                err => panic!("{err:?}"),
+            }
+        };
+        assert_eq!(
+            located(b"a: caf\xe9\n"),
+            (1, 7, "incomplete UTF-8 octet sequence".into())
+        );
+        assert_eq!(located(b"a: 1\na: 2\n").0, 2);
+        assert_eq!(located(b"x:\n  a: 1\n  b: 1\n  'a': 2\n").0, 4);
+        // flow mappings are not searched, so the error has no location rather than a wrong one
+        assert_eq!(located(b"{a: 1, a: 2}").0, 0);
+    }
+
+    #[test]
+    fn latin1_decoding() {
+        assert_eq!(latin1_fallback(b"caf\xe9 \xc3\xa9t\xe9"), "café été");
+        assert_eq!(latin1_fallback(b"trailing \xc3"), "trailing Ã");
+    }
+
+    #[test]
+    fn alias_expansion_limits() {
+        let laughs = "a: &a [lol, lol, lol]\nb: &b [*a, *a, *a]\nc: [*b, *b, *b]\n";
+        let doc = Args::default()
+            .yaml_doc_to_json(Deserializer::from_str(laughs))
+            .unwrap();
+        assert_eq!(doc["c"][2][2][2], "lol");
+        let args = Args {
+            yaml_max_nodes: Some(20),
+            ..Default::default()
+        };
+        let err = args.yaml_doc_to_json(Deserializer::from_str(laughs)).unwrap_err();
+        assert!(err.to_string().contains("more than 20 nodes"), "{err}");
+        let args = Args {
+            yaml_max_depth: Some(2),
+            ..Default::default()
+        };
+        assert!(args.yaml_doc_to_json(Deserializer::from_str("a: 1")).is_ok());
+        assert!(args
+            .yaml_doc_to_json(Deserializer::from_str("a: {b: {c: 1}}"))
+            .is_err());
+    }
+
+    #[test]
+    fn size_parsing() {
+        assert_eq!(parse_size("512"), Ok(512));
+        assert_eq!(parse_size("2k"), Ok(2048));
+        assert_eq!(parse_size("500M"), Ok(500 << 20));
+        assert_eq!(parse_size("1GiB"), Ok(1 << 30));
+        assert!(parse_size("1x").is_err());
+        assert!(parse_size("M").is_err());
+    }
+
+    #[test]
+    fn key_case_conversion() {
+        assert_eq!(KeyCase::Snake.convert("apiVersion"), "api_version");
+        assert_eq!(KeyCase::Kebab.convert("HTTPServerPort"), "http-server-port");
+        assert_eq!(KeyCase::Camel.convert("max_surge"), "maxSurge");
+        assert_eq!(KeyCase::Pascal.convert("read-only root"), "ReadOnlyRoot");
+        assert_eq!(KeyCase::Camel.convert("ipv6Address"), "ipv6Address");
+        let mut doc = serde_json::json!({"spec": {"containerPorts": [{"hostIP": "x"}]}});
+        rename_keys(&mut doc, KeyCase::Snake);
+        assert_eq!(
+            doc,
+            serde_json::json!({"spec": {"container_ports": [{"host_ip": "x"}]}})
+        );
+    }
+
+    #[test]
+    fn path_picking() {
+        let doc = serde_json::json!({
+            "metadata": {"name": "web", "labels": {"app": "web", "tier": "fe"}},
+            "spec": {"replicas": 2, "containers": [{"name": "a", "image": "x"}, {"name": "b"}]}
+        });
+        let paths = |ps: &[&str]| -> Vec<FieldPath> { ps.iter().map(|p| p.parse().unwrap()).collect() };
+        let pick = paths(&[".metadata.name", "spec.containers[].image"]);
+        let pick: Vec<_> = pick.iter().map(|p| p.0.as_slice()).collect();
+        assert_eq!(
+            pick_paths(&doc, &pick).unwrap(),
+            serde_json::json!({"metadata": {"name": "web"}, "spec": {"containers": [{"image": "x"}]}})
+        );
+        let omit = paths(&[".metadata.labels.t*", ".spec.containers[0]", ".spec.replicas"]);
+        let omit: Vec<_> = omit.iter().map(|p| p.0.as_slice()).collect();
+        let mut omitted = doc.clone();
+        omit_paths(&mut omitted, &omit);
+        assert_eq!(
+            omitted,
+            serde_json::json!({
+                "metadata": {"name": "web", "labels": {"app": "web"}},
+                "spec": {"containers": [{"name": "b"}]}
+            })
+        );
+        assert!("a[x]".parse::<FieldPath>().is_err());
+    }
+
+    #[test]
+    fn finding_matches() {
+        let doc = serde_json::json!({
+            "spec": {"containers": [{"name": "app", "image": "app:latest"}, {"image": "sidecar:1.0", "port": 8080}]}
+        });
+        let re = |s: &str| regex::Regex::new(s).unwrap();
+        let paths = |keys: Option<&regex::Regex>, values: Option<&regex::Regex>| {
+            let mut found = vec![];
+            find_matches(&doc, "", None, keys, values, &mut found);
+            found.into_iter().map(|(path, _)| path).collect::<Vec<_>>()
+        };
+        assert_eq!(
+            paths(None, Some(&re("latest|80"))),
+            [".spec.containers[0].image", ".spec.containers[1].port"]
+        );
+        assert_eq!(
+            paths(Some(&re("^image$")), Some(&re("^side"))),
+            [".spec.containers[1].image"]
+        );
+        assert_eq!(paths(Some(&re("containers")), None), [".spec.containers"]);
+    }
+
+    #[test]
+    fn table_alignment() {
+        let rows: Vec<_> = [
+            serde_json::json!({"name": "web", "replicas": 3}),
+            serde_json::json!({"name": "worker-pool", "image": "app:1.2", "replicas": null}),
+        ]
+        .into_iter()
+        .map(|row| row.as_object().unwrap().clone())
+        .collect();
+        assert_eq!(
+            text_table(&rows, &[], true),
+            "NAME          REPLICAS   IMAGE\nweb           3          <none>\nworker-pool   <none>     app:1.2\n"
+        );
+        assert_eq!(text_table(&rows, &["image".into()], false), "<none>\napp:1.2\n");
+    }
+
+    #[test]
+    fn type_coercion() {
+        let mut doc = serde_json::json!([
+            {"port": "8080", "ratio": "0.5", "debug": "true", "version": "1.10", "zip": "02134"},
+            {"id": "123456789012345678901", "zip": "90210", "name": "True"}
+        ]);
+        let exclude: FieldPath = "[].zip".parse().unwrap();
+        coerce_types(&mut doc, &[exclude.0.as_slice()]);
+        assert_eq!(
+            doc,
+            serde_json::json!([
+                {"port": 8080, "ratio": 0.5, "debug": true, "version": "1.10", "zip": "02134"},
+                {"id": "123456789012345678901", "zip": "90210", "name": "True"}
+            ])
+        );
+    }
+
+    #[test]
+    fn pruning() {
+        let doc = serde_json::json!({"a": null, "b": {"c": null}, "d": [[], 1, null], "e": {}});
+        let pruned = |mode| {
+            let mut doc = doc.clone();
+            prune(&mut doc, mode);
+            doc
+        };
+        assert_eq!(
+            pruned(Prune::Nulls),
+            serde_json::json!({"b": {}, "d": [[], 1], "e": {}})
+        );
+        assert_eq!(
+            pruned(Prune::Empty),
+            serde_json::json!({"a": null, "b": {"c": null}, "d": [1, null]})
+        );
+        assert_eq!(pruned(Prune::Both), serde_json::json!({"d": [1]}));
+    }
+
+    #[test]
+    fn redaction() {
+        let mut doc = serde_json::json!({
+            "db": {"Password": "hunter2", "user": "app"},
+            "api_token": {"value": "t"},
+            "items": [{"secretKey": 1, "name": "a"}],
+        });
+        redact(
+            &mut doc,
+            &["password".into(), "*token".into(), "secret?ey".into()],
+        );
+        let expected = serde_json::json!({
+            "db": {"Password": "***", "user": "app"},
+            "api_token": "***",
+            "items": [{"secretKey": "***", "name": "a"}],
+        });
+        assert_eq!(doc, expected);
+    }
+
+    #[test]
+    fn ascii_escaping() -> Result<()> {
+        assert_eq!(
+            escape_non_ascii(r#"{"k":"é😀"}"#),
+            r#"{"k":"\u00e9\ud83d\ude00"}"#
+        );
+        let doc = serde_json::json!({"café": ["ok", "ü\n😀"], "n": 1});
+        let yaml = to_ascii_string(&doc, |v| Ok(serde_yaml::to_string(v)?))?;
+        assert!(yaml.is_ascii());
+        let parsed: serde_json::Value = serde_yaml::from_str(&yaml)?;
+        assert_eq!(parsed, doc);
+        Ok(())
+    }
+
+    #[test]
+    fn env_expansion() -> Result<()> {
+        let lookup = |name: &str| match name {
+            "TAG" => Ok("1.2".to_string()),
+            _ => Ok(String::new()),
+        };
+        let text = "image: app:${TAG}\ntag: $TAG-x\nunset: '$NOPE'\ncost: $5 ${ } ${TAG";
+        let expanded = expand_vars(text, None, lookup)?;
+        assert_eq!(
+            expanded,
+            "image: app:1.2\ntag: 1.2-x\nunset: ''\ncost: $5 ${ } ${TAG"
+        );
+        let allowed = ["TAG".to_string()];
+        assert_eq!(expand_vars("$NOPE ${TAG}", Some(&allowed), lookup)?, "$NOPE 1.2");
+        Ok(())
+    }
+
+    #[test]
+    fn streamed_parse_errors() {
+        let args = Args {
+            file: Some("bad.yaml".into()),
+            each: true,
+            ..Default::default()
+        };
+        let input = "a: 1\n---\nb: 2\n---\nc: 3\nd: : e\n";
+        let err = args.feed_documents(input.as_bytes(), &mut vec![]).unwrap_err();
+        let err = err.to_string();
+        assert!(
+            err.contains(" --> bad.yaml:6:4\n  |\n6 | d: : e\n  |    ^"),
+            "{err}"
+        );
+    }
+}
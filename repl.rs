@@ -0,0 +1,365 @@
+//! Interactive query editor with a live preview of the results (`lq repl`)
+//!
+//! The editor is drawn on stderr so that the accepted output can be piped from stdout.
+use crate::Args;
+use anyhow::Result;
+use crossterm::{
+    cursor,
+    event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers},
+    execute, queue, style,
+    terminal::{self, ClearType},
+};
+use std::io::{stderr, IsTerminal, Write};
+use std::path::PathBuf;
+use std::time::Duration;
+use tracing::*;
+
+/// Time a preview may take before jq is killed (e.g. for accidental infinite generators)
+const EVAL_TIMEOUT: Duration = Duration::from_secs(2);
+/// Bytes of jq output (or errors) read for a preview
+const PREVIEW_LIMIT: u64 = 1 << 20;
+/// Number of queries kept in the history file
+const HISTORY_SIZE: usize = 500;
+
+impl Args {
+    /// Edit a query against the input with a live preview, then print the output of the accepted query
+    pub(crate) fn run_repl(&self, file: Option<&std::path::Path>) -> Result<()> {
+        if !stderr().is_terminal() {
+            anyhow::bail!("lq repl needs a terminal");
+        }
+        let mut args = match file {
+            Some(path) => self.file_args(path)?,
+            None => self.clone(),
+        };
+        args.color_output = false;
+        let input = args.read_input()?;
+        let mut history = History::load();
+        let query = args.jq_query.clone().unwrap_or_else(|| ".".into());
+        let accepted = {
+            let _terminal = RawTerminal::enter()?;
+            Editor::new(&query).run(&args, &input, &history)?
+        };
+        let Some((query, output)) = accepted else {
+            return Ok(());
+        };
+        if let Err(e) = history.push(&query) {
+            warn!("failed to save query history: {e}");
+        }
+        eprintln!("{query}");
+        args.write_output(output)
+    }
+
+    /// Evaluate a query for the preview, returning its converted output or jq's error message
+    fn preview(&self, input: &[u8], query: &str) -> Result<String, String> {
+        let mut args = self.clone();
+        args.jq_query = Some(query.into());
+        let jq_args = args.jq_args(if args.per_document() { None } else { Some(0) });
+        let stdout = args
+            .shellout_bounded(input, &jq_args, EVAL_TIMEOUT, PREVIEW_LIMIT, false)
+            .map_err(|e| e.to_string())?;
+        args.output(stdout).map_err(|e| e.to_string())
+    }
+}
+
+/// Raw mode on an alternate screen, restored when dropped (also on errors)
+struct RawTerminal;
+
+impl RawTerminal {
+    fn enter() -> Result<Self> {
+        terminal::enable_raw_mode()?;
+        execute!(stderr(), terminal::EnterAlternateScreen)?;
+        Ok(RawTerminal)
+    }
+}
+
+impl Drop for RawTerminal {
+    fn drop(&mut self) {
+        let _ = execute!(stderr(), terminal::LeaveAlternateScreen, cursor::Show);
+        let _ = terminal::disable_raw_mode();
+    }
+}
+
+/// Queries accepted in earlier sessions, oldest first
+#[derive(Default)]
+struct History {
+    path: Option<PathBuf>,
+    entries: Vec<String>,
+}
+
+impl History {
+    /// Read the history from `$XDG_STATE_HOME/lq/history` (or `~/.local/state/lq/history`)
+    fn load() -> Self {
+        let dir = match (std::env::var_os("XDG_STATE_HOME"), std::env::var_os("HOME")) {
+            (Some(dir), _) => PathBuf::from(dir),
+            (None, Some(home)) => PathBuf::from(home).join(".local").join("state"),
+            (None, None) => return History::default(),
+        };
+        let path = dir.join("lq").join("history");
+        let entries = std::fs::read_to_string(&path)
+            .map(|history| {
+                history
+                    .lines()
+                    .filter(|l| !l.is_empty())
+                    .map(Into::into)
+                    .collect()
+            })
+            .unwrap_or_default();
+        History {
+            path: Some(path),
+            entries,
+        }
+    }
+
+    /// Record a query (unless it repeats the last one) and save the history
+    fn push(&mut self, query: &str) -> Result<()> {
+        if query.is_empty() || query.contains('\n') || self.entries.last().is_some_and(|q| q == query) {
+            return Ok(());
+        }
+        self.entries.push(query.into());
+        let excess = self.entries.len().saturating_sub(HISTORY_SIZE);
+        self.entries.drain(..excess);
+        if let Some(path) = &self.path {
+            if let Some(dir) = path.parent() {
+                std::fs::create_dir_all(dir)?;
+            }
+            std::fs::write(path, self.entries.join("\n") + "\n")?;
+        }
+        Ok(())
+    }
+}
+
+/// What to do after a key press
+#[derive(Debug, PartialEq)]
+enum Action {
+    Continue,
+    Accept,
+    Cancel,
+}
+
+/// The query line being edited, and the position in the results and history
+struct Editor {
+    query: Vec<char>,
+    cursor: usize,
+    scroll: usize,
+    /// Index of the history entry being shown, if any
+    recalled: Option<usize>,
+    /// The query being typed before recalling history entries
+    draft: Vec<char>,
+}
+
+impl Editor {
+    fn new(query: &str) -> Self {
+        let query: Vec<char> = query.chars().collect();
+        Editor {
+            cursor: query.len(),
+            query,
+            scroll: 0,
+            recalled: None,
+            draft: vec![],
+        }
+    }
+
+    fn query(&self) -> String {
+        self.query.iter().collect()
+    }
+
+    /// Edit and preview until a query is accepted (with its output) or the editor is cancelled
+    fn run(mut self, args: &Args, input: &[u8], history: &History) -> Result<Option<(String, String)>> {
+        let mut out = stderr();
+        let mut last: Option<(String, Result<String, String>)> = None;
+        loop {
+            let query = self.query();
+            // only evaluate once typing pauses, so pasting or fast typing does not queue up jq runs
+            let stale = last.as_ref().map_or(true, |(q, _)| *q != query);
+            if stale && !event::poll(Duration::ZERO)? {
+                debug!("previewing {query}");
+                last = Some((query.clone(), args.preview(input, &query)));
+                self.scroll = 0;
+            }
+            let stale = last.as_ref().map_or(true, |(q, _)| *q != query);
+            let result = last.as_ref().map(|(_, result)| result);
+            self.draw(&mut out, result, stale)?;
+            let (_, height) = terminal::size()?;
+            let Event::Key(key) = event::read()? else {
+                continue; // resizes are handled by redrawing
+            };
+            match self.handle(key, history, usize::from(height.saturating_sub(2))) {
+                Action::Continue => {}
+                Action::Cancel => return Ok(None),
+                Action::Accept => match last {
+                    Some((ref q, Ok(ref output))) if *q == self.query() => {
+                        return Ok(Some((q.clone(), output.clone())));
+                    }
+                    // wait for the preview of the current query (or a fix for its error)
+                    _ => {}
+                },
+            }
+        }
+    }
+
+    /// Apply a key press to the query
+    fn handle(&mut self, key: KeyEvent, history: &History, page: usize) -> Action {
+        if key.kind == KeyEventKind::Release {
+            return Action::Continue;
+        }
+        let ctrl = key.modifiers.contains(KeyModifiers::CONTROL);
+        match key.code {
+            KeyCode::Char('c' | 'd') if ctrl => return Action::Cancel,
+            KeyCode::Esc => return Action::Cancel,
+            KeyCode::Enter => return Action::Accept,
+            KeyCode::Char('a') if ctrl => self.cursor = 0,
+            KeyCode::Char('e') if ctrl => self.cursor = self.query.len(),
+            KeyCode::Char('u') if ctrl => {
+                self.query.drain(..self.cursor);
+                self.cursor = 0;
+            }
+            KeyCode::Char(_) if ctrl => {}
+            KeyCode::Char(c) => {
+                self.query.insert(self.cursor, c);
+                self.cursor += 1;
+            }
+            KeyCode::Backspace if self.cursor > 0 => {
+                self.cursor -= 1;
+                self.query.remove(self.cursor);
+            }
+            KeyCode::Delete if self.cursor < self.query.len() => {
+                self.query.remove(self.cursor);
+            }
+            KeyCode::Left => self.cursor = self.cursor.saturating_sub(1),
+            KeyCode::Right => self.cursor = (self.cursor + 1).min(self.query.len()),
+            KeyCode::Home => self.cursor = 0,
+            KeyCode::End => self.cursor = self.query.len(),
+            KeyCode::Up => {
+                let current = self.recalled.unwrap_or(history.entries.len());
+                if let Some(i) = current.checked_sub(1) {
+                    if self.recalled.is_none() {
+                        self.draft = std::mem::take(&mut self.query);
+                    }
+                    self.recall(Some(i), history.entries[i].chars().collect());
+                }
+            }
+            KeyCode::Down => match self.recalled {
+                Some(i) if i + 1 < history.entries.len() => {
+                    self.recall(Some(i + 1), history.entries[i + 1].chars().collect())
+                }
+                Some(_) => {
+                    let draft = std::mem::take(&mut self.draft);
+                    self.recall(None, draft)
+                }
+                None => {}
+            },
+            KeyCode::PageUp => self.scroll = self.scroll.saturating_sub(page),
+            KeyCode::PageDown => self.scroll += page,
+            _ => {}
+        }
+        Action::Continue
+    }
+
+    fn recall(&mut self, index: Option<usize>, query: Vec<char>) {
+        self.recalled = index;
+        self.query = query;
+        self.cursor = self.query.len();
+    }
+
+    /// Redraw the query line, a status line, and as much of the result as fits below them
+    fn draw(
+        &mut self,
+        out: &mut impl Write,
+        result: Option<&Result<String, String>>,
+        stale: bool,
+    ) -> Result<()> {
+        let (width, height) = terminal::size()?;
+        let (width, rows) = (usize::from(width).max(3), usize::from(height).saturating_sub(2));
+        // scroll the query horizontally to keep the cursor in view
+        let offset = self.cursor.saturating_sub(width - 3);
+        let line: String = self.query.iter().skip(offset).take(width - 2).collect();
+        queue!(out, cursor::MoveTo(0, 0), style::Print("> "), style::Print(line))?;
+        queue!(out, terminal::Clear(ClearType::UntilNewLine))?;
+
+        let (text, status) = match result {
+            None => ("", "evaluating".to_string()),
+            Some(Ok(output)) => (output.as_str(), format!("{} lines", output.lines().count())),
+            Some(Err(e)) => (e.as_str(), "error".to_string()),
+        };
+        let status = if stale {
+            format!("{status} (outdated)")
+        } else {
+            status
+        };
+        let help = format!("-- {status} | enter: print  esc: quit  up/down: history  pgup/pgdn: scroll ");
+        let help: String = help.chars().chain(std::iter::repeat('-')).take(width).collect();
+        queue!(
+            out,
+            cursor::MoveTo(0, 1),
+            style::PrintStyledContent(style::Stylize::dim(help))
+        )?;
+
+        let lines: Vec<&str> = text.lines().collect();
+        self.scroll = self.scroll.min(lines.len().saturating_sub(rows));
+        for (row, line) in lines.iter().skip(self.scroll).take(rows).enumerate() {
+            let line: String = line.replace('\t', "    ").chars().take(width).collect();
+            queue!(out, cursor::MoveTo(0, row as u16 + 2))?;
+            match result {
+                Some(Err(_)) => queue!(out, style::PrintStyledContent(style::Stylize::red(line)))?,
+                _ => queue!(out, style::Print(line))?,
+            }
+            queue!(out, terminal::Clear(ClearType::UntilNewLine))?;
+        }
+        let drawn = lines.len().saturating_sub(self.scroll).min(rows);
+        queue!(
+            out,
+            cursor::MoveTo(0, drawn as u16 + 2),
+            terminal::Clear(ClearType::FromCursorDown)
+        )?;
+        queue!(out, cursor::MoveTo((2 + self.cursor - offset) as u16, 0))?;
+        Ok(out.flush()?)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn press(editor: &mut Editor, history: &History, code: KeyCode) -> Action {
+        editor.handle(KeyEvent::new(code, KeyModifiers::NONE), history, 10)
+    }
+
+    #[test]
+    fn editing_and_history() {
+        let history = History {
+            path: None,
+            entries: vec![".a".into(), ".b".into()],
+        };
+        let mut editor = Editor::new(".");
+        for c in "kind".chars() {
+            press(&mut editor, &history, KeyCode::Char(c));
+        }
+        press(&mut editor, &history, KeyCode::Home);
+        press(&mut editor, &history, KeyCode::Delete);
+        assert_eq!(editor.query(), "kind");
+        press(&mut editor, &history, KeyCode::Up);
+        assert_eq!(editor.query(), ".b");
+        press(&mut editor, &history, KeyCode::Up);
+        press(&mut editor, &history, KeyCode::Up);
+        assert_eq!(editor.query(), ".a");
+        press(&mut editor, &history, KeyCode::Down);
+        press(&mut editor, &history, KeyCode::Down);
+        assert_eq!(editor.query(), "kind");
+        press(&mut editor, &history, KeyCode::Backspace);
+        assert_eq!(editor.query(), "kin");
+        assert_eq!(press(&mut editor, &history, KeyCode::Enter), Action::Accept);
+        assert_eq!(press(&mut editor, &history, KeyCode::Esc), Action::Cancel);
+    }
+
+    #[test]
+    fn previews() -> Result<()> {
+        let args = Args {
+            output: crate::Output::Yaml,
+            ..Default::default()
+        };
+        let input = serde_json::to_vec(&serde_json::json!({"a": [1, 2]}))?;
+        assert_eq!(args.preview(&input, ".a"), Ok("- 1\n- 2".into()));
+        assert!(args.preview(&input, ".a.b").unwrap_err().contains("Cannot index"));
+        Ok(())
+    }
+}
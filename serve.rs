@@ -0,0 +1,167 @@
+//! A small http endpoint evaluating queries against posted documents (`lq serve`)
+use crate::{Args, Input, Output};
+use anyhow::Result;
+use clap::ValueEnum;
+use std::io::Read;
+use std::time::Duration;
+use tracing::*;
+
+/// Time a query may take before jq is killed and the request fails
+const EVAL_TIMEOUT: Duration = Duration::from_secs(30);
+/// Largest request body (document) accepted, and largest result returned
+const BODY_LIMIT: u64 = 64 << 20;
+/// Requests answered at once (each runs a jq), with the rest waiting for a free worker
+const WORKERS: usize = 8;
+
+impl Args {
+    /// Serve queries over http until killed
+    pub(crate) fn run_serve(&self, listen: &str) -> Result<()> {
+        // :8080 only listens locally, other interfaces have to be asked for by address
+        let addr = match listen.strip_prefix(':') {
+            Some(port) => format!("127.0.0.1:{port}"),
+            None => listen.to_string(),
+        };
+        let server =
+            tiny_http::Server::http(&addr).map_err(|e| anyhow::anyhow!("failed to listen on {addr}: {e}"))?;
+        info!("listening on {addr}");
+        std::thread::scope(|scope| {
+            for _ in 0..WORKERS {
+                scope.spawn(|| {
+                    while let Ok(request) = server.recv() {
+                        self.respond(request);
+                    }
+                });
+            }
+        });
+        Ok(())
+    }
+
+    /// Answer a single request, logging failures to respond
+    fn respond(&self, mut request: tiny_http::Request) {
+        let (method, url) = (request.method().clone(), request.url().to_string());
+        let (path, params) = url.split_once('?').unwrap_or((&url, ""));
+        let (status, content_type, body) = match (&method, path) {
+            (tiny_http::Method::Get, "/healthz") => (200, "text/plain", "ok\n".to_string()),
+            (tiny_http::Method::Post, "/" | "/query") => {
+                let mut document = vec![];
+                // one byte past the limit tells oversized bodies apart from ones exactly at it
+                let read = request
+                    .as_reader()
+                    .take(BODY_LIMIT + 1)
+                    .read_to_end(&mut document);
+                match read {
+                    Ok(n) if n as u64 > BODY_LIMIT => (
+                        413,
+                        "text/plain",
+                        format!("body larger than {BODY_LIMIT} bytes\n"),
+                    ),
+                    Ok(_) => match self.serve_query(params, document) {
+                        Ok((output, content_type)) => (200, content_type, output + "\n"),
+                        Err(e) => (400, "text/plain", format!("{e}\n")),
+                    },
+                    Err(e) => (400, "text/plain", format!("{e}\n")),
+                }
+            }
+            (_, "/" | "/query" | "/healthz") => (405, "text/plain", "method not allowed\n".into()),
+            _ => (404, "text/plain", "not found\n".into()),
+        };
+        debug!("{method} {url} -> {status}");
+        let header = tiny_http::Header::from_bytes("Content-Type", content_type).expect("valid header");
+        let response = tiny_http::Response::from_string(body)
+            .with_status_code(status)
+            .with_header(header);
+        if let Err(e) = request.respond(response) {
+            warn!("failed to respond to {method} {url}: {e}");
+        }
+    }
+
+    /// Evaluate the query in the url parameters against a posted document
+    ///
+    /// Parameters are query (default .), input, output, and the compact and raw flags.
+    /// jq runs without our environment, so queries cannot read secrets from it with `env` or `$ENV`.
+    fn serve_query(&self, params: &str, document: Vec<u8>) -> Result<(String, &'static str)> {
+        let mut args = self.clone();
+        args.jq_query = Some(".".into());
+        for (key, value) in params
+            .split('&')
+            .filter(|p| !p.is_empty())
+            .map(|p| p.split_once('=').unwrap_or((p, "")))
+        {
+            let value = percent_decode(value)?;
+            let flag = || matches!(value.as_str(), "" | "true" | "1");
+            match key {
+                "query" | "q" => args.jq_query = Some(value),
+                "input" => args.input = Input::from_str(&value, true).map_err(anyhow::Error::msg)?,
+                "output" => args.output = Output::from_str(&value, true).map_err(anyhow::Error::msg)?,
+                "compact" => args.compact_output = flag(),
+                "raw" => args.raw_output = flag(),
+                _ => anyhow::bail!("unknown parameter {key}"),
+            }
+        }
+        args.source = Some(document);
+        let input = args.read_input()?;
+        let jq_args = args.jq_args(if args.per_document() { None } else { Some(0) });
+        let stdout = args.shellout_bounded(&input, &jq_args, EVAL_TIMEOUT, BODY_LIMIT, true)?;
+        let stdout = args.postprocess(stdout)?;
+        let content_type = match args.output {
+            _ if args.raw_output => "text/plain",
+            Output::Jq | Output::Json => "application/json",
+            Output::Yaml => "application/yaml",
+            Output::Toml => "application/toml",
+            Output::Html => "text/html",
+            Output::Csv => "text/csv",
+            Output::Xml => "application/xml",
+            Output::Kdl => "text/plain",
+        };
+        Ok((args.output(stdout)?, content_type))
+    }
+}
+
+/// Decode a percent encoded url parameter (with + for spaces)
+fn percent_decode(value: &str) -> Result<String> {
+    let mut bytes = vec![];
+    let mut iter = value.bytes();
+    while let Some(b) = iter.next() {
+        match b {
+            b'+' => bytes.push(b' '),
+            b'%' => {
+                let hex = [iter.next().unwrap_or(0), iter.next().unwrap_or(0)];
+                let hex = std::str::from_utf8(&hex)
+                    .ok()
+                    .and_then(|h| u8::from_str_radix(h, 16).ok());
+                bytes.push(hex.ok_or_else(|| anyhow::anyhow!("invalid percent encoding in {value:?}"))?);
+            }
+            b => bytes.push(b),
+        }
+    }
+    Ok(String::from_utf8(bytes)?)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn queries() -> Result<()> {
+        let args = Args::default();
+        let (out, content_type) = args.serve_query("q=.a%5B1%5D&output=yaml", b"a: [1, {b: x}]".to_vec())?;
+        assert_eq!((out.as_str(), content_type), ("b: x", "application/yaml"));
+        let (out, _) = args.serve_query("input=json&query=.a+%2B+1&compact", b"{\"a\": 1}".to_vec())?;
+        assert_eq!(out, "2");
+        assert!(args.serve_query("query=.a.b", b"a: 1".to_vec()).is_err());
+        assert!(args.serve_query("nope=1", b"a: 1".to_vec()).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn hides_environment() -> Result<()> {
+        std::env::set_var("LQ_SERVE_TEST_SECRET", "hunter2");
+        let args = Args::default();
+        let env = args.serve_query("q=env&compact", b"a: 1".to_vec());
+        let dollar_env = args.serve_query("q=%24ENV&compact", b"a: 1".to_vec());
+        std::env::remove_var("LQ_SERVE_TEST_SECRET");
+        assert_eq!(env?.0, "{}");
+        assert_eq!(dollar_env?.0, "{}");
+        Ok(())
+    }
+}
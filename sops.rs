@@ -0,0 +1,93 @@
+//! Decrypting and re-encrypting sops files (the sops feature, runs sops)
+use crate::{Args, Input};
+use anyhow::Result;
+use serde_yaml::Deserializer;
+use std::io::Write;
+use tracing::*;
+
+/// Run sops with the given stdin, collecting its stdout
+fn run_sops(args: &[&str], input: &[u8], envs: &[(&str, &str)]) -> Result<std::process::Output> {
+    use std::process::Stdio;
+    debug!("sops args: {args:?}");
+    let mut child = std::process::Command::new("sops")
+        .args(args)
+        .envs(envs.iter().copied())
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::inherit())
+        .spawn()
+        .map_err(|e| match e.kind() {
+            std::io::ErrorKind::NotFound => anyhow::anyhow!("sops not found on PATH; see https://getsops.io"),
+            _ => anyhow::Error::new(e).context("failed to run sops"),
+        })?;
+    let mut stdin = child.stdin.take().unwrap();
+    let output = std::thread::scope(|scope| {
+        scope.spawn(move || {
+            let _ = stdin.write_all(input);
+        });
+        child.wait_with_output()
+    })?;
+    Ok(output)
+}
+
+/// Re-encrypt new contents into a sops encrypted file, keeping its keys and creation rules
+///
+/// Runs sops in edit mode with an editor that replaces the decrypted file with the contents,
+/// so that plaintext is only ever written to the temporary file sops manages itself.
+pub(crate) fn sops_rewrite(path: &std::path::Path, contents: &[u8]) -> Result<()> {
+    let path = path
+        .to_str()
+        .ok_or_else(|| anyhow::anyhow!("non-utf8 path {}", path.display()))?;
+    let editor = r#"sh -c 'cat > "$0"'"#;
+    let output = run_sops(&[path], contents, &[("SOPS_EDITOR", editor), ("EDITOR", editor)])?;
+    // sops exits 200 when the file is unchanged
+    if !output.status.success() && output.status.code() != Some(200) {
+        anyhow::bail!("failed to re-encrypt {path} with sops: {}", output.status);
+    }
+    Ok(())
+}
+
+impl Args {
+    /// Replace sops encrypted input with its decrypted form when --sops is set
+    pub(crate) fn decrypt_sops(&mut self) -> Result<()> {
+        if !self.sops {
+            return Ok(());
+        }
+        let data = self.input_bytes()?.to_vec();
+        // sops stores its metadata (keys and mac) under a top level sops key
+        let metadata = Deserializer::from_slice(&data)
+            .next()
+            .and_then(|doc| <serde_yaml::Value as serde::Deserialize>::deserialize(doc).ok())
+            .is_some_and(|doc| doc.get("sops").and_then(|sops| sops.get("mac")).is_some());
+        if !metadata {
+            debug!("no sops metadata in input");
+            self.source = Some(data);
+            return Ok(());
+        }
+        let format = match self.input {
+            Input::Yaml => "yaml",
+            Input::Json => "json",
+            Input::Toml => anyhow::bail!("sops does not support toml files"),
+            Input::Xlsx => anyhow::bail!("sops does not support spreadsheets"),
+            Input::Csv => anyhow::bail!("sops does not support csv files"),
+            Input::Xml => anyhow::bail!("sops does not support xml files"),
+            Input::Edn => anyhow::bail!("sops does not support edn files"),
+            Input::Kdl => anyhow::bail!("sops does not support kdl files"),
+        };
+        let args = [
+            "--decrypt",
+            "--input-type",
+            format,
+            "--output-type",
+            format,
+            "/dev/stdin",
+        ];
+        let output = run_sops(&args, &data, &[])?;
+        if !output.status.success() {
+            anyhow::bail!("failed to decrypt input with sops: {}", output.status);
+        }
+        self.source = Some(output.stdout);
+        self.sops_encrypted = true;
+        Ok(())
+    }
+}
@@ -53,7 +53,7 @@
     skip # ci is fun
   fi
   run lq
-  [ "$status" -eq 1 ]
+  [ "$status" -eq 2 ]
 }
 
 @test "toml" {
@@ -92,6 +92,27 @@
 @test "jq_modules" {
   run lq 'include "k"; . | gvk' -r -L$PWD/test/modules < test/grafana.yaml
   echo "$output" && echo "$output" | grep 'apps/v1.Deployment'
+  run lq 'include "k"; . | gvk' -r -L/nonexistent -L$PWD/test/modules < test/grafana.yaml
+  echo "$output" && echo "$output" | grep 'apps/v1.Deployment'
+}
+
+@test "query_files" {
+  mkdir -p test/split
+  echo 'def gvk: "\(.apiVersion).\(.kind)";' > test/split/defs.jq
+  echo '.[] | gvk' > test/split/gvks.jq
+  run lq -r -f test/split/defs.jq -f test/split/gvks.jq test/deploy.yaml
+  echo "$output" && [ "${lines[0]}" = "v1.ServiceAccount" ] && [ "${lines[4]}" = "apps/v1.Deployment" ]
+  run lq -f test/split/gvks.jq . test/deploy.yaml
+  echo "$output" && [ "$status" -eq 100 ]
+}
+
+@test "named_queries" {
+  run lq -c --query name='.metadata.name' --query 'kind=.kind' test/grafana.yaml
+  echo "$output" && [ "$output" = "$(lq -c '{name: .metadata.name, kind: .kind}' test/grafana.yaml)" ]
+  run lq -y --query 'kinds=[.[].kind]' --query 'count=length' < test/deploy.yaml
+  echo "$output" && [ "${lines[0]}" = "kinds:" ] && [ "${lines[-1]}" = "count: 5" ]
+  run lq --query 'x=.' -f test/modules/k.jq test/deploy.yaml
+  echo "$output" && [ "$status" -eq 2 ]
 }
 
 @test "paramless" {
@@ -117,6 +138,33 @@
   echo "$output" && echo "$output" | grep "Service"
 }
 
+@test "split-k8s" {
+  rm -f test/split/*
+  mkdir -p test/split
+  run lq -y --split-k8s=test/split/ . test/deploy.yaml
+  [ "$status" -eq 0 ]
+  run lq -r '.kind' test/split/default/service_controller.yaml
+  echo "$output" && [ "$output" = "Service" ]
+  run lq -r '.kind' test/split/clusterrole_controller.yaml
+  echo "$output" && [ "$output" = "ClusterRole" ]
+  rm -rf test/split/default # other tests expect only files here
+  run lq --split-k8s=test/split --split '"x"' test/deploy.yaml
+  [ "$status" -eq 2 ]
+}
+
+@test "split-absolute-dir" {
+  dir="$(mktemp -d)"
+  run lq -y --split-k8s="$dir" . test/deploy.yaml
+  [ "$status" -eq 0 ]
+  run lq -r '.kind' "$dir/clusterrole_controller.yaml"
+  echo "$output" && [ "$output" = "ClusterRole" ]
+  run lq '.' --split "\"$dir/\" + .metadata.name + \".yaml\"" -y test/grafana.yaml
+  [ "$status" -eq 0 ]
+  run lq -r '.kind' "$dir/promstack-grafana.yaml"
+  echo "$output" && [ "$output" = "Deployment" ]
+  rm -rf "$dir"
+}
+
 @test "split-yaml-single-to-json" {
   rm -f test/split/*
   mkdir -p test/split
@@ -138,3 +186,867 @@
   run jq '.foo' test/split/bar.json -r
   echo "$output" && echo "$output" | grep "bar"
 }
+
+@test "kubernetes-validate" {
+  run lq --kubernetes-validate --kubernetes-schemas test/kubernetes-schemas -r '.kind' test/grafana.yaml
+  [ "$status" -eq 0 ]
+  echo "$output" && echo "$output" | grep "Deployment"
+
+  run bash -c "lq -y '.spec.replicas = \"two\"' test/grafana.yaml | lq --kubernetes-validate --kubernetes-schemas test/kubernetes-schemas ."
+  [ "$status" -eq 100 ]
+  echo "$output" && echo "$output" | grep ".spec.replicas: expected integer or null, found string"
+
+  run bash -c "(cat test/grafana.yaml; printf -- '---\napiVersion: example.com/v1\nkind: Widget\nspec: {size: 2}\n---\n') | lq --kubernetes-validate --kubernetes-schemas test/kubernetes-schemas --crd test/crd.yaml -c '[.[].kind]'"
+  [ "$status" -eq 0 ]
+  echo "$output" && echo "$output" | grep '\["Deployment","Widget",null\]'
+}
+
+@test "crd" {
+  run lq --crd test/crd.yaml -c '.spec' <<< "$(printf 'apiVersion: example.com/v1\nkind: Widget\nspec: {size: 2, color: null}\n')"
+  echo "$output" && [ "$output" = '{"size":2,"color":null}' ]
+  run lq --crd test/crd.yaml -c '.[].kind' <<< "$(printf 'apiVersion: example.com/v1\nkind: Widget\nspec: {size: 0, color: green}\n---\nkind: Other\n')"
+  [ "$status" -eq 100 ]
+  echo "$output" && echo "$output" | grep ".spec.size: 0 is less than minimum 1"
+  echo "$output" && echo "$output" | grep "1 of 2 documents failed crd validation"
+  run lq --crd test/deploy.yaml . test/grafana.yaml
+  echo "$output" && [ "$status" -eq 100 ] && echo "$output" | grep "no CustomResourceDefinition schemas"
+}
+
+@test "strict" {
+  run lq --input=json -c . <<< '{"a":1,"a":2}'
+  echo "$output" && echo "$output" | grep '{"a":2}'
+  run lq --input=json --strict -c . <<< '{"a":1,"a":2}'
+  [ "$status" -eq 100 ]
+  echo "$output" && echo "$output" | grep 'duplicate key "a"'
+
+  run lq --strict -c . <<< '1: x'
+  [ "$status" -eq 100 ]
+  echo "$output" && echo "$output" | grep 'non-string key 1'
+}
+
+@test "quiet" {
+  run lq -q '.spec.replicas >= 1' test/grafana.yaml
+  [ "$status" -eq 0 ]
+  [ -z "$output" ]
+  run lq -q '.spec.replicas > 3' test/grafana.yaml
+  [ "$status" -eq 1 ]
+  run lq --quiet '.[] | select(.kind == "Secret")' test/deploy.yaml
+  [ "$status" -eq 1 ]
+}
+
+@test "count" {
+  run lq --count '.[] | select(.kind == "Deployment")' test/deploy.yaml
+  echo "$output" && [ "$output" = "1" ]
+  run lq --count '.[].metadata.name' test/deploy.yaml
+  echo "$output" && [ "$output" = "5" ]
+}
+
+@test "paths" {
+  run lq --paths '.[1]' test/deploy.yaml
+  echo "$output" && echo "$output" | grep -F '.rules[0].verbs[2]'
+  run lq --paths=values '.metadata.labels' test/grafana.yaml
+  echo "$output" && echo "$output" | grep -F '.["helm.sh/chart"] = "grafana-6.59.0"'
+  run lq --paths=types '.spec.replicas' test/grafana.yaml
+  echo "$output" && echo "$output" | grep -F '.: integer'
+}
+
+@test "equal" {
+  lq -c '.' test/deploy.yaml > test/output.json
+  run lq equal test/deploy.yaml test/output.json
+  [ "$status" -eq 0 ]
+  lq -y '.spec.replicas = 2' test/grafana.yaml > test/output.yaml
+  run lq equal test/grafana.yaml test/output.yaml
+  [ "$status" -eq 1 ]
+  echo "$output" && echo "$output" | grep -F '.spec.replicas: 1 != 2'
+  run lq equal test/grafana.yaml test/output.yaml --ignore-paths .spec.replicas
+  [ "$status" -eq 0 ]
+  rm test/output.json test/output.yaml
+}
+
+@test "hash" {
+  lq -c '.' test/deploy.yaml > test/output.json
+  yaml="$(lq hash test/deploy.yaml | cut -d' ' -f1)"
+  json="$(lq hash test/output.json | cut -d' ' -f1)"
+  echo "$yaml $json" && [ "$yaml" = "$json" ]
+  other="$(lq hash test/grafana.yaml | cut -d' ' -f1)"
+  [ "$yaml" != "$other" ]
+  rm test/output.json
+}
+
+@test "normalize" {
+  run lq normalize --check test/grafana.yaml
+  [ "$status" -eq 0 ]
+  run lq normalize --check test/deploy.yaml test/grafana.yaml
+  [ "$status" -eq 1 ]
+  echo "$output" && [ "$output" = "test/deploy.yaml" ]
+
+  cp test/deploy.yaml test/output.yaml
+  lq normalize -i test/output.yaml
+  run lq normalize --check test/output.yaml
+  [ "$status" -eq 0 ]
+  run lq equal test/deploy.yaml test/output.yaml
+  [ "$status" -eq 0 ]
+  rm test/output.yaml
+}
+
+@test "doc_selection" {
+  run lq --doc 2 -r '.kind' test/deploy.yaml
+  echo "$output" && [ "$output" = "ClusterRoleBinding" ]
+  run lq --doc 0,3- -c '[.[].kind]' test/deploy.yaml
+  echo "$output" && [ "$output" = '["ServiceAccount","Service","Deployment"]' ]
+  run lq --doc 1 -r '.foo' --input=json test/multi.json
+  echo "$output" && [ "$output" = "baz" ]
+  run lq --doc 2-0 . test/deploy.yaml
+  [ "$status" -eq 2 ]
+  run lq --doc 9 . test/deploy.yaml
+  echo "$output" && [ "$status" -eq 100 ] && echo "$output" | grep "selects none of the 5 input documents"
+}
+
+@test "select_passthrough" {
+  run lq --select '.kind == "Deployment"' '.metadata.name = "renamed"' -c test/deploy.yaml
+  echo "$output" && [ "$(echo "$output" | wc -l)" -eq 5 ]
+  echo "$output" | grep '"kind":"ServiceAccount","metadata":{"name":"controller"'
+  run lq --select 'select(.kind == "Deployment")' '.metadata.name = "renamed" | .metadata.name' -r test/grafana.yaml
+  echo "$output" && [ "$output" = "renamed" ]
+}
+
+@test "multidoc-output" {
+  run lq -y --multidoc-output '.[]' test/deploy.yaml
+  echo "$output" && [ "$(echo "$output" | grep -c -- '^---$')" -eq 4 ]
+  echo "$output" | lq -r '.[3].kind' | grep "Service"
+}
+
+@test "each" {
+  run lq --each '.kind' -r test/deploy.yaml
+  echo "$output" && [ "$(echo "$output" | head -n1)" = "ServiceAccount" ]
+  run lq --each 'length' --input=json test/multi.json
+  echo "$output" && [ "$output" = "$(printf '1\n1')" ]
+}
+
+@test "each_streaming" {
+  run lq --each -c '[$__doc_index, .a]' <<< "$(for i in $(seq 1 1000); do printf 'a: %s\n---\n' "$i"; done)"
+  echo "$output" | tail -n2 && [ "$(echo "$output" | tail -n2 | head -n1)" = "[999,1000]" ]
+  run lq --each -y --doc 1- '.metadata.name' test/list.yaml --unwrap-list
+  echo "$output" && [ "$output" = "app" ]
+}
+
+@test "jq_output_streaming" {
+  run bash -c "lq -c '.[]' test/deploy.yaml | head -n1"
+  echo "$output" && [ "$status" -eq 0 ] && [ "$(echo "$output" | wc -l)" -eq 1 ]
+  run lq -j '.kind' test/grafana.yaml
+  echo "$output" && [ "$output" = "Deployment" ]
+}
+
+@test "closed_stdout" {
+  run bash -o pipefail -c "lq completions bash | head -n1"
+  echo "$output" && [ "$status" -eq 0 ]
+  run bash -o pipefail -c "lq -y '[range(100000)]' -n | head -n1"
+  echo "$output" && [ "$status" -eq 0 ] && [ "$output" = "- 0" ]
+  run bash -o pipefail -c "lq k8s images test/deploy.yaml | head -n0"
+  [ "$status" -eq 0 ]
+  # other write errors still fail
+  run bash -c "lq . test/grafana.yaml > /dev/full"
+  [ "$status" -eq 100 ]
+  run bash -c "lq --paths . test/grafana.yaml > /dev/full"
+  [ "$status" -eq 100 ]
+}
+
+@test "windows_files" {
+  run lq -c '.a' <<< "$(printf '\xef\xbb\xbf{"a": 1}')"
+  echo "$output" && [ "$output" = '1' ]
+  run lq -c '.a' --input=toml <<< "$(printf '\xef\xbb\xbfa = 1')"
+  echo "$output" && [ "$output" = '1' ]
+  printf 'a: 1\r\nb: 2\r\n' > test/crlf.yaml
+  run lq -i -y '.a = 3' test/crlf.yaml
+  run od -c test/crlf.yaml
+  rm test/crlf.yaml
+  echo "$output" && [[ "$output" == *'3  \r  \n   b'* ]]
+}
+
+@test "stdin_flag" {
+  run lq --stdin -c '.a' <<< 'a: 1'
+  echo "$output" && [ "$output" = '1' ]
+  run lq --stdin '.a' test/grafana.yaml
+  [ "$status" -eq 2 ]
+}
+
+@test "empty_input" {
+  run lq -c '.' <<< ''
+  echo "$output" && [ "$output" = '{}' ]
+  run lq --empty-input=null -c '.' <<< ''
+  echo "$output" && [ "$output" = 'null' ]
+  run lq --empty-input=error -c '.' <<< ''
+  echo "$output" && [ "$status" -eq 100 ] && [[ "$output" == *"no documents"* ]]
+  run lq --empty-input=error --each -c '.' <<< ''
+  [ "$status" -eq 100 ]
+}
+
+@test "parse_errors" {
+  run lq '.' <<< "$(printf 'a: 1\nb: c: d\n')"
+  echo "$output" && [ "$status" -eq 100 ] && [[ "$output" == *"--> stdin:2:5"* ]]
+  run lq --input=json '.' <<< "$(printf '{\n  "a": tru\n}')"
+  echo "$output" && [ "$status" -eq 100 ] && [[ "$output" == *'2 |   "a": tru'* ]]
+  run lq --input=toml '.' <<< "$(printf 'a = 1\nb = \n')"
+  echo "$output" && [ "$status" -eq 100 ] && [[ "$output" == *"--> stdin:2:"* ]]
+}
+
+@test "invalid_utf8" {
+  run lq -r '.name' <<< "$(printf 'name: caf\xe9\n')"
+  [ "$status" -eq 100 ]
+  run lq --invalid-utf8=latin1 -r '.name' <<< "$(printf 'name: caf\xe9\n')"
+  echo "$output" && [ "$output" = 'café' ]
+  run lq --invalid-utf8=replace -r '.name' <<< "$(printf 'name: caf\xe9\n')"
+  echo "$output" && [ "$output" = 'caf�' ]
+}
+
+@test "exit_codes" {
+  run lq '.[' test/grafana.yaml
+  [ "$status" -eq 3 ]
+  run lq '.kind | error' test/grafana.yaml
+  [ "$status" -eq 5 ]
+  run lq -e '.spec.paused' test/grafana.yaml
+  echo "$output" && [ "$status" -eq 1 ] && [ "$output" = "null" ]
+  run lq -e -y 'empty' test/grafana.yaml
+  [ "$status" -eq 4 ]
+  run lq -e -r '.kind' test/grafana.yaml
+  echo "$output" && [ "$status" -eq 0 ] && [ "$output" = "Deployment" ]
+  run lq --strict -c . <<< '1: x'
+  [ "$status" -eq 100 ]
+}
+
+@test "final_newline" {
+  mkdir -p test/split
+  printf 'a: 1' > test/split/nonl.yaml
+  run lq -i -y '.a = 2' test/split/nonl.yaml
+  run tail -c 1 test/split/nonl.yaml
+  [ "$output" = "2" ]
+  run lq -i -y --final-newline=always '.a = 3' test/split/nonl.yaml
+  run od -c test/split/nonl.yaml
+  echo "$output" && [[ "$output" == *'3  \n'* ]]
+  rm -f test/split/nonl.yaml
+  run lq -y '.kind' -o test/split/new.yaml test/grafana.yaml
+  run od -c test/split/new.yaml
+  rm -f test/split/new.yaml
+  echo "$output" && [[ "$output" == *'t  \n'* ]]
+}
+
+@test "yaml_limits" {
+  run lq --yaml-max-nodes 100 -c '.c | flatten | length' <<< "$(printf 'a: &a [lol, lol, lol]\nb: &b [*a, *a, *a]\nc: [*b, *b, *b]\n')"
+  echo "$output" && [ "$output" = '27' ]
+  run lq --yaml-max-nodes 20 -c '.c' <<< "$(printf 'a: &a [lol, lol, lol]\nb: &b [*a, *a, *a]\nc: [*b, *b, *b]\n')"
+  echo "$output" && [ "$status" -eq 100 ] && [[ "$output" == *"--yaml-max-nodes"* ]]
+  run lq --yaml-max-depth 2 -c '.' <<< 'a: {b: {c: 1}}'
+  echo "$output" && [ "$status" -eq 100 ] && [[ "$output" == *"--yaml-max-depth"* ]]
+}
+
+@test "max_input_size" {
+  run lq --max-input-size 1k '.' test/grafana.yaml
+  echo "$output" && [ "$status" -eq 100 ] && [[ "$output" == *"--max-input-size"* ]]
+  run lq --max-input-size 1k '.' <<< "$(cat test/grafana.yaml)"
+  echo "$output" && [ "$status" -eq 100 ] && [[ "$output" == *"--max-input-size"* ]]
+  run lq --max-input-size 1M -r '.kind' test/grafana.yaml
+  echo "$output" && [ "$output" = "Deployment" ]
+  run lq --max-input-size 1k --large-input=each -r '.kind' test/deploy.yaml
+  echo "$output" && [ "$status" -eq 0 ] && [[ "$output" == *"Deployment"* ]]
+  run lq --max-input-size 1x '.' test/grafana.yaml
+  echo "$output" && [ "$status" -eq 2 ]
+}
+
+@test "rename_keys" {
+  run lq --rename-keys snake -c '.spec.template.spec | keys_unsorted[:2]' test/grafana.yaml
+  echo "$output" && [ "$output" = '["automount_service_account_token","containers"]' ]
+  run lq --rename-keys camel -c '.' <<< '{"max_surge": 1, "pod-ip": {"HTTP_PORT": 80}}'
+  echo "$output" && [ "$output" = '{"maxSurge":1,"podIp":{"httpPort":80}}' ]
+  run lq --rename-keys pascal -c 'keys' <<< 'apiVersion: v1'
+  echo "$output" && [ "$output" = '["ApiVersion"]' ]
+}
+
+@test "pick_omit" {
+  run lq --pick '.metadata.name,.spec.replicas' -c test/grafana.yaml
+  echo "$output" && [ "$output" = '{"metadata":{"name":"promstack-grafana"},"spec":{"replicas":1}}' ]
+  run lq --pick '.spec.template.spec.containers[].name' -c '.spec.template.spec.containers' test/grafana.yaml
+  echo "$output" && [ "$output" = '[{"name":"grafana-sc-dashboard"},{"name":"grafana-sc-datasources"},{"name":"grafana"}]' ]
+  run lq --omit '.spec,.metadata.labels.app*' -c '.' test/grafana.yaml
+  echo "$output" && [ "$output" = '{"apiVersion":"apps/v1","kind":"Deployment","metadata":{"labels":{"helm.sh/chart":"grafana-6.59.0"},"name":"promstack-grafana","namespace":"monitoring"}}' ]
+  run lq --pick '.a[x]' -c '.' <<< '{}'
+  echo "$output" && [ "$status" -eq 2 ]
+}
+
+@test "prune" {
+  run lq --prune -c '.' <<< '{"a": null, "b": {"c": []}, "d": 1}'
+  echo "$output" && [ "$output" = '{"d":1}' ]
+  run lq --prune=nulls -c '.' <<< '{"a": null, "b": {"c": []}, "d": [null, 1]}'
+  echo "$output" && [ "$output" = '{"b":{"c":[]},"d":[1]}' ]
+  run lq --prune=empty -y '.' <<< '{"a": null, "b": {}}'
+  echo "$output" && [ "$output" = 'a: null' ]
+  run lq --prune -r '.' <<< '{}'
+  echo "$output" && [ "$status" -eq 2 ]
+}
+
+@test "coerce_types" {
+  run lq --coerce-types -c '.' <<< '{"port": "8080", "tls": "true", "version": "1.10", "code": "007"}'
+  echo "$output" && [ "$output" = '{"port":8080,"tls":true,"version":"1.10","code":"007"}' ]
+  run lq --coerce-types --coerce-exclude '.[].zip' -c '.' <<< '[{"port": "8080", "zip": "90210"}]'
+  echo "$output" && [ "$output" = '[{"port":8080,"zip":"90210"}]' ]
+  run lq --coerce-exclude '.zip' -c '.' <<< '{}'
+  echo "$output" && [ "$status" -eq 2 ]
+}
+
+@test "sort_arrays_by" {
+  run lq --sort-arrays-by .name -c '.' <<< '{"env": [{"name": "B"}, {"name": "A", "x": [{"name": 2}, {"name": 1}]}], "args": ["b", "a"]}'
+  echo "$output" && [ "$output" = '{"env":[{"name":"A","x":[{"name":1},{"name":2}]},{"name":"B"}],"args":["b","a"]}' ]
+  run lq --sort-arrays-by .name -c '.spec.template.spec.containers | map(.name)' test/grafana.yaml
+  echo "$output" && [ "$output" = '["grafana-sc-dashboard","grafana-sc-datasources","grafana"]' ]
+  run lq --sort-arrays-by .name -r '.spec.template.spec.containers[0].env[0].name' test/grafana.yaml
+  echo "$output" && [ "$output" = "METHOD" ]
+}
+
+@test "defaults" {
+  run lq --defaults <(printf 'replicas: 1\nresources: {limits: {memory: 128Mi}}\nports: [80]\n') -c '.' <<< '{"resources": {"limits": {"cpu": 1}}, "ports": [443]}'
+  echo "$output" && [ "$output" = '{"replicas":1,"resources":{"limits":{"memory":"128Mi","cpu":1}},"ports":[443]}' ]
+  run lq --defaults test/grafana.yaml -r '.metadata.name' <<< 'kind: Deployment'
+  echo "$output" && [ "$output" = "promstack-grafana" ]
+  run lq --defaults test/missing.yaml '.' <<< '{}'
+  echo "$output" && [ "$status" -eq 100 ]
+}
+
+@test "delete_keys" {
+  run lq --delete-keys '(?i)password|token' -c '.' <<< '{"db": {"user": "a", "password": "b"}, "items": [{"apiToken": "c", "n": 1}]}'
+  echo "$output" && [ "$output" = '{"db":{"user":"a"},"items":[{"n":1}]}' ]
+  run lq --delete-keys '^app\.kubernetes\.io/' -c '.metadata.labels' test/grafana.yaml
+  echo "$output" && [ "$output" = '{"helm.sh/chart":"grafana-6.59.0"}' ]
+  run lq --delete-keys '(' '.' <<< '{}'
+  echo "$output" && [ "$status" -eq 2 ]
+}
+
+@test "json_stream" {
+  run lq --input=json -c 'map(.a)' <<< '{"a": 1}{"a": 2}'
+  echo "$output" && [ "$output" = '[1,2]' ]
+  run lq --input=json -c '.a' --each <<< "$(printf '{"a": 1}\n{"a": 2}\n')"
+  echo "$output" && [ "${lines[0]}" = '1' ] && [ "${lines[1]}" = '2' ]
+  run lq --input=json -c '.' <<< '[1, 2] [3]'
+  echo "$output" && [ "$output" = '[[1,2],[3]]' ]
+  run lq --input=json -c '.' --each <<< "$(printf '[1, 2]\n[3]\n')"
+  echo "$output" && [ "${lines[0]}" = '[1,2]' ] && [ "${lines[1]}" = '[3]' ]
+  run lq --input=json -c '.' --each <<< '[1, 2]'
+  echo "$output" && [ "${lines[0]}" = '1' ] && [ "${lines[1]}" = '2' ]
+  run lq --input=json -c '.' <<< '{"a": 1} {"a": '
+  echo "$output" && [ "$status" -eq 100 ]
+}
+
+@test "toml_style" {
+  run lq -t '.' <<< '{"users": [{"name": "a"}], "server": {"port": 80, "tls": {"enabled": true}}, "empty": {}}'
+  echo "$output" && [ "${lines[0]}" = "[[users]]" ] && [ "${lines[1]}" = 'name = "a"' ]
+  [[ "$output" == *"[server.tls]"* ]] && [[ "$output" == *"[empty]"* ]]
+  run lq -t --toml-inline-tables --toml-arrays=inline '.' <<< '{"server": {"port": 80, "tls": {"enabled": true}}, "users": [{"name": "a"}]}'
+  echo "$output" && [ "${lines[0]}" = "server = { port = 80, tls = { enabled = true } }" ] && [ "${lines[1]}" = 'users = [{ name = "a" }]' ]
+  run lq -t --toml-sort-keys '.' <<< '{"b": 1, "a": {"d": 2, "c": 3}}'
+  echo "$output" && [ "${lines[0]}" = "b = 1" ] && [ "${lines[1]}" = "[a]" ] && [ "${lines[2]}" = "c = 3" ]
+}
+
+@test "json_output" {
+  run lq -J --indent 4 '.spec.selector' test/grafana.yaml
+  echo "$output" && [ "${lines[1]}" = '    "matchLabels": {' ]
+  run lq --output=json -c '.[].kind' test/deploy.yaml
+  echo "$output" && [ "${lines[0]}" = '"ServiceAccount"' ] && [ "${#lines[@]}" -eq 5 ]
+  run lq --output=json -r '.a' <<< '{"a": "x y"}'
+  echo "$output" && [ "$status" -eq 100 ]
+  run lq -J -r '.' <<< '{}'
+  [ "$status" -eq 2 ]
+}
+
+@test "flow_yaml" {
+  run lq -y --flow '.[3].metadata' test/deploy.yaml
+  echo "$output" && [ "$output" = '{name: controller, namespace: default, labels: {app: controller}}' ]
+  run lq -y --flow=1 '.[3].metadata' test/deploy.yaml
+  echo "$output" && [ "${lines[0]}" = 'name: controller' ] && [ "${lines[2]}" = 'labels: {app: controller}' ]
+  run lq -y --flow '.[]' <<< '[{"a": "x, y"}, ["1"]]'
+  echo "$output" && [ "$output" = "[{a: \"x, y\"}, ['1']]" ]
+}
+
+@test "csv" {
+  run lq --input=csv --delimiter ';' -c '.' <<< "$(printf 'zip;name;n\n01234;"a;b";1\n12345;c;true\n')"
+  echo "$output" && [ "$output" = '[{"zip":"01234","name":"a;b","n":1},{"zip":12345,"name":"c","n":true}]' ]
+  run lq --input=csv --no-headers --no-type-inference -c '.' <<< "$(printf 'a,1\nb,2\n')"
+  echo "$output" && [ "$output" = '[["a","1"],["b","2"]]' ]
+  run lq --output=csv --quote-char "'" '.[] | {kind, name: (.metadata.name + ", " + .kind)}' test/deploy.yaml
+  echo "$output" && [ "${lines[0]}" = 'kind,name' ] && [ "${lines[1]}" = "ServiceAccount,'controller, ServiceAccount'" ]
+  run lq --output=csv --no-headers --delimiter '\t' '.[] | [.kind, .metadata.namespace]' test/deploy.yaml
+  echo "$output" && [ "${lines[1]}" = "$(printf 'ClusterRole\t')" ]
+  run lq --output=csv '.[].kind' test/deploy.yaml
+  [ "$status" -eq 100 ]
+}
+
+@test "xml" {
+  run lq --input=xml -c '.' <<< '<a x="1"><b>t</b><b>u</b><n:c xmlns:n="urn:n"/></a>'
+  echo "$output" && [ "$output" = '{"a":{"@x":"1","b":["t","u"],"n:c":{"@xmlns:n":"urn:n"}}}' ]
+  run lq --input=xml --xml-strip-namespaces --xml-force-list d -c '.' <<< '<a><n:c xmlns:n="urn:n">x</n:c><d>y</d></a>'
+  echo "$output" && [ "$output" = '{"a":{"c":"x","d":["y"]}}' ]
+  run lq --input=xml --output=xml --xml-attribute-prefix _ '.a._id = "2"' <<< '<a id="1"><b>t</b></a>'
+  echo "$output" && [ "${lines[0]}" = '<a id="2">' ] && [ "${lines[1]}" = '  <b>t</b>' ]
+  run lq --output=xml '.' <<< 'a: 1'
+  echo "$output" && [ "$output" = '<a>1</a>' ]
+  run lq --output=xml '.' <<< '{a: 1, b: 2}'
+  [ "$status" -eq 100 ]
+  run lq --input=xml '.' <<< '<a><b></a>'
+  echo "$output" && [ "$status" -eq 100 ] && [[ "$output" == *"invalid xml"* ]]
+}
+
+@test "edn" {
+  run lq --input=edn -c '.' <<< '{:a #{1} :b [nil true] "c" \x, :d #my/t 2.5M #_ :e}'
+  echo "$output" && [ "$output" = '{"a":[1],"b":[null,true],"c":"x","d":{"my/t":2.5}}' ]
+  run lq --input=edn -c '.' <<< '1 (:x) ; comment'
+  echo "$output" && [ "$output" = '[1,["x"]]' ]
+  run lq --input=edn '.' <<< '{:a [1}'
+  echo "$output" && [ "$status" -eq 100 ] && [[ "$output" == *"invalid edn"* ]]
+}
+
+@test "kdl" {
+  run lq --input=kdl -c '.' <<< 'a 1 "x" k=#true /-k=2 { (t)b; }'
+  echo "$output" && [ "$output" = '[{"name":"a","args":[1,"x"],"props":{"k":true},"children":[{"name":"b","type":"t"}]}]' ]
+  run lq --input=kdl --output=kdl '.[1].args[0] += 1' <<< "$(printf 'a\nb 1 "c d"=null\n')"
+  echo "$output" && [ "${lines[0]}" = 'a' ] && [ "${lines[1]}" = 'b 2 "c d"=null' ]
+  run lq --output=kdl '.' <<< 'name: [1]'
+  [ "$status" -eq 100 ]
+  run lq --input=kdl '.' <<< 'a {'
+  echo "$output" && [ "$status" -eq 100 ] && [[ "$output" == *"invalid kdl"* ]]
+}
+
+@test "no_mmap" {
+  run lq --no-mmap -r '.[4].kind' test/deploy.yaml
+  echo "$output" && [ "$output" = "Deployment" ]
+  run lq -c '.a' <(echo 'a: 1')
+  echo "$output" && [ "$output" = "1" ]
+}
+
+@test "stream" {
+  run lq --stream -c 'select(length == 2)' <<< "$(printf 'a: [1, {b: 2}]\n')"
+  echo "$output" && [ "$output" = "$(printf '[["a",0],1]\n[["a",1,"b"],2]')" ]
+  run lq --stream -n -r 'fromstream(inputs) | .kind' test/deploy.yaml
+  echo "$output" && [ "$(echo "$output" | tail -n1)" = "Deployment" ]
+  run lq --stream --input=json -n -c '[inputs | select(length == 2) | .[1]]' test/guacamole.json
+  echo "$output" && [ "$status" -eq 0 ]
+}
+
+@test "large_buffered_output" {
+  # output larger than the pipe buffers while jq still has input to read
+  run timeout 60 lq --each --count '.' <<< "$(for i in $(seq 1 20000); do printf 'a: %s\nb: [1, 2, 3]\n---\n' "$i"; done)"
+  echo "$output" | tail -n1 && [ "$status" -eq 0 ] && [ "$output" = "20001" ]
+}
+
+@test "key_order" {
+  run lq -c '.' <<< "$(printf 'z: 1\na: {y: 2, b: 3}\n')"
+  echo "$output" && [ "$output" = '{"z":1,"a":{"y":2,"b":3}}' ]
+  run lq -y '.a' <<< "$(printf 'z: 1\na: {y: 2, b: 3}\n')"
+  echo "$output" && [ "$output" = "$(printf 'y: 2\nb: 3')" ]
+}
+
+@test "completions" {
+  run lq completions bash
+  echo "$output" | head -n3 && [ "$status" -eq 0 ] && echo "$output" | grep -F -- "--input"
+  run lq completions fish
+  echo "$output" | grep -F "yaml"
+}
+
+@test "verbosity" {
+  run lq -v '.kind' test/grafana.yaml
+  echo "$output" && echo "$output" | grep "DEBUG" && echo "$output" | grep '"Deployment"'
+  run lq --log-level=warn '.kind' test/grafana.yaml
+  echo "$output" && [ "$output" = '"Deployment"' ]
+}
+
+@test "config" {
+  printf 'output = "yaml"\n[aliases]\nnames = ".[].metadata.name"\n' > "$BATS_TMPDIR/lq-config.toml"
+  export LQ_CONFIG="$BATS_TMPDIR/lq-config.toml"
+  run lq keys <<< 'b: 1'
+  echo "$output" && [ "$output" = "- b" ]
+  run lq @names test/deploy.yaml
+  echo "$output" && [ "$(echo "$output" | head -n1)" = "- controller" ]
+  run lq --output=jq -r @csv <<< '["a", 1]'
+  echo "$output" && [ "$output" = '"a",1' ]
+  run lq --output=jq -c '.kind' test/grafana.yaml
+  echo "$output" && [ "$output" = '"Deployment"' ]
+  printf '[aliases]\ncsv = "keys"\n' > "$BATS_TMPDIR/lq-config.toml"
+  run lq -r @csv <<< '["a", 1]'
+  echo "$output" && [ "$status" -eq 100 ] && echo "$output" | grep "shadows the jq format @csv"
+}
+
+@test "output_file" {
+  rm -f test/split/out.yaml
+  mkdir -p test/split
+  run lq -y '.[4].metadata' test/deploy.yaml -o test/split/out.yaml
+  [ "$status" -eq 0 ] && [ "$output" = "" ]
+  run lq -r '.name' test/split/out.yaml
+  echo "$output" && [ "$output" = "controller" ]
+  [ -z "$(tail -c1 test/split/out.yaml)" ] # newline terminated
+}
+
+@test "no_newline" {
+  [ "$(lq --no-newline -r '.[0].kind' test/deploy.yaml | tail -c1)" = "t" ]
+  [ "$(lq -j '.[0].kind' test/deploy.yaml | tail -c1)" = "t" ]
+  [ "$(lq --no-newline -y '.[0].kind' test/deploy.yaml | tail -c1)" = "t" ]
+  [ "$(lq --no-newline --each -r '.kind' test/deploy.yaml | wc -l)" = "4" ]
+  run lq -r '.[0].kind' test/deploy.yaml
+  [ "$output" = "ServiceAccount" ]
+}
+
+@test "unbuffered" {
+  run lq --unbuffered --each -r '.kind' test/deploy.yaml
+  echo "$output" && [ "${lines[0]}" = "ServiceAccount" ] && [ "${lines[4]}" = "Deployment" ]
+  # the first result is written before the input ends
+  run timeout 2 sh -c "(cat test/deploy.yaml; sleep 5) | lq --unbuffered --each -r '.kind'"
+  echo "$output" && [ "${lines[0]}" = "ServiceAccount" ] && [ "${lines[3]}" = "Service" ]
+}
+
+@test "jq_not_found" {
+  run lq --jq /nonexistent/jq '.kind' test/grafana.yaml
+  echo "$output" && [ "$status" -eq 127 ] && echo "$output" | grep "/nonexistent/jq not found"
+  run env PATH=/nonexistent "$(command -v lq)" '.kind' test/grafana.yaml
+  echo "$output" && [ "$status" -eq 127 ] && echo "$output" | grep "jq not found on PATH"
+}
+
+@test "url_input" {
+  if ! command -v python3 > /dev/null || ! command -v curl > /dev/null; then
+    skip "needs python3 and curl"
+  fi
+  python3 -m http.server 18765 --bind 127.0.0.1 -d test > /dev/null 2>&1 &
+  server=$!
+  sleep 1
+  run lq -r '.[4].kind' http://127.0.0.1:18765/deploy.yaml --header 'X-Test: 1'
+  run2=$(lq . http://127.0.0.1:18765/missing.yaml 2>&1; echo "status=$?")
+  kill $server
+  echo "$output" && [ "$output" = "Deployment" ]
+  echo "$run2" && echo "$run2" | grep -x "status=100"
+}
+
+@test "watch" {
+  mkdir -p test/split
+  echo 'a: 1' > test/split/watched.yaml
+  timeout 3 lq --watch -c '.a' test/split/watched.yaml > test/split/watch.out &
+  sleep 1
+  echo 'a: 2' > test/split/watched.yaml
+  wait
+  run cat test/split/watch.out
+  echo "$output" && [ "${lines[0]}" = "1" ] && [ "${lines[1]}" = "2" ]
+  run lq --watch '.a'
+  [ "$status" -eq 2 ] # requires a file
+}
+
+@test "repl" {
+  run lq repl test/deploy.yaml
+  echo "$output" && [ "$status" -eq 100 ] && echo "$output" | grep "needs a terminal"
+}
+
+@test "sops" {
+  # plain input is passed through
+  run lq --sops -r '.[0].kind' test/deploy.yaml
+  echo "$output" && [ "$output" = "ServiceAccount" ]
+  if command -v sops > /dev/null; then
+    skip "sops is installed"
+  fi
+  run sh -c "printf 'a: ENC[AES256_GCM,data:x]\nsops:\n  mac: ENC[x]\n' | lq --sops .a"
+  echo "$output" && [ "$status" -eq 100 ] && echo "$output" | grep "sops not found"
+}
+
+@test "archive" {
+  mkdir -p test/split
+  tar czf test/split/manifests.tgz test/deploy.yaml test/grafana.yaml
+  run lq -r '.[].kind' test/split/manifests.tgz
+  echo "$output" && [ "${#lines[@]}" -eq 6 ] && [ "${lines[5]}" = "Deployment" ]
+  run lq -r '.metadata.name' test/split/manifests.tgz --archive-glob '*/grafana.*'
+  echo "$output" && [ "$output" = "promstack-grafana" ]
+  run lq --each -r '.kind' test/split/manifests.tgz --archive-glob '*deploy*'
+  echo "$output" && [ "${#lines[@]}" -eq 5 ]
+}
+
+@test "template" {
+  mkdir -p test/split
+  printf '{{ metadata.name }}:{%% for p in spec.ports %%} {{ p.port }}{%% endfor %%}\n' > test/split/svc.j2
+  run lq --template test/split/svc.j2 '.[3]' test/deploy.yaml
+  echo "$output" && [ "$output" = "controller: 80" ]
+  printf '{{ data }}\n' > test/split/kind.j2
+  run lq --template test/split/kind.j2 --each '.kind' test/deploy.yaml
+  echo "$output" && [ "${#lines[@]}" -eq 5 ] && [ "${lines[4]}" = "Deployment" ]
+  printf '{{ missing }}' > test/split/missing.j2
+  run lq --strict --template test/split/missing.j2 '.[0]' test/deploy.yaml
+  [ "$status" -eq 100 ]
+}
+
+@test "expand_env" {
+  run env TAG=1.2 sh -c "printf 'image: app:\${TAG}\nother: \$OTHER\n' | lq --expand-env -c ."
+  echo "$output" && [ "$output" = '{"image":"app:1.2","other":null}' ]
+  run env TAG=1.2 OTHER=x sh -c "printf 'image: app:\${TAG}\nother: \$OTHER\n' | lq --expand-env --env-allowlist TAG -c ."
+  echo "$output" && [ "$output" = '{"image":"app:1.2","other":"$OTHER"}' ]
+  run sh -c "echo 'a: \$UNSET_VAR' | lq --expand-env --strict ."
+  [ "$status" -eq 100 ]
+}
+
+@test "redact" {
+  run lq --redact 'image,*port' -c '.[4].spec.template.spec.containers[0] | [.image, .ports[0].containerPort, .name]' test/deploy.yaml
+  echo "$output" && [ "$output" = '["***","***","controller"]' ]
+  run lq --redact name -r '.metadata.name' test/grafana.yaml
+  echo "$output" && [ "$output" = '***' ]
+  run lq --redact name --each -r '.metadata.name' test/deploy.yaml
+  echo "$output" && [ "${lines[0]}" = '***' ]
+}
+
+@test "serve" {
+  if ! command -v curl > /dev/null; then
+    skip "needs curl"
+  fi
+  lq serve --listen 127.0.0.1:18766 2> /dev/null &
+  server=$!
+  sleep 1
+  kind=$(curl -s --data-binary @test/deploy.yaml '127.0.0.1:18766/?query=.%5B4%5D.kind&raw')
+  status=$(curl -s -o /dev/null -w '%{http_code}' --data-binary @test/deploy.yaml '127.0.0.1:18766/?query=.a.b')
+  kill $server
+  echo "$kind" && [ "$kind" = "Deployment" ]
+  echo "$status" && [ "$status" = "400" ]
+}
+
+@test "codec" {
+  mkdir -p test/split
+  printf 'name=app\nport=80\n' > test/split/app.kv
+  run lq --codec kv=test/kv-codec -c . test/split/app.kv
+  echo "$output" && [ "$output" = '{"name":"app","port":"80"}' ]
+  run lq --codec kv=test/kv-codec --output-codec kv '.[0].metadata' test/deploy.yaml
+  echo "$output" && [ "${lines[0]}" = "name=controller" ] && [ "${lines[1]}" = "namespace=default" ]
+  run lq --codec kv=test/kv-codec --input-codec kv -i --output-codec kv '.port = "81"' test/split/app.kv
+  run cat test/split/app.kv
+  echo "$output" && [ "${lines[1]}" = "port=81" ]
+  run lq --output-codec nope . test/deploy.yaml
+  echo "$output" && [ "$status" -eq 100 ] && echo "$output" | grep "unknown codec nope"
+}
+
+@test "raw_input_slurp" {
+  run lq --raw-input-slurp -c 'split("\n") | map(select(length > 0))' test/list.yaml
+  echo "$output" && [ "$output" = "$(jq -Rsc 'split("\n") | map(select(length > 0))' test/list.yaml)" ]
+  run lq --raw-input-slurp -y 'split("\n") | map(select(length > 0) | split("=") | {(.[0]): .[1]}) | add' <<< $'a=1\nb=2'
+  echo "$output" && [ "${lines[0]}" = "a: '1'" ] && [ "${lines[1]}" = "b: '2'" ]
+  run lq --raw-input-slurp --each . test/list.yaml
+  echo "$output" && [ "$status" -eq 2 ]
+}
+
+@test "ascii_output" {
+  run lq -a -c '.name' <<< 'name: café'
+  echo "$output" && [ "$output" = '"caf\u00e9"' ]
+  run lq -a -y '.' <<< 'name: café'
+  echo "$output" && [ "$output" = 'name: "caf\u00e9"' ]
+  run lq -a -t '.' <<< 'name: café'
+  echo "$output" && [ "$output" = 'name = "caf\u00e9"' ]
+}
+
+@test "color_output" {
+  run lq -C '.name' <<< 'name: x'
+  echo "$output" && [[ "$output" == *$'\e['* ]]
+  run lq '.name' <<< 'name: x'
+  echo "$output" && [ "$output" = '"x"' ]
+  run lq -M -C '.name' <<< 'name: x'
+  echo "$output" && [ "$status" -eq 2 ]
+}
+
+@test "arg_env" {
+  run env API_TOKEN=s3cret lq --arg-env token=API_TOKEN -r '$token' test/deploy.yaml
+  echo "$output" && [ "$output" = "s3cret" ]
+  run env TAG=1.2 lq --arg-env TAG -c '[$TAG]' <<< '{}'
+  echo "$output" && [ "$output" = '["1.2"]' ]
+  run env -u LQ_NOPE lq --arg-env x=LQ_NOPE . test/deploy.yaml
+  echo "$output" && [ "$status" -eq 2 ]
+}
+
+@test "argyaml" {
+  run lq --argyaml extra '{team: infra, tiers: [a, b]}' -c '.metadata.labels += $extra | .metadata.labels' test/grafana.yaml
+  echo "$output" && echo "$output" | grep '"team":"infra","tiers":\["a","b"\]}$'
+  run lq --argyaml n 3 --argyaml s "'3'" -c '[$n, $s]' <<< '{}'
+  echo "$output" && [ "$output" = '[3,"3"]' ]
+  run lq --argyaml bad '[a' . <<< '{}'
+  echo "$output" && [ "$status" -eq 100 ] && echo "$output" | grep "invalid yaml for --argyaml bad"
+}
+
+@test "merge" {
+  mkdir -p test/split
+  printf 'replicas: 1\nimage: {tag: "1"}\nenv: [A]\n' > test/split/base.yaml
+  printf 'replicas: 2\nimage: {tag: "1"}\nenv: [A, B]\n' > test/split/ours.yaml
+  printf 'replicas: 1\nimage: {tag: "2"}\nenv: [C]\n' > test/split/theirs.yaml
+  run lq -c merge --three-way --prefer theirs test/split/base.yaml test/split/ours.yaml test/split/theirs.yaml
+  echo "$output" && [ "$status" -eq 0 ] && [ "${lines[0]}" = '{"replicas":2,"image":{"tag":"2"},"env":["C"]}' ]
+  run lq -c merge --three-way test/split/base.yaml test/split/ours.yaml test/split/theirs.yaml
+  echo "$output" && [ "$status" -eq 1 ] && echo "$output" | grep "conflict at .env"
+  echo "$output" | grep '"env":{"<<<<<<< ours":\["A","B"\],">>>>>>> theirs":\["C"\]}'
+  run lq -c merge test/split/base.yaml test/split/theirs.yaml
+  echo "$output" && [ "$output" = '{"replicas":1,"image":{"tag":"2"},"env":["C"]}' ]
+  run lq merge --three-way test/split/base.yaml test/split/ours.yaml
+  [ "$status" -eq 100 ]
+}
+
+@test "values" {
+  mkdir -p test/split
+  printf 'image: {repo: app, tag: "1"}\nports: [80, 443]\ndebug: true\n' > test/split/base-values.yaml
+  printf '{"image": {"tag": "2"}, "ports": [8080], "debug": null}' > test/split/prod-values.json
+  run lq -c values test/split/base-values.yaml test/split/prod-values.json --set 'replicas=3,hosts={a,b}'
+  echo "$output" && [ "$output" = '{"image":{"repo":"app","tag":"2"},"ports":[8080],"replicas":3,"hosts":["a","b"]}' ]
+  run lq -y values test/split/base-values.yaml --set image.tag=3
+  echo "$output" && [ "${lines[2]}" = "  tag: 3" ]
+}
+
+@test "k8s_images" {
+  run lq k8s images test/deploy.yaml test/grafana.yaml
+  echo "$output" && [ "${lines[0]}" = "ghcr.io/kube-rs/version-rs:1.16.2" ] && [ "${lines[2]}" = "docker.io/grafana/grafana:10.1.0" ]
+  [ "${#lines[@]}" -eq 3 ]
+  mkdir -p test/split
+  cp test/grafana.yaml test/split/grafana.yaml
+  run lq k8s images -i --set quay.io/kiwigrid/k8s-sidecar:1.25.0 test/split/grafana.yaml
+  run lq k8s images test/split/grafana.yaml
+  echo "$output" && [ "${lines[0]}" = "quay.io/kiwigrid/k8s-sidecar:1.25.0" ] && [ "${lines[1]}" = "docker.io/grafana/grafana:10.1.0" ]
+  run lq k8s images --set nginx test/deploy.yaml
+  echo "$output" && [ "$status" -eq 100 ]
+}
+
+@test "html_output" {
+  run lq --output=html --bare-html '.spec.template.spec.containers | map({name, image})' test/grafana.yaml
+  echo "$output" && [ "${lines[0]}" = "<table>" ] && [ "${lines[2]}" = "<tr><th>name</th><th>image</th></tr>" ]
+  [ "${lines[7]}" = "<tr><td>grafana</td><td>docker.io/grafana/grafana:10.1.0</td></tr>" ]
+  run lq --output=html '{a: "<&>"}' <<< '{}'
+  echo "$output" && [ "${lines[0]}" = "<!DOCTYPE html>" ] && [[ "$output" == *"<td>&lt;&amp;&gt;</td>"* ]]
+  run lq --output=html '.[]' <<< '[1, 2]'
+  echo "$output" && [ "$status" -eq 100 ]
+}
+
+@test "find" {
+  run lq find --value-regex 'grafana:10' test/grafana.yaml
+  echo "$output" && [ "$output" = ".spec.template.spec.containers[2].image" ]
+  run lq find --key-regex '^image$' --values test/deploy.yaml test/grafana.yaml
+  echo "$output" && [ "${lines[0]}" = 'test/deploy.yaml:.[4].spec.template.spec.containers[0].image = "ghcr.io/kube-rs/version-rs:1.16.2"' ]
+  [ "${#lines[@]}" -eq 4 ]
+  run lq find --key-regex 'memory' --value-regex '^128' test/deploy.yaml
+  echo "$output" && [ "$output" = ".[4].spec.template.spec.containers[0].resources.limits.memory" ]
+  run lq find --value-regex 'nothing-like-this' <<< 'a: b'
+  echo "$output" && [ "$status" -eq 1 ] && [ -z "$output" ]
+  run lq find test/grafana.yaml
+  [ "$status" -eq 2 ]
+}
+
+@test "table" {
+  run lq table '.spec.template.spec.containers[] | {name, image}' test/grafana.yaml
+  echo "$output" && [ "${lines[0]}" = "NAME                     IMAGE" ] && [ "${lines[3]}" = "grafana                  docker.io/grafana/grafana:10.1.0" ]
+  run lq table --no-headers --columns kind,missing '.' test/deploy.yaml
+  echo "$output" && [ "${lines[0]}" = "ServiceAccount       <none>" ] && [ "${#lines[@]}" -eq 5 ]
+  run lq table '.spec.replicas' test/grafana.yaml
+  echo "$output" && [ "$status" -eq 100 ]
+}
+
+@test "annotate_source" {
+  run lq -y --annotate-source '.metadata.name' test/deploy.yaml
+  echo "$output" && [ "${lines[0]}" = "# source: test/deploy.yaml (doc 0)" ] && [ "${lines[1]}" = "controller" ]
+  [ "${lines[2]}" = "---" ] && [ "${lines[3]}" = "# source: test/deploy.yaml (doc 1)" ]
+  run lq -y --annotate-source --doc 1 --unwrap-list '.metadata.name' test/list.yaml
+  echo "$output" && [ "${lines[0]}" = "# source: test/list.yaml (doc 0)" ] && [ "${lines[1]}" = "app" ]
+  run lq --annotate-source . test/deploy.yaml
+  echo "$output" && [ "$status" -eq 100 ] && echo "$output" | grep "requires yaml output"
+}
+
+@test "version" {
+  run lq --version
+  echo "$output" && echo "$output" | grep -E '^lq [0-9.]+ \(jq-'
+  run lq -V --jq /nonexistent/jq
+  echo "$output" && echo "$output" | grep "not found"
+}
+
+@test "binary_name" {
+  ln -sf "$(which lq)" "$BATS_TMPDIR/tq"
+  ln -sf "$(which lq)" "$BATS_TMPDIR/jsonq"
+  ln -sf "$(which lq)" "$BATS_TMPDIR/xq"
+  run "$BATS_TMPDIR/tq" -r '.package.name' Cargo.toml
+  echo "$output" && [ "$output" = "lq" ]
+  run "$BATS_TMPDIR/jsonq" -c '.[0]' test/multi.json
+  echo "$output" && [ "$output" = '{"foo":"bar"}' ]
+  run "$BATS_TMPDIR/xq" -r '.a.b' <<< '<a><b>c</b></a>'
+  echo "$output" && [ "$output" = "c" ]
+  run "$BATS_TMPDIR/tq" --input=yaml -r '.kind' test/grafana.yaml
+  echo "$output" && [ "$output" = "Deployment" ]
+}
+
+@test "yaml_tags" {
+  run lq -c '.' <<< "$(printf 'a: !Ref b\n1: !!str c\n')"
+  echo "$output" && [ "$output" = '{"a":{"Ref":"b"},"1":"c"}' ]
+}
+
+@test "unique" {
+  run lq --unique -c '.[].metadata.name' test/deploy.yaml
+  echo "$output" && [ "$output" = '"controller"' ]
+  run lq --unique-by '.kind' -c '.[], .[]' test/deploy.yaml
+  echo "$output" && [ "$(echo "$output" | wc -l)" -eq 5 ]
+}
+
+@test "sort-docs-by" {
+  run lq --sort-docs-by '.kind' -c '.[] | {kind}' test/deploy.yaml
+  echo "$output" && [ "$(echo "$output" | head -n1)" = '{"kind":"ClusterRole"}' ]
+  run lq --sort-docs-by k8s-kind-order -c '.[] | {kind}' <<< "$(printf 'kind: Deployment\n---\nkind: Namespace\n---\nkind: Foo\n')"
+  echo "$output" && [ "$output" = "$(printf '{"kind":"Namespace"}\n{"kind":"Deployment"}\n{"kind":"Foo"}')" ]
+  run lq --sort-docs=k8s -c '.[]' <<< "$(printf 'kind: Service\n---\nkind: CustomResourceDefinition\n---\nkind: Namespace\n')"
+  echo "$output" && [ "$output" = "$(printf '{"kind":"Namespace"}\n{"kind":"CustomResourceDefinition"}\n{"kind":"Service"}')" ]
+}
+
+@test "unwrap-list" {
+  run lq --unwrap-list -c '.[] | [.kind, .metadata.name]' test/list.yaml
+  echo "$output" && [ "$output" = "$(printf '["ConfigMap","settings"]\n["Deployment","app"]')" ]
+
+  rm -f test/split/*
+  mkdir -p test/split
+  run lq '.' --unwrap-list --split '"test/split/" + (.metadata.name) + ".yaml"' -y test/list.yaml
+  [ "$status" -eq 0 ]
+  run lq -r '.kind' test/split/settings.yaml
+  echo "$output" && [ "$output" = "ConfigMap" ]
+}
+
+@test "k8s-clean" {
+  run lq --k8s-clean -c '.metadata' <<< "$(printf 'kind: Pod\nmetadata:\n  name: a\n  uid: x\n  managedFields: []\nstatus: {phase: Running}\n')"
+  echo "$output" && [ "$output" = '{"name":"a"}' ]
+  run lq --k8s-clean -c 'has("status")' <<< 'status: {}'
+  echo "$output" && [ "$output" = 'false' ]
+  run lq --k8s-clean -c '.metadata' test/list.yaml
+  echo "$output" && [ "$output" = '{}' ]
+}
+
+@test "set-namespace" {
+  run lq --set-namespace staging -r '.metadata.namespace' test/grafana.yaml
+  echo "$output" && [ "$output" = 'staging' ]
+  run lq --set-namespace staging -c '.metadata' <<< "$(printf 'kind: ClusterRole\nmetadata:\n  name: a\n')"
+  echo "$output" && [ "$output" = '{"name":"a"}' ]
+  run lq --set-namespace staging -c '.metadata' <<< 'kind: ConfigMap'
+  echo "$output" && [ "$output" = '{"namespace":"staging"}' ]
+}
+
+@test "add-label" {
+  run lq --add-label team=infra --add-annotation note=x -c '[.metadata.labels.team, .metadata.annotations, .spec.template.metadata.labels.team]' test/grafana.yaml
+  echo "$output" && [ "$output" = '["infra",{"note":"x"},null]' ]
+  run lq --add-label team=infra --pod-templates -r '.spec.template.metadata.labels.team' test/grafana.yaml
+  echo "$output" && [ "$output" = 'infra' ]
+  run lq --add-label team=infra -c '.items[0].metadata.labels' test/list.yaml
+  echo "$output" && [ "$output" = '{"team":"infra"}' ]
+  run lq --add-label =infra '.' test/grafana.yaml
+  [ "$status" -eq 2 ]
+}
+
+@test "merge-docs" {
+  run lq --merge-docs -c '.' <<< "$(printf 'a: {b: 1, c: [1]}\n---\na: {d: 2, c: [2]}\n')"
+  echo "$output" && [ "$output" = '{"a":{"b":1,"c":[2],"d":2}}' ]
+  run lq --merge-docs=shallow -c '.' <<< "$(printf 'a: {b: 1, c: [1]}\n---\na: {d: 2, c: [2]}\n')"
+  echo "$output" && [ "$output" = '{"a":{"d":2,"c":[2]}}' ]
+}
+
+@test "doc_index_variables" {
+  run lq -c '[$__doc_index, $__doc_count]' test/deploy.yaml
+  echo "$output" && [ "$output" = "[0,5]" ]
+  run lq --each -r 'select($__doc_index == 2) | .kind' test/deploy.yaml
+  echo "$output" && [ "$output" = "ClusterRoleBinding" ]
+  run lq --each 'include "k"; "\($__doc_index + 1)/\($__doc_count) \(gvk)"' -r -L$PWD/test/modules test/deploy.yaml
+  echo "$output" && echo "$output" | grep -F "5/5 apps/v1.Deployment"
+}
+
+@test "json_seq" {
+  run bash -c "printf '\x1e{\"a\":1}\n\x1e{\"a\":2}\n' | lq --input=json --seq -y '.[].a'"
+  echo "$output" && [ "$output" = "$(printf -- '- 1\n- 2')" ]
+  run bash -c "lq --seq -c '.[].kind' test/deploy.yaml | od -c | head -n1"
+  echo "$output" && echo "$output" | grep '036   "   S   e   r   v   i   c   e   A'
+}